@@ -0,0 +1,717 @@
+// Audio processing unit: two square channels (the first with frequency sweep), the wave channel
+// and the noise channel, mixed to stereo through NR50/NR51.
+//
+// The channels are stepped once per M-cycle from GameBoy::tick and the frame sequencer (length,
+// sweep and envelope clocks) is stepped by the falling edge of DIV bit 4, see GameBoy::update_timers.
+// The mixed output is box filtered down to `sample_rate` and collected as interleaved stereo f32
+// samples, which the frontend pulls with `drain_samples`.
+use crate::gb::GameBoy;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 48000;
+const M_CYCLES_PER_SECOND: u32 = 1 << 20;
+
+// NR10-NR52 live at 0xFF10-0xFF26, wave RAM at 0xFF30-0xFF3F.
+const NR10: usize = 0x00;
+const NR11: usize = 0x01;
+const NR12: usize = 0x02;
+const NR13: usize = 0x03;
+const NR14: usize = 0x04;
+const NR21: usize = 0x06;
+const NR22: usize = 0x07;
+const NR23: usize = 0x08;
+const NR24: usize = 0x09;
+const NR30: usize = 0x0A;
+const NR31: usize = 0x0B;
+const NR32: usize = 0x0C;
+const NR33: usize = 0x0D;
+const NR34: usize = 0x0E;
+const NR41: usize = 0x10;
+const NR42: usize = 0x11;
+const NR43: usize = 0x12;
+const NR44: usize = 0x13;
+const NR50: usize = 0x14;
+const NR51: usize = 0x15;
+const NR52: usize = 0x16;
+const NUM_REGISTERS: usize = 0x17;
+
+// Bits that always read back as 1 (write-only or unused bits).
+const READ_MASKS: [u8; NUM_REGISTERS] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+];
+
+const DUTY_CYCLES: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+#[derive(Default, Serialize, Deserialize)]
+struct Envelope {
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn trigger(&mut self, nrx2: u8) {
+        self.volume = nrx2 >> 4;
+        self.timer = nrx2 & 7;
+    }
+
+    fn clock(&mut self, nrx2: u8) {
+        let period = nrx2 & 7;
+        if period == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = period;
+            if nrx2 & 0x08 != 0 && self.volume < 15 {
+                self.volume += 1;
+            } else if nrx2 & 0x08 == 0 && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct SquareChannel {
+    enabled: bool,
+    length: u16,
+    timer: u32,
+    duty_position: u8,
+    envelope: Envelope,
+    // only used by channel 1
+    sweep_enabled: bool,
+    sweep_timer: u8,
+    sweep_shadow: u16,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct WaveChannel {
+    enabled: bool,
+    length: u16,
+    timer: u32,
+    position: u8,
+    sample: u8,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct NoiseChannel {
+    enabled: bool,
+    length: u16,
+    timer: u32,
+    lfsr: u16,
+    envelope: Envelope,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Apu {
+    regs: [u8; NUM_REGISTERS],
+    wave_ram: [u8; 16],
+    powered: bool,
+    frame_sequencer_step: u8,
+    ch1: SquareChannel,
+    ch2: SquareChannel,
+    ch3: WaveChannel,
+    ch4: NoiseChannel,
+    sample_rate: u32,
+    sample_phase: u32,
+    sum_left: f32,
+    sum_right: f32,
+    sum_count: u32,
+    capacitor_left: f32,
+    capacitor_right: f32,
+    // Output only, so it isn't part of a save state.
+    #[serde(skip)]
+    samples: Vec<f32>,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Apu {
+            regs: [0; NUM_REGISTERS],
+            wave_ram: [0; 16],
+            powered: false,
+            frame_sequencer_step: 0,
+            ch1: Default::default(),
+            ch2: Default::default(),
+            ch3: Default::default(),
+            ch4: Default::default(),
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            sample_phase: 0,
+            sum_left: 0.0,
+            sum_right: 0.0,
+            sum_count: 0,
+            capacitor_left: 0.0,
+            capacitor_right: 0.0,
+            samples: vec![],
+        }
+    }
+}
+
+impl Apu {
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.sample_phase = 0;
+    }
+
+    // Interleaved stereo samples (left, right, left, ...) in the range -1.0..=1.0 produced since
+    // the last call.
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0xFF10..=0xFF25 => {
+                let i = address as usize - 0xFF10;
+                self.regs[i] | READ_MASKS[i]
+            }
+            0xFF26 => {
+                let mut nr52 = READ_MASKS[NR52] | ((self.powered as u8) << 7);
+                nr52 |= self.ch1.enabled as u8;
+                nr52 |= (self.ch2.enabled as u8) << 1;
+                nr52 |= (self.ch3.enabled as u8) << 2;
+                nr52 |= (self.ch4.enabled as u8) << 3;
+                nr52
+            }
+            0xFF30..=0xFF3F => self.wave_ram[address as usize - 0xFF30],
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        match address {
+            0xFF30..=0xFF3F => self.wave_ram[address as usize - 0xFF30] = data,
+            0xFF26 => {
+                let power = data & 0x80 != 0;
+                if self.powered && !power {
+                    // powering off clears every register and silences everything
+                    self.regs = [0; NUM_REGISTERS];
+                    self.ch1 = Default::default();
+                    self.ch2 = Default::default();
+                    self.ch3 = Default::default();
+                    self.ch4 = Default::default();
+                } else if !self.powered && power {
+                    self.frame_sequencer_step = 0;
+                }
+                self.powered = power;
+            }
+            0xFF10..=0xFF25 if self.powered => {
+                let i = address as usize - 0xFF10;
+                self.regs[i] = data;
+                self.write_register(i, data);
+            }
+            _ => {}
+        }
+    }
+
+    fn write_register(&mut self, register: usize, data: u8) {
+        match register {
+            NR10 => {}
+            NR11 => self.ch1.length = 64 - (data & 0x3F) as u16,
+            NR12 => {
+                if data & 0xF8 == 0 {
+                    self.ch1.enabled = false;
+                }
+            }
+            NR14 => {
+                if data & 0x80 != 0 {
+                    self.trigger_ch1();
+                }
+            }
+            NR21 => self.ch2.length = 64 - (data & 0x3F) as u16,
+            NR22 => {
+                if data & 0xF8 == 0 {
+                    self.ch2.enabled = false;
+                }
+            }
+            NR24 => {
+                if data & 0x80 != 0 {
+                    self.trigger_ch2();
+                }
+            }
+            NR30 => {
+                if data & 0x80 == 0 {
+                    self.ch3.enabled = false;
+                }
+            }
+            NR31 => self.ch3.length = 256 - data as u16,
+            NR34 => {
+                if data & 0x80 != 0 {
+                    self.trigger_ch3();
+                }
+            }
+            NR41 => self.ch4.length = 64 - (data & 0x3F) as u16,
+            NR42 => {
+                if data & 0xF8 == 0 {
+                    self.ch4.enabled = false;
+                }
+            }
+            NR44 => {
+                if data & 0x80 != 0 {
+                    self.trigger_ch4();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn frequency(&self, low: usize, high: usize) -> u16 {
+        ((self.regs[high] as u16 & 7) << 8) | self.regs[low] as u16
+    }
+
+    fn square_period(frequency: u16) -> u32 {
+        (2048 - frequency as u32) * 4
+    }
+
+    fn trigger_ch1(&mut self) {
+        let frequency = self.frequency(NR13, NR14);
+        let ch1 = &mut self.ch1;
+        ch1.enabled = self.regs[NR12] & 0xF8 != 0;
+        if ch1.length == 0 {
+            ch1.length = 64;
+        }
+        ch1.timer = Self::square_period(frequency);
+        ch1.envelope.trigger(self.regs[NR12]);
+
+        let sweep_period = (self.regs[NR10] >> 4) & 7;
+        let sweep_shift = self.regs[NR10] & 7;
+        ch1.sweep_shadow = frequency;
+        ch1.sweep_timer = if sweep_period == 0 { 8 } else { sweep_period };
+        ch1.sweep_enabled = sweep_period != 0 || sweep_shift != 0;
+        if sweep_shift != 0 {
+            self.sweep_calculation();
+        }
+    }
+
+    fn trigger_ch2(&mut self) {
+        let frequency = self.frequency(NR23, NR24);
+        let ch2 = &mut self.ch2;
+        ch2.enabled = self.regs[NR22] & 0xF8 != 0;
+        if ch2.length == 0 {
+            ch2.length = 64;
+        }
+        ch2.timer = Self::square_period(frequency);
+        ch2.envelope.trigger(self.regs[NR22]);
+    }
+
+    fn trigger_ch3(&mut self) {
+        let frequency = self.frequency(NR33, NR34);
+        let ch3 = &mut self.ch3;
+        ch3.enabled = self.regs[NR30] & 0x80 != 0;
+        if ch3.length == 0 {
+            ch3.length = 256;
+        }
+        ch3.timer = (2048 - frequency as u32) * 2;
+        ch3.position = 0;
+    }
+
+    fn trigger_ch4(&mut self) {
+        let ch4 = &mut self.ch4;
+        ch4.enabled = self.regs[NR42] & 0xF8 != 0;
+        if ch4.length == 0 {
+            ch4.length = 64;
+        }
+        ch4.timer = Self::noise_period(self.regs[NR43]);
+        ch4.lfsr = 0x7FFF;
+        ch4.envelope.trigger(self.regs[NR42]);
+    }
+
+    fn noise_period(nr43: u8) -> u32 {
+        NOISE_DIVISORS[(nr43 & 7) as usize] << (nr43 >> 4)
+    }
+
+    // Computes the next swept frequency, disabling channel 1 if it overflows.
+    fn sweep_calculation(&mut self) -> u16 {
+        let shift = self.regs[NR10] & 7;
+        let delta = self.ch1.sweep_shadow >> shift;
+        let frequency = if self.regs[NR10] & 0x08 != 0 {
+            self.ch1.sweep_shadow.wrapping_sub(delta)
+        } else {
+            self.ch1.sweep_shadow + delta
+        };
+        if frequency > 2047 {
+            self.ch1.enabled = false;
+        }
+        frequency
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.ch1.sweep_timer > 0 {
+            self.ch1.sweep_timer -= 1;
+        }
+        if self.ch1.sweep_timer != 0 {
+            return;
+        }
+        let period = (self.regs[NR10] >> 4) & 7;
+        self.ch1.sweep_timer = if period == 0 { 8 } else { period };
+        if !self.ch1.sweep_enabled || period == 0 {
+            return;
+        }
+        let frequency = self.sweep_calculation();
+        if frequency <= 2047 && self.regs[NR10] & 7 != 0 {
+            self.ch1.sweep_shadow = frequency;
+            self.regs[NR13] = frequency as u8;
+            self.regs[NR14] = (self.regs[NR14] & !7) | ((frequency >> 8) as u8 & 7);
+            // the new frequency is checked for overflow a second time but not used
+            self.sweep_calculation();
+        }
+    }
+
+    fn clock_lengths(&mut self) {
+        fn clock(enabled: &mut bool, length: &mut u16, nrx4: u8) {
+            if nrx4 & 0x40 != 0 && *length > 0 {
+                *length -= 1;
+                if *length == 0 {
+                    *enabled = false;
+                }
+            }
+        }
+        clock(&mut self.ch1.enabled, &mut self.ch1.length, self.regs[NR14]);
+        clock(&mut self.ch2.enabled, &mut self.ch2.length, self.regs[NR24]);
+        clock(&mut self.ch3.enabled, &mut self.ch3.length, self.regs[NR34]);
+        clock(&mut self.ch4.enabled, &mut self.ch4.length, self.regs[NR44]);
+    }
+
+    // Called on every falling edge of DIV bit 4 (512Hz).
+    pub fn clock_frame_sequencer(&mut self) {
+        if !self.powered {
+            return;
+        }
+        match self.frame_sequencer_step {
+            0 | 4 => self.clock_lengths(),
+            2 | 6 => {
+                self.clock_lengths();
+                self.clock_sweep();
+            }
+            7 => {
+                self.ch1.envelope.clock(self.regs[NR12]);
+                self.ch2.envelope.clock(self.regs[NR22]);
+                self.ch4.envelope.clock(self.regs[NR42]);
+            }
+            _ => {}
+        }
+        self.frame_sequencer_step = (self.frame_sequencer_step + 1) % 8;
+    }
+
+    // Advances every channel by one M-cycle (4 T-cycles) and mixes the result.
+    pub fn tick(&mut self) {
+        if self.powered {
+            self.step_channels(4);
+        }
+        let (left, right) = self.mix();
+        self.sum_left += left;
+        self.sum_right += right;
+        self.sum_count += 1;
+
+        self.sample_phase += self.sample_rate;
+        if self.sample_phase >= M_CYCLES_PER_SECOND {
+            self.sample_phase -= M_CYCLES_PER_SECOND;
+            let left = self.high_pass_left(self.sum_left / self.sum_count as f32);
+            let right = self.high_pass_right(self.sum_right / self.sum_count as f32);
+            self.sum_left = 0.0;
+            self.sum_right = 0.0;
+            self.sum_count = 0;
+            // Nobody is pulling samples, don't grow forever. Keep about a second of audio.
+            if self.samples.len() >= 2 * self.sample_rate as usize {
+                self.samples.drain(..self.sample_rate as usize);
+            }
+            self.samples.push(left);
+            self.samples.push(right);
+        }
+    }
+
+    fn step_channels(&mut self, t_cycles: u32) {
+        let ch1_period = Self::square_period(self.frequency(NR13, NR14));
+        step_timer(&mut self.ch1.timer, t_cycles, ch1_period, || {
+            self.ch1.duty_position = (self.ch1.duty_position + 1) % 8;
+        });
+        let ch2_period = Self::square_period(self.frequency(NR23, NR24));
+        step_timer(&mut self.ch2.timer, t_cycles, ch2_period, || {
+            self.ch2.duty_position = (self.ch2.duty_position + 1) % 8;
+        });
+
+        let ch3_period = (2048 - self.frequency(NR33, NR34) as u32) * 2;
+        let wave_ram = &self.wave_ram;
+        let ch3 = &mut self.ch3;
+        step_timer(&mut ch3.timer, t_cycles, ch3_period, || {
+            ch3.position = (ch3.position + 1) % 32;
+            let byte = wave_ram[ch3.position as usize / 2];
+            ch3.sample = if ch3.position % 2 == 0 { byte >> 4 } else { byte & 0x0F };
+        });
+
+        let nr43 = self.regs[NR43];
+        let ch4 = &mut self.ch4;
+        step_timer(&mut ch4.timer, t_cycles, Self::noise_period(nr43), || {
+            let xor = (ch4.lfsr & 1) ^ ((ch4.lfsr >> 1) & 1);
+            ch4.lfsr = (ch4.lfsr >> 1) | (xor << 14);
+            if nr43 & 0x08 != 0 {
+                // 7 bit mode
+                ch4.lfsr = (ch4.lfsr & !(1 << 6)) | (xor << 6);
+            }
+        });
+    }
+
+    // Each channel's DAC output in the range -1.0..=1.0, or None if the DAC is off.
+    fn channel_outputs(&self) -> [Option<f32>; 4] {
+        let dac = |digital: u8| digital as f32 / 7.5 - 1.0;
+
+        let ch1 = (self.regs[NR12] & 0xF8 != 0).then(|| {
+            let duty = DUTY_CYCLES[(self.regs[NR11] >> 6) as usize][self.ch1.duty_position as usize];
+            dac(if self.ch1.enabled { duty * self.ch1.envelope.volume } else { 0 })
+        });
+        let ch2 = (self.regs[NR22] & 0xF8 != 0).then(|| {
+            let duty = DUTY_CYCLES[(self.regs[NR21] >> 6) as usize][self.ch2.duty_position as usize];
+            dac(if self.ch2.enabled { duty * self.ch2.envelope.volume } else { 0 })
+        });
+        let ch3 = (self.regs[NR30] & 0x80 != 0).then(|| {
+            let sample = match (self.regs[NR32] >> 5) & 3 {
+                0 => 0,
+                shift => self.ch3.sample >> (shift - 1),
+            };
+            dac(if self.ch3.enabled { sample } else { 0 })
+        });
+        let ch4 = (self.regs[NR42] & 0xF8 != 0).then(|| {
+            let bit = (!self.ch4.lfsr & 1) as u8;
+            dac(if self.ch4.enabled { bit * self.ch4.envelope.volume } else { 0 })
+        });
+        [ch1, ch2, ch3, ch4]
+    }
+
+    fn mix(&self) -> (f32, f32) {
+        if !self.powered {
+            return (0.0, 0.0);
+        }
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (i, output) in self.channel_outputs().iter().enumerate() {
+            if let Some(output) = output {
+                if (self.regs[NR51] >> (4 + i)) & 1 != 0 {
+                    left += output;
+                }
+                if (self.regs[NR51] >> i) & 1 != 0 {
+                    right += output;
+                }
+            }
+        }
+        let left_volume = ((self.regs[NR50] >> 4) & 7) as f32 + 1.0;
+        let right_volume = (self.regs[NR50] & 7) as f32 + 1.0;
+        (left * left_volume / 32.0, right * right_volume / 32.0)
+    }
+
+    // The DMG has a capacitor on each output that removes the DC offset of the DACs.
+    fn high_pass_charge_factor(&self) -> f32 {
+        0.999958f32.powf(4194304.0 / self.sample_rate as f32)
+    }
+
+    fn high_pass_left(&mut self, input: f32) -> f32 {
+        let output = input - self.capacitor_left;
+        self.capacitor_left = input - output * self.high_pass_charge_factor();
+        output
+    }
+
+    fn high_pass_right(&mut self, input: f32) -> f32 {
+        let output = input - self.capacitor_right;
+        self.capacitor_right = input - output * self.high_pass_charge_factor();
+        output
+    }
+}
+
+// Runs a channel frequency timer for `t_cycles`, calling `on_expire` every time it reloads.
+fn step_timer(timer: &mut u32, t_cycles: u32, period: u32, mut on_expire: impl FnMut()) {
+    let mut remaining = t_cycles;
+    while remaining > 0 {
+        if *timer > remaining {
+            *timer -= remaining;
+            return;
+        }
+        remaining -= *timer;
+        *timer = period;
+        on_expire();
+    }
+}
+
+impl GameBoy {
+    pub fn drain_audio_samples(&mut self) -> Vec<f32> {
+        self.apu.drain_samples()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powered_apu() -> Apu {
+        let mut apu = Apu::default();
+        apu.write(0xFF26, 0x80);
+        apu
+    }
+
+    fn channels_enabled(apu: &Apu) -> u8 {
+        apu.read(0xFF26) & 0x0F
+    }
+
+    #[test]
+    fn length_counter_expires() {
+        let mut apu = powered_apu();
+        apu.write(0xFF11, 62); // 2 length clocks
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF14, 0xC0); // trigger with the length counter on
+        assert_eq!(channels_enabled(&apu), 1);
+        // steps 0 and 2 clock the length counters
+        apu.clock_frame_sequencer();
+        apu.clock_frame_sequencer();
+        assert_eq!(channels_enabled(&apu), 1);
+        apu.clock_frame_sequencer();
+        assert_eq!(channels_enabled(&apu), 0);
+
+        // without the length counter on it plays forever
+        apu.write(0xFF14, 0x80);
+        for _ in 0..1024 {
+            apu.clock_frame_sequencer();
+        }
+        assert_eq!(channels_enabled(&apu), 1);
+    }
+
+    #[test]
+    fn envelope_steps_once_a_period() {
+        let mut apu = powered_apu();
+        apu.write(0xFF12, 0x21); // volume 2, down, every envelope clock
+        apu.write(0xFF14, 0x80);
+        let mut volumes = vec![];
+        for _ in 0..4 {
+            // the envelope is clocked on step 7
+            for _ in 0..8 {
+                apu.clock_frame_sequencer();
+            }
+            volumes.push(apu.ch1.envelope.volume);
+        }
+        assert_eq!(volumes, [1, 0, 0, 0]);
+
+        apu.write(0xFF12, 0xEA); // volume 14, up, every other envelope clock
+        apu.write(0xFF14, 0x80);
+        let mut volumes = vec![];
+        for _ in 0..6 {
+            for _ in 0..8 {
+                apu.clock_frame_sequencer();
+            }
+            volumes.push(apu.ch1.envelope.volume);
+        }
+        assert_eq!(volumes, [14, 15, 15, 15, 15, 15]);
+    }
+
+    #[test]
+    fn sweep_overflow_disables_channel_1() {
+        let mut apu = powered_apu();
+        apu.write(0xFF10, 0x11); // every sweep clock, up, shift 1
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF13, 0xB0); // frequency 0x4B0 = 1200
+        apu.write(0xFF14, 0x84);
+        assert_eq!(channels_enabled(&apu), 1);
+        // step 2 clocks the sweep: 1200 -> 1800, and the check after it overflows to 2700
+        for _ in 0..3 {
+            apu.clock_frame_sequencer();
+        }
+        assert_eq!(apu.frequency(NR13, NR14), 1800);
+        assert_eq!(channels_enabled(&apu), 0);
+
+        // overflowing on the trigger's own calculation disables it straight away
+        apu.write(0xFF13, 0x00);
+        apu.write(0xFF14, 0x86); // 0x600 + 0x300 > 2047
+        assert_eq!(channels_enabled(&apu), 0);
+    }
+
+    #[test]
+    fn powering_off_clears_registers() {
+        let mut apu = powered_apu();
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF25, 0xFF);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF14, 0x80);
+        apu.write(0xFF30, 0x5A);
+        assert_eq!(apu.read(0xFF26), 0xF1);
+
+        apu.write(0xFF26, 0x00);
+        assert_eq!(apu.read(0xFF26), 0x70);
+        assert_eq!(apu.read(0xFF24), 0x00);
+        assert_eq!(apu.read(0xFF25), 0x00);
+        assert_eq!(apu.read(0xFF12), 0x00);
+        // registers can't be written while it's off, wave RAM can and keeps its contents
+        apu.write(0xFF24, 0x77);
+        assert_eq!(apu.read(0xFF24), 0x00);
+        assert_eq!(apu.read(0xFF30), 0x5A);
+        assert_eq!(apu.mix(), (0.0, 0.0));
+    }
+
+    #[test]
+    fn wave_ram_reads_back_and_plays() {
+        let mut apu = powered_apu();
+        // 0F 1E 2D ... F0
+        let byte = |i: u16| ((i as u8) << 4) | (0x0F - i as u8);
+        for i in 0..16 {
+            apu.write(0xFF30 + i, byte(i));
+        }
+        for i in 0..16 {
+            assert_eq!(apu.read(0xFF30 + i), byte(i));
+        }
+
+        apu.write(0xFF1A, 0x80);
+        apu.write(0xFF1C, 0x20); // full volume
+        apu.write(0xFF1D, 0xFF);
+        apu.write(0xFF1E, 0x87); // frequency 2047, a sample every 2 T-cycles
+        assert_eq!(channels_enabled(&apu), 4);
+        // position 0 is skipped on trigger, the first sample played is the low nibble of byte 0
+        let mut samples = vec![];
+        for _ in 0..4 {
+            apu.step_channels(2);
+            samples.push(apu.ch3.sample);
+        }
+        assert_eq!(samples, [0x0F, 0x01, 0x0E, 0x02]);
+    }
+
+    #[test]
+    fn dac_off_silences_the_channel() {
+        let mut apu = powered_apu();
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF25, 0x11); // channel 1 on both sides
+        apu.write(0xFF11, 0x80); // 50% duty
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF14, 0x87);
+        assert!(apu.channel_outputs()[0].is_some());
+
+        // a DAC with volume 0 going up is still on, it outputs its lowest level
+        apu.write(0xFF12, 0x08);
+        assert_eq!(channels_enabled(&apu), 1);
+        assert!(apu.channel_outputs()[0].is_some());
+
+        apu.write(0xFF12, 0x00);
+        assert_eq!(channels_enabled(&apu), 0);
+        assert_eq!(apu.channel_outputs()[0], None);
+        assert_eq!(apu.mix(), (0.0, 0.0));
+        // triggering doesn't turn it back on while the DAC is off
+        apu.write(0xFF14, 0x87);
+        assert_eq!(channels_enabled(&apu), 0);
+
+        apu.write(0xFF1A, 0x80);
+        apu.write(0xFF1E, 0x80);
+        assert_eq!(channels_enabled(&apu), 4);
+        apu.write(0xFF1A, 0x00);
+        assert_eq!(channels_enabled(&apu), 0);
+    }
+}
@@ -0,0 +1,89 @@
+// Disassembles part of a ROM file, e.g. `scgb-disasm game.gb 03:4A2F --count 20`.
+use dmg::disasm;
+use std::env;
+use std::fs;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: scgb-disasm <rom> [<bank>:]<address> [--count <n>]
+
+The address and bank are hex. Addresses 4000-7FFF are in bank 1 unless a bank is given.";
+
+struct Options {
+    rom_path: String,
+    bank: usize,
+    address: u16,
+    count: usize,
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("not a hex number: {text}\n{USAGE}"))
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut positional = vec![];
+    let mut count = 20;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--count" => {
+                let n = args.next().ok_or_else(|| format!("--count needs a value\n{USAGE}"))?;
+                count = n.parse().map_err(|_| format!("--count: not a number: {n}"))?;
+            }
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {arg}\n{USAGE}")),
+            _ => positional.push(arg),
+        }
+    }
+    let [rom_path, location] = <[String; 2]>::try_from(positional).map_err(|_| USAGE.to_string())?;
+    let (bank, address) = match location.split_once(':') {
+        Some((bank, address)) => (parse_hex(bank)? as usize, parse_hex(address)?),
+        None => (1, parse_hex(&location)?),
+    };
+    if address >= 0x8000 {
+        return Err(format!("{address:04X} isn't in ROM\n{USAGE}"));
+    }
+    let bank = if address < 0x4000 { 0 } else { bank };
+    Ok(Options {
+        rom_path,
+        bank,
+        address,
+        count,
+    })
+}
+
+fn run(options: &Options) -> Result<(), String> {
+    let rom = fs::read(&options.rom_path).map_err(|e| format!("Couldn't read {}: {e}", options.rom_path))?;
+    // where an address is in the ROM file, with the bank mapped at 4000-7FFF
+    let offset = |address: u16| match address {
+        0x0000..=0x3FFF => address as usize,
+        _ => options.bank * 0x4000 + (address as usize - 0x4000),
+    };
+    if offset(options.address) >= rom.len() {
+        return Err(format!("the ROM doesn't have bank {:02X}", options.bank));
+    }
+
+    let mut address = options.address;
+    for _ in 0..options.count {
+        let instruction = disasm::decode(address, |a| rom.get(offset(a & 0x7FFF)).copied().unwrap_or(0xFF));
+        let bytes: Vec<String> = (0..instruction.length as u16)
+            .map(|i| format!("{:02X}", rom.get(offset(address.wrapping_add(i) & 0x7FFF)).unwrap_or(&0xFF)))
+            .collect();
+        println!("{:02X}:{address:04X}  {:<9} {instruction}", options.bank, bytes.join(" "));
+        address = address.wrapping_add(instruction.length as u16);
+        if address >= 0x8000 {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match parse_args().and_then(|options| run(&options)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,260 @@
+// Runs a ROM without a window, for scripts and CI.
+//
+// Exits with 0 if the success condition held (or there isn't one), 1 if it didn't by the end of
+// the run, and 2 if the ROM or options couldn't be used.
+use dmg::fifo::RendererKind;
+use dmg::movie::Movie;
+use dmg::runner::{self, Condition, Runner, M_CYCLES_PER_FRAME};
+use dmg::trace::Trace;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: scgb-headless <rom> [--frames <n> | --cycles <n>] [--boot-rom <file>]
+                    [--input <script> | --movie <file>] [--screenshot <out.png>] [--sram <out.sav>]
+                    [--save-state <out>] [--expect-state <file>] [--expect-frame-hash <hash>]
+                    [--expect-serial <text> | --expect-memory <addr>=<value>]
+                    [--serial-stdout] [--ppu <scanline|fifo>]
+                    [--trace <out.log> [--trace-start <pc>] [--trace-stop <pc>]
+                     [--trace-lines <n>] [--trace-ly-stub] [--trace-disasm]]
+
+Input scripts have one event per line: <frame> <press|release> <a|b|select|start|right|left|up|down>
+Addresses and values for --expect-memory are hex, e.g. --expect-memory c000=42
+--movie plays a movie recorded in supercoolgb from its initial state, to its end unless --frames or
+--cycles is given. --expect-state and --expect-frame-hash check where the run ended up against a
+state saved with --save-state and the frame hash printed at the end of an earlier run.
+--trace writes a gameboy-doctor log, one line per instruction. The start and stop PCs are hex, and
+--trace-ly-stub makes LY read 90 like the doctor's reference logs expect. --trace-disasm adds each
+instruction after the doctor's columns.";
+
+struct Options {
+    rom_path: PathBuf,
+    // 600 frames, or a movie's length, if not given
+    max_cycles: Option<u64>,
+    boot_rom: Option<PathBuf>,
+    input: Option<PathBuf>,
+    movie: Option<PathBuf>,
+    screenshot: Option<PathBuf>,
+    sram: Option<PathBuf>,
+    save_state: Option<PathBuf>,
+    expect_state: Option<PathBuf>,
+    expect_frame_hash: Option<u32>,
+    condition: Option<Condition>,
+    serial_stdout: bool,
+    renderer: RendererKind,
+    trace: Option<TraceOptions>,
+}
+
+#[derive(Default)]
+struct TraceOptions {
+    path: PathBuf,
+    start: Option<u16>,
+    stop: Option<u16>,
+    lines: Option<u64>,
+    ly_stub: bool,
+    disassembly: bool,
+}
+
+impl TraceOptions {
+    fn open(&self) -> Result<Trace, String> {
+        let mut trace =
+            Trace::to_file(&self.path).map_err(|e| format!("Couldn't create {}: {e}", self.path.display()))?;
+        if let Some(pc) = self.start {
+            trace = trace.starting_at(pc);
+        }
+        if let Some(pc) = self.stop {
+            trace = trace.stopping_at(pc);
+        }
+        if let Some(lines) = self.lines {
+            trace = trace.with_max_lines(lines);
+        }
+        if self.ly_stub {
+            trace = trace.with_ly_stub();
+        }
+        if self.disassembly {
+            trace = trace.with_disassembly();
+        }
+        Ok(trace)
+    }
+}
+
+fn parse_memory_condition(arg: &str) -> Result<Condition, String> {
+    let error = || format!("--expect-memory: expected <addr>=<value> in hex, got {arg}");
+    let (address, value) = arg.split_once('=').ok_or_else(error)?;
+    Ok(Condition::Memory {
+        address: u16::from_str_radix(address.trim_start_matches("0x"), 16).map_err(|_| error())?,
+        value: u8::from_str_radix(value.trim_start_matches("0x"), 16).map_err(|_| error())?,
+    })
+}
+
+fn parse_pc(option: &str, arg: &str) -> Result<u16, String> {
+    u16::from_str_radix(arg.trim_start_matches("0x"), 16).map_err(|_| format!("{option}: not a hex address: {arg}"))
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        rom_path: PathBuf::new(),
+        max_cycles: None,
+        boot_rom: None,
+        input: None,
+        movie: None,
+        screenshot: None,
+        sram: None,
+        save_state: None,
+        expect_state: None,
+        expect_frame_hash: None,
+        condition: None,
+        serial_stdout: false,
+        renderer: RendererKind::default(),
+        trace: None,
+    };
+    let mut rom_path = None;
+    let mut trace = TraceOptions::default();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value\n{USAGE}"));
+        match arg.as_str() {
+            "--frames" | "--cycles" => {
+                let n = value()?;
+                let n: u64 = n.parse().map_err(|_| format!("{arg}: not a number: {n}"))?;
+                options.max_cycles = Some(if arg == "--frames" { n * M_CYCLES_PER_FRAME } else { n });
+            }
+            "--boot-rom" => options.boot_rom = Some(PathBuf::from(value()?)),
+            "--input" => options.input = Some(PathBuf::from(value()?)),
+            "--movie" => options.movie = Some(PathBuf::from(value()?)),
+            "--screenshot" => options.screenshot = Some(PathBuf::from(value()?)),
+            "--sram" => options.sram = Some(PathBuf::from(value()?)),
+            "--save-state" => options.save_state = Some(PathBuf::from(value()?)),
+            "--expect-state" => options.expect_state = Some(PathBuf::from(value()?)),
+            "--expect-frame-hash" => {
+                let hash = value()?;
+                let parsed = u32::from_str_radix(hash.trim_start_matches("0x"), 16);
+                options.expect_frame_hash = Some(parsed.map_err(|_| format!("{arg}: not a hex hash: {hash}"))?);
+            }
+            "--expect-serial" => options.condition = Some(Condition::Serial(value()?)),
+            "--expect-memory" => options.condition = Some(parse_memory_condition(&value()?)?),
+            "--serial-stdout" => options.serial_stdout = true,
+            "--ppu" => {
+                options.renderer = match value()?.as_str() {
+                    "scanline" => RendererKind::Scanline,
+                    "fifo" => RendererKind::Fifo,
+                    other => return Err(format!("--ppu: unknown renderer {other}\n{USAGE}")),
+                };
+            }
+            "--trace" => trace.path = PathBuf::from(value()?),
+            "--trace-start" => trace.start = Some(parse_pc(&arg, &value()?)?),
+            "--trace-stop" => trace.stop = Some(parse_pc(&arg, &value()?)?),
+            "--trace-lines" => {
+                let n = value()?;
+                trace.lines = Some(n.parse().map_err(|_| format!("{arg}: not a number: {n}"))?);
+            }
+            "--trace-ly-stub" => trace.ly_stub = true,
+            "--trace-disasm" => trace.disassembly = true,
+            "--help" | "-h" => return Err(USAGE.to_string()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option {arg}\n{USAGE}")),
+            _ if rom_path.is_none() => rom_path = Some(PathBuf::from(arg)),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    options.rom_path = rom_path.ok_or_else(|| format!("Provide the ROM file to run\n{USAGE}"))?;
+    if options.input.is_some() && options.movie.is_some() {
+        return Err(format!("--input and --movie can't be used together\n{USAGE}"));
+    }
+    if !trace.path.as_os_str().is_empty() {
+        options.trace = Some(trace);
+    }
+    Ok(options)
+}
+
+fn run(options: &Options) -> Result<bool, String> {
+    let read = |path: &PathBuf| fs::read(path).map_err(|e| format!("Couldn't read {}: {e}", path.display()));
+    let rom = read(&options.rom_path)?;
+    let boot_rom = options.boot_rom.as_ref().map(read).transpose()?;
+    let input = match &options.input {
+        Some(path) => {
+            let script = fs::read_to_string(path).map_err(|e| format!("Couldn't read {}: {e}", path.display()))?;
+            runner::parse_input_script(&script).map_err(|e| format!("{}: {e}", path.display()))?
+        }
+        None => vec![],
+    };
+
+    let movie = match &options.movie {
+        Some(path) => Some(Movie::from_bytes(&read(path)?).map_err(|e| format!("{}: {e}", path.display()))?),
+        None => None,
+    };
+    let expected_state = options.expect_state.as_ref().map(read).transpose()?;
+
+    let mut gameboy = runner::load_rom(rom, boot_rom.as_deref());
+    gameboy.renderer_kind = options.renderer;
+    let player = match &movie {
+        Some(movie) => Some(
+            gameboy
+                .play_movie(movie)
+                .map_err(|e| format!("Couldn't play the movie: {e}"))?,
+        ),
+        None => None,
+    };
+    if let Some(trace) = &options.trace {
+        gameboy.start_trace(trace.open()?);
+    }
+    let mut runner = Runner::new(gameboy, options.serial_stdout).with_input(input);
+    if let Some(player) = player {
+        runner = runner.with_movie(player);
+    }
+    let max_cycles = options.max_cycles.unwrap_or(match &movie {
+        Some(movie) => movie.cycles(),
+        None => 600 * M_CYCLES_PER_FRAME,
+    });
+    let mut passed = runner.run(max_cycles, options.condition.as_ref());
+
+    match runner.gameboy.stop_trace() {
+        Ok(Some(trace)) => eprintln!("Traced {} instructions", trace.lines),
+        Ok(None) => {}
+        Err(e) => eprintln!("Couldn't write the trace: {e}"),
+    }
+    if let Some(path) = &options.screenshot {
+        runner::save_screenshot(&runner.gameboy, path)
+            .map_err(|e| format!("Couldn't write {}: {e}", path.display()))?;
+    }
+    if let Some(path) = &options.sram {
+        runner
+            .gameboy
+            .save_sav(path)
+            .map_err(|e| format!("Couldn't write {}: {e}", path.display()))?;
+    }
+    let state = runner.gameboy.save_state();
+    if let Some(path) = &options.save_state {
+        fs::write(path, &state).map_err(|e| format!("Couldn't write {}: {e}", path.display()))?;
+    }
+    if expected_state.is_some_and(|expected| expected != state) {
+        eprintln!("The end state isn't the expected one");
+        passed = false;
+    }
+    let frame_hash = runner::frame_hash(&runner.gameboy);
+    eprintln!("Frame hash {frame_hash:08x}");
+    if options.expect_frame_hash.is_some_and(|expected| expected != frame_hash) {
+        eprintln!("The last frame isn't the expected one");
+        passed = false;
+    }
+    eprintln!(
+        "Ran {} frames ({} M-cycles): {}",
+        runner.frames(),
+        runner.cycles,
+        if passed { "passed" } else { "failed" }
+    );
+    Ok(passed)
+}
+
+fn main() -> ExitCode {
+    let result = parse_args().and_then(|options| run(&options));
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::from(2)
+        }
+    }
+}
@@ -0,0 +1,351 @@
+// Game Boy Color mode: the second VRAM bank, switchable WRAM banks, colour palettes, double speed
+// and HDMA. All of it is only active when `cgb.enabled` is set, otherwise the registers read back
+// 0xFF like on a DMG.
+use crate::gb::GameBoy;
+use crate::state::big_array;
+use serde::{Deserialize, Serialize};
+
+const VRAM_BANK_SIZE: usize = 0x2000;
+const WRAM_BANK_SIZE: usize = 0x1000;
+const PALETTE_RAM_SIZE: usize = 64;
+// M-cycles the CPU is paused for while switching speed.
+const SPEED_SWITCH_CYCLES: u16 = 2050;
+
+#[derive(Serialize, Deserialize)]
+pub struct Cgb {
+    pub enabled: bool,
+    pub vram_bank: u8, // VBK
+    #[serde(with = "big_array")]
+    pub vram_1: Box<[u8; VRAM_BANK_SIZE]>, // bank 0 lives in memory.main
+    pub wram_bank: u8, // SVBK
+    // banks 2-7, bank 0 and 1 live in memory.main
+    pub wram: Vec<u8>,
+    #[serde(with = "big_array")]
+    pub bg_palettes: Box<[u8; PALETTE_RAM_SIZE]>,
+    #[serde(with = "big_array")]
+    pub obj_palettes: Box<[u8; PALETTE_RAM_SIZE]>,
+    pub bcps: u8,
+    pub ocps: u8,
+    pub double_speed: bool,
+    pub speed_switch_armed: bool, // KEY1 bit 0
+    pub hdma_source: u16,
+    pub hdma_destination: u16,
+    // 16 byte blocks left in an HBlank DMA
+    pub hblank_dma_blocks: Option<u8>,
+    // M-cycles the CPU is paused for (general purpose DMA, speed switch)
+    pub cpu_stall: u16,
+}
+
+impl Default for Cgb {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            vram_bank: 0,
+            vram_1: Box::new([0; VRAM_BANK_SIZE]),
+            wram_bank: 1,
+            wram: vec![0; 6 * WRAM_BANK_SIZE],
+            bg_palettes: Box::new([0xFF; PALETTE_RAM_SIZE]),
+            obj_palettes: Box::new([0xFF; PALETTE_RAM_SIZE]),
+            bcps: 0,
+            ocps: 0,
+            double_speed: false,
+            speed_switch_armed: false,
+            hdma_source: 0,
+            hdma_destination: 0x8000,
+            hblank_dma_blocks: None,
+            cpu_stall: 0,
+        }
+    }
+}
+
+fn palette_colour(palettes: &[u8; PALETTE_RAM_SIZE], palette: u8, colour: u8) -> u16 {
+    let index = (palette as usize & 7) * 8 + colour as usize * 2;
+    u16::from_le_bytes([palettes[index], palettes[index + 1]]) & 0x7FFF
+}
+
+// BCPS/OCPS: bits 0-5 index, bit 7 auto increment after a write to the data register.
+fn write_palette_data(palettes: &mut [u8; PALETTE_RAM_SIZE], spec: &mut u8, data: u8) {
+    palettes[(*spec & 0x3F) as usize] = data;
+    if *spec & 0x80 != 0 {
+        *spec = 0x80 | ((*spec + 1) & 0x3F);
+    }
+}
+
+impl GameBoy {
+    // Header byte 0x143: 0x80 means the game also runs on a DMG, 0xC0 means CGB only.
+    pub fn cartridge_supports_cgb(&self) -> bool {
+        matches!(self.memory.cartridge.get(0x143), Some(0x80) | Some(0xC0))
+    }
+
+    // Switches to CGB mode. We don't have the CGB boot ROM, so this starts straight at the
+    // cartridge with the registers the boot ROM would have left behind.
+    pub fn start_cgb_mode(&mut self) {
+        self.cgb.enabled = true;
+        self.skip_boot_rom();
+    }
+
+    pub(crate) fn cgb_read(&self, address: u16) -> u8 {
+        if !self.cgb.enabled {
+            return 0xFF;
+        }
+        match address {
+            0xFF4D => 0x7E | ((self.cgb.double_speed as u8) << 7) | self.cgb.speed_switch_armed as u8,
+            0xFF4F => 0xFE | self.cgb.vram_bank,
+            0xFF55 => match self.cgb.hblank_dma_blocks {
+                Some(blocks) => blocks - 1,
+                None => 0xFF,
+            },
+            0xFF68 => self.cgb.bcps | 0x40,
+            0xFF69 => self.cgb.bg_palettes[(self.cgb.bcps & 0x3F) as usize],
+            0xFF6A => self.cgb.ocps | 0x40,
+            0xFF6B => self.cgb.obj_palettes[(self.cgb.ocps & 0x3F) as usize],
+            0xFF70 => 0xF8 | self.cgb.wram_bank,
+            _ => 0xFF,
+        }
+    }
+
+    pub(crate) fn cgb_write(&mut self, address: u16, data: u8) {
+        if !self.cgb.enabled {
+            return;
+        }
+        match address {
+            0xFF4D => self.cgb.speed_switch_armed = data & 1 != 0,
+            0xFF4F => self.cgb.vram_bank = data & 1,
+            0xFF51 => self.cgb.hdma_source = (self.cgb.hdma_source & 0x00F0) | ((data as u16) << 8),
+            0xFF52 => self.cgb.hdma_source = (self.cgb.hdma_source & 0xFF00) | (data as u16 & 0xF0),
+            0xFF53 => {
+                self.cgb.hdma_destination =
+                    (self.cgb.hdma_destination & 0x00F0) | 0x8000 | ((data as u16 & 0x1F) << 8)
+            }
+            0xFF54 => {
+                self.cgb.hdma_destination = (self.cgb.hdma_destination & 0xFF00) | (data as u16 & 0xF0)
+            }
+            0xFF55 => self.start_hdma(data),
+            0xFF68 => self.cgb.bcps = data & 0xBF,
+            0xFF69 => write_palette_data(&mut self.cgb.bg_palettes, &mut self.cgb.bcps, data),
+            0xFF6A => self.cgb.ocps = data & 0xBF,
+            0xFF6B => write_palette_data(&mut self.cgb.obj_palettes, &mut self.cgb.ocps, data),
+            0xFF70 => self.cgb.wram_bank = (data & 7).max(1),
+            _ => {}
+        }
+    }
+
+    // Where a CPU access to VRAM or WRAM ends up when it isn't in memory.main: VRAM bank 1, or an
+    // offset into WRAM banks 2-7.
+    fn cgb_banked_location(&self, address: u16) -> Option<(bool, usize)> {
+        if !self.cgb.enabled {
+            return None;
+        }
+        match address {
+            0x8000..=0x9FFF if self.cgb.vram_bank == 1 => Some((true, address as usize - 0x8000)),
+            0xD000..=0xDFFF | 0xF000..=0xFDFF if self.cgb.wram_bank > 1 => {
+                let offset = (address as usize & 0x0FFF) + (self.cgb.wram_bank as usize - 2) * WRAM_BANK_SIZE;
+                Some((false, offset))
+            }
+            _ => None,
+        }
+    }
+
+    pub(crate) fn cgb_banked_ram(&mut self, address: u16) -> Option<&mut u8> {
+        match self.cgb_banked_location(address)? {
+            (true, offset) => Some(&mut self.cgb.vram_1[offset]),
+            (false, offset) => Some(&mut self.cgb.wram[offset]),
+        }
+    }
+
+    pub(crate) fn cgb_banked_byte(&self, address: u16) -> Option<u8> {
+        match self.cgb_banked_location(address)? {
+            (true, offset) => Some(self.cgb.vram_1[offset]),
+            (false, offset) => Some(self.cgb.wram[offset]),
+        }
+    }
+
+    // VRAM as the PPU sees it, regardless of VBK.
+    pub(crate) fn vram(&self, bank: u8, address: u16) -> u8 {
+        match bank {
+            0 => self.memory.main[address as usize],
+            _ => self.cgb.vram_1[address as usize - 0x8000],
+        }
+    }
+
+    pub(crate) fn cgb_bg_colour(&self, palette: u8, colour: u8) -> u16 {
+        palette_colour(&self.cgb.bg_palettes, palette, colour)
+    }
+
+    pub(crate) fn cgb_obj_colour(&self, palette: u8, colour: u8) -> u16 {
+        palette_colour(&self.cgb.obj_palettes, palette, colour)
+    }
+
+    // STOP with KEY1 armed switches speed instead of stopping. Returns true if it did.
+    pub(crate) fn try_speed_switch(&mut self) -> bool {
+        if !self.cgb.enabled || !self.cgb.speed_switch_armed {
+            return false;
+        }
+        self.cgb.double_speed = !self.cgb.double_speed;
+        self.cgb.speed_switch_armed = false;
+        self.cgb.cpu_stall = SPEED_SWITCH_CYCLES;
+        self.r.div_16 = 0;
+        true
+    }
+
+    // HDMA5: bit 7 clear copies everything now (general purpose DMA), bit 7 set copies 16 bytes
+    // every HBlank. Writing with bit 7 clear during an HBlank DMA cancels it.
+    fn start_hdma(&mut self, data: u8) {
+        let blocks = (data & 0x7F) + 1;
+        if data & 0x80 != 0 {
+            self.cgb.hblank_dma_blocks = Some(blocks);
+        } else if self.cgb.hblank_dma_blocks.is_some() {
+            self.cgb.hblank_dma_blocks = None;
+        } else {
+            for _ in 0..blocks {
+                self.hdma_copy_block();
+            }
+        }
+    }
+
+    // Called at the start of HBlank on visible lines.
+    pub(crate) fn hblank_dma(&mut self) {
+        if let Some(blocks) = self.cgb.hblank_dma_blocks {
+            self.hdma_copy_block();
+            self.cgb.hblank_dma_blocks = if blocks > 1 { Some(blocks - 1) } else { None };
+        }
+    }
+
+    fn hdma_copy_block(&mut self) {
+        for _ in 0..16 {
+            // the DMA engine's own reads, not the CPU's: no watchpoints and no OAM DMA lockout
+            let byte = self.bus_read(self.cgb.hdma_source);
+            let offset = self.cgb.hdma_destination as usize & 0x1FFF;
+            match self.cgb.vram_bank {
+                0 => self.memory.main[0x8000 + offset] = byte,
+                _ => self.cgb.vram_1[offset] = byte,
+            }
+            self.cgb.hdma_source = self.cgb.hdma_source.wrapping_add(1);
+            self.cgb.hdma_destination = 0x8000 | ((self.cgb.hdma_destination + 1) & 0x1FFF);
+        }
+        // 8 M-cycles per block at either speed
+        self.cgb.cpu_stall += if self.cgb.double_speed { 16 } else { 8 };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gb;
+
+    fn cgb_gameboy() -> gb::GameBoy {
+        let mut gameboy = gb::init();
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0xC0;
+        // 0x100: jr -2
+        rom[0x100..0x102].copy_from_slice(&[0x18, 0xFE]);
+        gameboy.memory.cartridge = rom;
+        gameboy.mbc = gameboy.detect_mbc();
+        gameboy.start_cgb_mode();
+        gameboy
+    }
+
+    #[test]
+    fn vram_and_wram_banks() {
+        let mut gameboy = cgb_gameboy();
+        assert_eq!(gameboy.r.a, 0x11);
+        gameboy.write(0x8000, 0x12);
+        gameboy.write(0xFF4F, 1);
+        gameboy.write(0x8000, 0x34);
+        assert_eq!(gameboy.read(0x8000), 0x34);
+        gameboy.write(0xFF4F, 0);
+        assert_eq!(gameboy.read(0x8000), 0x12);
+        assert_eq!(gameboy.vram(1, 0x8000), 0x34);
+
+        gameboy.write(0xD000, 0x56);
+        gameboy.write(0xFF70, 3);
+        assert_eq!(gameboy.read(0xD000), 0x00);
+        gameboy.write(0xD000, 0x78);
+        gameboy.write(0xFF70, 0); // bank 0 selects bank 1
+        assert_eq!(gameboy.read(0xD000), 0x56);
+        gameboy.write(0xFF70, 3);
+        assert_eq!(gameboy.read(0xD000), 0x78);
+    }
+
+    #[test]
+    fn palette_ram_auto_increments() {
+        let mut gameboy = cgb_gameboy();
+        gameboy.write(0xFF68, 0x80 | 8);
+        gameboy.write(0xFF69, 0x1F);
+        gameboy.write(0xFF69, 0x00);
+        assert_eq!(gameboy.read(0xFF68), 0xC0 | 10);
+        assert_eq!(gameboy.cgb_bg_colour(1, 0), 0x001F);
+    }
+
+    #[test]
+    fn general_purpose_dma_copies_to_vram() {
+        let mut gameboy = cgb_gameboy();
+        for i in 0..32 {
+            gameboy.write(0xC000 + i, i as u8);
+        }
+        gameboy.write(0xFF51, 0xC0);
+        gameboy.write(0xFF52, 0x00);
+        gameboy.write(0xFF53, 0x01);
+        gameboy.write(0xFF54, 0x00);
+        gameboy.write(0xFF55, 0x01);
+        assert_eq!(gameboy.read(0xFF55), 0xFF);
+        assert_eq!(gameboy.read(0x8100), 0);
+        assert_eq!(gameboy.read(0x811F), 31);
+    }
+
+    #[test]
+    fn dma_reads_arent_cpu_reads() {
+        let mut gameboy = cgb_gameboy();
+        for i in 0..16 {
+            gameboy.write(0xC000 + i, 0x10 + i as u8);
+        }
+        // an OAM DMA running at the same time locks the CPU out of WRAM, but not HDMA
+        gameboy.write(0xFF46, 0xC1);
+        gameboy.tick();
+        gameboy.tick();
+        assert_eq!(gameboy.read(0xC000), 0xFF);
+        gameboy.debugger.watchpoints.push(crate::debugger::parse_watchpoint("c000-c00f r").unwrap());
+        gameboy.write(0xFF51, 0xC0);
+        gameboy.write(0xFF52, 0x00);
+        gameboy.write(0xFF53, 0x01);
+        gameboy.write(0xFF54, 0x00);
+        gameboy.write(0xFF55, 0x00);
+        assert_eq!(gameboy.vram(0, 0x8100), 0x10);
+        assert_eq!(gameboy.vram(0, 0x810F), 0x1F);
+        assert!(gameboy.debugger.stop_reason().is_none());
+    }
+
+    #[test]
+    fn background_uses_attributes_and_colour_palettes() {
+        let mut gameboy = cgb_gameboy();
+        // tile 0 in VRAM bank 1 is solid colour 1
+        gameboy.write(0xFF4F, 1);
+        for row in 0..8 {
+            gameboy.write(0x8000 + row * 2, 0xFF);
+        }
+        // top left map entry: tile 0 from bank 1, palette 2
+        gameboy.write(0x9800, 0x08 | 2);
+        gameboy.write(0xFF4F, 0);
+        // palette 2 colour 1 is pure red
+        gameboy.write(0xFF68, 0x80 | (2 * 8 + 2));
+        gameboy.write(0xFF69, 0x1F);
+        gameboy.write(0xFF69, 0x00);
+
+        for _ in 0..2 * 17556 {
+            gameboy.tick();
+        }
+        assert_eq!(gameboy.display()[0], 0x001F);
+        assert_eq!(gameboy.display()[8], 0x7FFF);
+    }
+
+    #[test]
+    fn stop_switches_speed_when_armed() {
+        let mut gameboy = cgb_gameboy();
+        // 0x100: stop; jr -2
+        gameboy.memory.cartridge[0x100..0x104].copy_from_slice(&[0x10, 0x00, 0x18, 0xFE]);
+        gameboy.write(0xFF4D, 1);
+        for _ in 0..10 {
+            gameboy.tick();
+        }
+        assert_eq!(gameboy.read(0xFF4D), 0xFE);
+    }
+}
@@ -0,0 +1,561 @@
+// Breakpoints, watchpoints and stepping. tick() asks the debugger before doing anything, and while
+// execution is stopped it returns without running, so frontends can keep calling it.
+//
+// PC breakpoints and execute watchpoints are checked when the CPU is about to fetch an instruction,
+// so they stop before it runs. Read and write watchpoints stop once the instruction that made the
+// access has finished.
+use crate::gb::{GameBoy, State};
+use crate::runner::M_CYCLES_PER_FRAME;
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Register {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+    LY,
+}
+
+const REGISTER_NAMES: [(&str, Register); 15] = [
+    ("a", Register::A),
+    ("f", Register::F),
+    ("b", Register::B),
+    ("c", Register::C),
+    ("d", Register::D),
+    ("e", Register::E),
+    ("h", Register::H),
+    ("l", Register::L),
+    ("af", Register::AF),
+    ("bc", Register::BC),
+    ("de", Register::DE),
+    ("hl", Register::HL),
+    ("sp", Register::SP),
+    ("pc", Register::PC),
+    ("ly", Register::LY),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+// Longest first, so "<=" isn't read as "<"
+const COMPARISON_NAMES: [(&str, Comparison); 6] = [
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    ("<=", Comparison::LessOrEqual),
+    (">=", Comparison::GreaterOrEqual),
+    ("<", Comparison::Less),
+    (">", Comparison::Greater),
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Condition {
+    pub register: Register,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Breakpoint {
+    pub address: u16,
+    // only stop when this bank is mapped at the address (ROM, SRAM, VRAM or WRAM bank)
+    pub bank: Option<u16>,
+    pub condition: Option<Condition>,
+    pub enabled: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    pub enabled: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Step {
+    Instruction,
+    // stop at the instruction after a CALL or RST, once the call has returned
+    Over { pc: u16, sp: u16 },
+    // stop once a RET has returned from the current function
+    Out { sp: u16 },
+    Scanline(u8),
+    Frame,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum StopReason {
+    Paused,
+    Breakpoint(Breakpoint),
+    Watchpoint { access: Access, address: u16, value: u8 },
+    Step(Step),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopReason::Paused => write!(f, "paused"),
+            StopReason::Breakpoint(breakpoint) => write!(f, "hit breakpoint {breakpoint}"),
+            StopReason::Watchpoint { access: Access::Execute, address, .. } => {
+                write!(f, "execute watchpoint at {address:04X}")
+            }
+            StopReason::Watchpoint { access, address, value } => {
+                let verb = if *access == Access::Read { "read" } else { "wrote" };
+                write!(f, "watchpoint: {verb} {value:02X} at {address:04X}")
+            }
+            StopReason::Step(Step::Instruction) => write!(f, "stepped one instruction"),
+            StopReason::Step(Step::Over { .. }) => write!(f, "stepped over"),
+            StopReason::Step(Step::Out { .. }) => write!(f, "stepped out"),
+            StopReason::Step(Step::Scanline(ly)) => write!(f, "reached scanline {ly}"),
+            StopReason::Step(Step::Frame) => write!(f, "reached the next frame"),
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(bank) = self.bank {
+            write!(f, "{bank:02X}:")?;
+        }
+        write!(f, "{:04X}", self.address)?;
+        if let Some(condition) = &self.condition {
+            let register = REGISTER_NAMES.iter().find(|(_, r)| *r == condition.register).unwrap().0;
+            let comparison = COMPARISON_NAMES.iter().find(|(_, c)| *c == condition.comparison).unwrap().0;
+            write!(f, " if {register} {comparison} {:X}", condition.value)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text.trim().trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("not a hex number: {text}"))
+}
+
+// `[bank:]address [if <register> <comparison> <value>]` in hex, e.g. `03:4A2F if a == 3`
+pub fn parse_breakpoint(text: &str) -> Result<Breakpoint, String> {
+    let (location, condition) = match text.split_once(" if ") {
+        Some((location, condition)) => (location, Some(parse_condition(condition)?)),
+        None => (text, None),
+    };
+    let (bank, address) = match location.split_once(':') {
+        Some((bank, address)) => (Some(parse_hex(bank)?), parse_hex(address)?),
+        None => (None, parse_hex(location)?),
+    };
+    Ok(Breakpoint {
+        address,
+        bank,
+        condition,
+        enabled: true,
+    })
+}
+
+fn parse_condition(text: &str) -> Result<Condition, String> {
+    let (comparison_name, comparison) = COMPARISON_NAMES
+        .iter()
+        .find(|(name, _)| text.contains(name))
+        .ok_or_else(|| format!("expected a comparison (== != < <= > >=) in {text}"))?;
+    let (register, value) = text.split_once(comparison_name).unwrap();
+    let register = register.trim().to_ascii_lowercase();
+    let register = REGISTER_NAMES
+        .iter()
+        .find(|(name, _)| *name == register)
+        .ok_or_else(|| format!("unknown register {register}"))?
+        .1;
+    Ok(Condition {
+        register,
+        comparison: *comparison,
+        value: parse_hex(value)?,
+    })
+}
+
+// `start[-end]` in hex, with any of r, w and x for the accesses to watch, e.g. `c000-c0ff rw`
+pub fn parse_watchpoint(text: &str) -> Result<Watchpoint, String> {
+    let (range, accesses) = text.trim().split_once(' ').unwrap_or((text.trim(), "w"));
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse_hex(start)?, parse_hex(end)?),
+        None => (parse_hex(range)?, parse_hex(range)?),
+    };
+    let accesses = accesses.trim().to_ascii_lowercase();
+    if accesses.is_empty() || !accesses.chars().all(|c| "rwx".contains(c)) {
+        return Err(format!("expected any of r, w and x, got {accesses}"));
+    }
+    Ok(Watchpoint {
+        start: start.min(end),
+        end: start.max(end),
+        read: accesses.contains('r'),
+        write: accesses.contains('w'),
+        execute: accesses.contains('x'),
+        enabled: true,
+    })
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    pub breakpoints: Vec<Breakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    stop: Option<StopReason>,
+    step: Option<Step>,
+    // set on resuming until the next check, so it doesn't stop again on the breakpoint it's sitting on
+    resuming: bool,
+    // clock when execution last resumed
+    resumed_at: u128,
+    // stopped between the two CPU M-cycles of a double speed tick, the next tick finishes that one
+    pub(crate) second_half: bool,
+    last_ly: u8,
+    pub(crate) last_opcode: u8,
+}
+
+impl Debugger {
+    pub fn stop_reason(&self) -> Option<&StopReason> {
+        self.stop.as_ref()
+    }
+
+    fn watching(&self) -> bool {
+        !self.watchpoints.is_empty()
+    }
+
+    fn idle(&self) -> bool {
+        self.stop.is_none() && self.step.is_none() && self.breakpoints.is_empty() && self.watchpoints.is_empty()
+    }
+}
+
+impl Condition {
+    fn holds(&self, gameboy: &GameBoy) -> bool {
+        let r = &gameboy.r;
+        let pair = |high: u8, low: u8| u16::from_be_bytes([high, low]);
+        let value = match self.register {
+            Register::A => r.a as u16,
+            Register::F => r.f as u16,
+            Register::B => r.b as u16,
+            Register::C => r.c as u16,
+            Register::D => r.d as u16,
+            Register::E => r.e as u16,
+            Register::H => r.h as u16,
+            Register::L => r.l as u16,
+            Register::AF => pair(r.a, r.f),
+            Register::BC => pair(r.b, r.c),
+            Register::DE => pair(r.d, r.e),
+            Register::HL => pair(r.h, r.l),
+            Register::SP => r.sp,
+            Register::PC => r.pc,
+            Register::LY => r.ly as u16,
+        };
+        match self.comparison {
+            Comparison::Equal => value == self.value,
+            Comparison::NotEqual => value != self.value,
+            Comparison::Less => value < self.value,
+            Comparison::LessOrEqual => value <= self.value,
+            Comparison::Greater => value > self.value,
+            Comparison::GreaterOrEqual => value >= self.value,
+        }
+    }
+}
+
+impl GameBoy {
+    pub fn pause(&mut self) {
+        if self.debugger.stop.is_none() {
+            self.debugger.stop = Some(StopReason::Paused);
+        }
+    }
+
+    pub fn resume(&mut self) {
+        self.debugger.stop = None;
+        self.debugger.step = None;
+        self.debugger.resuming = true;
+        self.debugger.resumed_at = self.clock;
+        self.debugger.last_ly = self.r.ly;
+    }
+
+    pub fn step_instruction(&mut self) {
+        self.resume();
+        self.debugger.step = Some(Step::Instruction);
+    }
+
+    pub fn step_over(&mut self) {
+        self.resume();
+        let pc = self.r.pc;
+        let opcode = self.peek(pc);
+        let length = match opcode {
+            // CALL and CALL cc
+            0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC => 3,
+            // RST
+            _ if opcode & 0xC7 == 0xC7 => 1,
+            _ => 0,
+        };
+        self.debugger.step = Some(match length {
+            0 => Step::Instruction,
+            _ => Step::Over {
+                pc: pc.wrapping_add(length),
+                sp: self.r.sp,
+            },
+        });
+    }
+
+    pub fn step_out(&mut self) {
+        self.resume();
+        self.debugger.step = Some(Step::Out { sp: self.r.sp });
+    }
+
+    pub fn run_to_scanline(&mut self, ly: u8) {
+        self.resume();
+        self.debugger.step = Some(Step::Scanline(ly));
+    }
+
+    pub fn run_to_next_frame(&mut self) {
+        self.resume();
+        self.debugger.step = Some(Step::Frame);
+    }
+
+    // Which bank is mapped at an address, for bank-qualified breakpoints.
+    pub fn bank_at(&self, address: u16) -> u16 {
+        match address {
+            0x4000..=0x7FFF if self.mbc.rom_size > 0x8000 => self.mbc.rom_bank_number as u16,
+            0x4000..=0x7FFF => 1,
+            0x8000..=0x9FFF => self.cgb.vram_bank as u16,
+            0xA000..=0xBFFF => self.mbc.ram_bank_number as u16,
+            0xD000..=0xDFFF if self.cgb.enabled => self.cgb.wram_bank as u16,
+            0xD000..=0xDFFF => 1,
+            _ => 0,
+        }
+    }
+
+    // Called before every CPU M-cycle, twice a tick in double speed. Returns whether execution is stopped.
+    pub(crate) fn debugger_stopped(&mut self) -> bool {
+        let resuming = std::mem::take(&mut self.debugger.resuming);
+        if self.debugger.idle() {
+            return false;
+        }
+        if self.debugger.stop.is_some() {
+            return true;
+        }
+        let fetching = self.state == State::Execute && self.cycles_to_idle == Some(0) && self.cgb.cpu_stall == 0;
+        let ly_changed = self.r.ly != self.debugger.last_ly;
+        self.debugger.last_ly = self.r.ly;
+
+        let stepped = match self.debugger.step {
+            Some(Step::Instruction) => fetching && !resuming,
+            Some(Step::Over { pc, sp }) => fetching && self.r.pc == pc && self.r.sp >= sp,
+            Some(Step::Out { sp }) => {
+                // RET, RETI and RET cc
+                let returned = matches!(self.debugger.last_opcode, 0xC9 | 0xD9 | 0xC0 | 0xC8 | 0xD0 | 0xD8);
+                fetching && returned && self.r.sp > sp
+            }
+            Some(Step::Scanline(ly)) => ly_changed && self.r.ly == ly,
+            // stop at VBlank, or after a frame's worth of cycles with the LCD off
+            Some(Step::Frame) => {
+                (ly_changed && self.r.ly == 144) || self.clock - self.debugger.resumed_at >= M_CYCLES_PER_FRAME as u128
+            }
+            None => false,
+        };
+        if stepped {
+            self.debugger.stop = self.debugger.step.take().map(StopReason::Step);
+            return true;
+        }
+
+        if fetching && !resuming {
+            self.check_breakpoints();
+        }
+        self.debugger.stop.is_some()
+    }
+
+    fn check_breakpoints(&mut self) {
+        let pc = self.r.pc;
+        let bank = self.bank_at(pc);
+        let hit = self.debugger.breakpoints.iter().find(|breakpoint| {
+            breakpoint.enabled
+                && breakpoint.address == pc
+                && breakpoint.bank.map_or(true, |b| b == bank)
+                && breakpoint.condition.map_or(true, |condition| condition.holds(self))
+        });
+        if let Some(breakpoint) = hit {
+            self.debugger.stop = Some(StopReason::Breakpoint(breakpoint.clone()));
+            return;
+        }
+        let executed = self
+            .debugger
+            .watchpoints
+            .iter()
+            .any(|watchpoint| watchpoint.enabled && watchpoint.execute && (watchpoint.start..=watchpoint.end).contains(&pc));
+        if executed {
+            let value = self.peek(pc);
+            self.debugger.stop = Some(StopReason::Watchpoint {
+                access: Access::Execute,
+                address: pc,
+                value,
+            });
+        }
+    }
+
+    // Called on every CPU read and write.
+    pub(crate) fn check_watchpoints(&mut self, address: u16, value: u8, access: Access) {
+        if !self.debugger.watching() || self.debugger.stop.is_some() {
+            return;
+        }
+        let hit = self.debugger.watchpoints.iter().any(|watchpoint| {
+            let watched = match access {
+                Access::Read => watchpoint.read,
+                Access::Write => watchpoint.write,
+                Access::Execute => false,
+            };
+            watchpoint.enabled && watched && (watchpoint.start..=watchpoint.end).contains(&address)
+        });
+        if hit {
+            self.debugger.stop = Some(StopReason::Watchpoint { access, address, value });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::load_rom;
+
+    // 0x100: ld a, 1; call 0x200; ld (0xC000), a; jr -2
+    // 0x200: inc a; ret
+    fn debug_gameboy() -> GameBoy {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x10A].copy_from_slice(&[0x3E, 0x01, 0xCD, 0x00, 0x02, 0xEA, 0x00, 0xC0, 0x18, 0xFE]);
+        rom[0x200..0x202].copy_from_slice(&[0x3C, 0xC9]);
+        load_rom(rom, None)
+    }
+
+    fn run(gameboy: &mut GameBoy) -> StopReason {
+        for _ in 0..M_CYCLES_PER_FRAME * 2 {
+            gameboy.tick();
+            if let Some(reason) = gameboy.debugger.stop_reason() {
+                return reason.clone();
+            }
+        }
+        panic!("didn't stop");
+    }
+
+    #[test]
+    fn parses_breakpoints_and_watchpoints() {
+        let breakpoint = parse_breakpoint("03:4A2F if a >= 10").unwrap();
+        assert_eq!(breakpoint.bank, Some(3));
+        assert_eq!(breakpoint.address, 0x4A2F);
+        assert_eq!(
+            breakpoint.condition,
+            Some(Condition {
+                register: Register::A,
+                comparison: Comparison::GreaterOrEqual,
+                value: 0x10
+            })
+        );
+        assert_eq!(breakpoint.to_string(), "03:4A2F if a >= 10");
+        assert!(parse_breakpoint("4A2F if q == 1").is_err());
+
+        let watchpoint = parse_watchpoint("c0ff-c000 rx").unwrap();
+        assert_eq!((watchpoint.start, watchpoint.end), (0xC000, 0xC0FF));
+        assert!(watchpoint.read && !watchpoint.write && watchpoint.execute);
+    }
+
+    #[test]
+    fn stops_on_breakpoints_and_watchpoints() {
+        let mut gameboy = debug_gameboy();
+        gameboy.debugger.breakpoints.push(parse_breakpoint("0200 if a == 2").unwrap());
+        gameboy.debugger.breakpoints.push(parse_breakpoint("0105").unwrap());
+        gameboy.debugger.watchpoints.push(parse_watchpoint("c000 w").unwrap());
+
+        // the condition doesn't hold yet, a is 1
+        assert_eq!(run(&mut gameboy).to_string(), "hit breakpoint 0105");
+        assert_eq!(gameboy.r.pc, 0x105);
+        // ticking while stopped does nothing
+        let clock = gameboy.clock;
+        gameboy.tick();
+        assert_eq!(gameboy.clock, clock);
+
+        gameboy.resume();
+        assert_eq!(
+            run(&mut gameboy),
+            StopReason::Watchpoint {
+                access: Access::Write,
+                address: 0xC000,
+                value: 2
+            }
+        );
+    }
+
+    #[test]
+    fn steps() {
+        let mut gameboy = debug_gameboy();
+        gameboy.debugger.breakpoints.push(parse_breakpoint("0102").unwrap());
+        run(&mut gameboy);
+
+        gameboy.step_over();
+        run(&mut gameboy);
+        assert_eq!((gameboy.r.pc, gameboy.r.a), (0x105, 2));
+
+        gameboy.debugger.breakpoints[0].address = 0x200;
+        gameboy.debugger.last_opcode = 0;
+        gameboy.r.pc = 0x102;
+        gameboy.resume();
+        run(&mut gameboy);
+        gameboy.step_instruction();
+        run(&mut gameboy);
+        assert_eq!(gameboy.r.pc, 0x201);
+        gameboy.step_out();
+        assert_eq!(run(&mut gameboy), StopReason::Step(Step::Out { sp: 0xFFFC }));
+        assert_eq!(gameboy.r.pc, 0x105);
+
+        gameboy.r.lcdc |= 0x80;
+        gameboy.run_to_scanline(10);
+        run(&mut gameboy);
+        assert_eq!(gameboy.r.ly, 10);
+        gameboy.run_to_next_frame();
+        run(&mut gameboy);
+        assert_eq!(gameboy.r.ly, 144);
+    }
+
+    #[test]
+    fn steps_in_double_speed() {
+        // two instructions can start in one tick, it has to stop between them
+        let mut gameboy = debug_gameboy();
+        gameboy.cgb.double_speed = true;
+        let mut pcs = vec![];
+        for _ in 0..6 {
+            gameboy.step_instruction();
+            run(&mut gameboy);
+            pcs.push(gameboy.r.pc);
+        }
+        assert_eq!(pcs, [0x102, 0x200, 0x201, 0x105, 0x108, 0x108]);
+
+        gameboy.debugger.breakpoints.push(parse_breakpoint("0201").unwrap());
+        gameboy.r.pc = 0x102;
+        gameboy.resume();
+        assert_eq!(run(&mut gameboy).to_string(), "hit breakpoint 0201");
+        assert_eq!((gameboy.r.pc, gameboy.r.a), (0x201, 3));
+        // still stopped, on either half of the tick
+        let clock = gameboy.clock;
+        gameboy.tick();
+        gameboy.tick();
+        assert_eq!((gameboy.clock, gameboy.r.pc), (clock, 0x201));
+    }
+}
@@ -0,0 +1,303 @@
+// SM83 disassembler. Decodes the instruction at an address into its mnemonic, operands, length,
+// timing and branch target, and prints it in RGBDS syntax, e.g. `ld a, [hl+]` or `jr nz, $0150`.
+//
+// Opcodes are decoded from their bit fields, see https://gbdev.io/gb-opcodes/optables/octal
+use crate::gb::GameBoy;
+use std::fmt;
+
+const R: [&str; 8] = ["b", "c", "d", "e", "h", "l", "[hl]", "a"];
+const RP: [&str; 4] = ["bc", "de", "hl", "sp"];
+const RP2: [&str; 4] = ["bc", "de", "hl", "af"];
+const CC: [&str; 4] = ["nz", "z", "nc", "c"];
+const ALU: [&str; 8] = ["add", "adc", "sub", "sbc", "and", "xor", "or", "cp"];
+const ROT: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+const ACCUMULATOR_OPS: [&str; 8] = ["rlca", "rrca", "rla", "rra", "daa", "cpl", "scf", "ccf"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Operand {
+    // a, hl, sp, af...
+    Register(&'static str),
+    // nz, z, nc, c
+    Condition(&'static str),
+    // [hl], [bc], [hl+], [c]...
+    Indirect(&'static str),
+    Immediate8(u8),
+    Immediate16(u16),
+    // add sp, e8
+    Signed8(i8),
+    // [n16], and [$FF00 + n8] for ldh
+    Address(u16),
+    // jr, jp and call destinations
+    Target(u16),
+    // ld hl, sp + e8
+    SpOffset(i8),
+    // bit, res and set
+    Bit(u8),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Instruction {
+    pub address: u16,
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+    pub length: u8,
+    // M-cycles, with a branch taken for conditional instructions
+    pub cycles: u8,
+    // M-cycles when a conditional branch isn't taken
+    pub cycles_not_taken: Option<u8>,
+    // where a jump, call or rst goes, if it's known without running it
+    pub target: Option<u16>,
+}
+
+fn signed_hex(f: &mut fmt::Formatter<'_>, value: i8) -> fmt::Result {
+    match value < 0 {
+        true => write!(f, "-${:02X}", value.unsigned_abs()),
+        false => write!(f, "${value:02X}"),
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Register(name) | Operand::Condition(name) | Operand::Indirect(name) => write!(f, "{name}"),
+            Operand::Immediate8(value) => write!(f, "${value:02X}"),
+            Operand::Immediate16(value) | Operand::Target(value) => write!(f, "${value:04X}"),
+            Operand::Signed8(value) => signed_hex(f, *value),
+            Operand::Address(address) => write!(f, "[${address:04X}]"),
+            Operand::SpOffset(offset) => {
+                write!(f, "sp + ")?;
+                signed_hex(f, *offset)
+            }
+            Operand::Bit(bit) => write!(f, "{bit}"),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.mnemonic)?;
+        for (i, operand) in self.operands.iter().enumerate() {
+            write!(f, "{}{operand}", if i == 0 { " " } else { ", " })?;
+        }
+        Ok(())
+    }
+}
+
+fn r(index: u8) -> Operand {
+    match index {
+        6 => Operand::Indirect(R[6]),
+        _ => Operand::Register(R[index as usize]),
+    }
+}
+
+// Decodes the instruction at `address`, reading its bytes with `read`.
+pub fn decode(address: u16, mut read: impl FnMut(u16) -> u8) -> Instruction {
+    let opcode = read(address);
+    let n8 = read(address.wrapping_add(1));
+    let n16 = u16::from_le_bytes([n8, read(address.wrapping_add(2))]);
+    let relative = address.wrapping_add(2).wrapping_add(n8 as i8 as u16);
+
+    let mut instruction = Instruction {
+        address,
+        mnemonic: "",
+        operands: vec![],
+        length: 1,
+        cycles: 1,
+        cycles_not_taken: None,
+        target: None,
+    };
+    let mut set = |mnemonic, operands: Vec<Operand>, length, cycles| {
+        instruction.mnemonic = mnemonic;
+        instruction.operands = operands;
+        instruction.length = length;
+        instruction.cycles = cycles;
+    };
+
+    let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+    let (p, q) = ((y >> 1) as usize, y & 1);
+    // [hl] takes an extra M-cycle to read, and two for read-modify-write instructions
+    let hl_cycles = |register: u8, extra| if register == 6 { extra } else { 0 };
+    match (x, z) {
+        (0, 0) => match y {
+            0 => set("nop", vec![], 1, 1),
+            1 => set("ld", vec![Operand::Address(n16), Operand::Register("sp")], 3, 5),
+            // STOP is followed by a byte that's skipped
+            2 => set("stop", vec![], 2, 1),
+            3 => {
+                set("jr", vec![Operand::Target(relative)], 2, 3);
+                instruction.target = Some(relative);
+            }
+            _ => {
+                set("jr", vec![Operand::Condition(CC[y as usize - 4]), Operand::Target(relative)], 2, 3);
+                instruction.cycles_not_taken = Some(2);
+                instruction.target = Some(relative);
+            }
+        },
+        (0, 1) if q == 0 => set("ld", vec![Operand::Register(RP[p]), Operand::Immediate16(n16)], 3, 3),
+        (0, 1) => set("add", vec![Operand::Register("hl"), Operand::Register(RP[p])], 1, 2),
+        (0, 2) => {
+            let pointer = Operand::Indirect(["[bc]", "[de]", "[hl+]", "[hl-]"][p]);
+            let operands = match q {
+                0 => vec![pointer, Operand::Register("a")],
+                _ => vec![Operand::Register("a"), pointer],
+            };
+            set("ld", operands, 1, 2);
+        }
+        (0, 3) => set(["inc", "dec"][q as usize], vec![Operand::Register(RP[p])], 1, 2),
+        (0, 4) => set("inc", vec![r(y)], 1, 1 + hl_cycles(y, 2)),
+        (0, 5) => set("dec", vec![r(y)], 1, 1 + hl_cycles(y, 2)),
+        (0, 6) => set("ld", vec![r(y), Operand::Immediate8(n8)], 2, 2 + hl_cycles(y, 1)),
+        (0, 7) => set(ACCUMULATOR_OPS[y as usize], vec![], 1, 1),
+        (1, 6) if y == 6 => set("halt", vec![], 1, 1),
+        (1, _) => set("ld", vec![r(y), r(z)], 1, 1 + hl_cycles(y, 1) + hl_cycles(z, 1)),
+        (2, _) => set(ALU[y as usize], alu_operands(y, r(z)), 1, 1 + hl_cycles(z, 1)),
+        (3, 0) => match y {
+            0..=3 => {
+                set("ret", vec![Operand::Condition(CC[y as usize])], 1, 5);
+                instruction.cycles_not_taken = Some(2);
+            }
+            4 => set("ldh", vec![Operand::Address(0xFF00 | n8 as u16), Operand::Register("a")], 2, 3),
+            5 => set("add", vec![Operand::Register("sp"), Operand::Signed8(n8 as i8)], 2, 4),
+            6 => set("ldh", vec![Operand::Register("a"), Operand::Address(0xFF00 | n8 as u16)], 2, 3),
+            _ => set("ld", vec![Operand::Register("hl"), Operand::SpOffset(n8 as i8)], 2, 3),
+        },
+        (3, 1) if q == 0 => set("pop", vec![Operand::Register(RP2[p])], 1, 3),
+        (3, 1) => match p {
+            0 => set("ret", vec![], 1, 4),
+            1 => set("reti", vec![], 1, 4),
+            2 => set("jp", vec![Operand::Register("hl")], 1, 1),
+            _ => set("ld", vec![Operand::Register("sp"), Operand::Register("hl")], 1, 2),
+        },
+        (3, 2) => match y {
+            0..=3 => {
+                set("jp", vec![Operand::Condition(CC[y as usize]), Operand::Target(n16)], 3, 4);
+                instruction.cycles_not_taken = Some(3);
+                instruction.target = Some(n16);
+            }
+            4 => set("ldh", vec![Operand::Indirect("[c]"), Operand::Register("a")], 1, 2),
+            5 => set("ld", vec![Operand::Address(n16), Operand::Register("a")], 3, 4),
+            6 => set("ldh", vec![Operand::Register("a"), Operand::Indirect("[c]")], 1, 2),
+            _ => set("ld", vec![Operand::Register("a"), Operand::Address(n16)], 3, 4),
+        },
+        (3, 3) => match y {
+            0 => {
+                set("jp", vec![Operand::Target(n16)], 3, 4);
+                instruction.target = Some(n16);
+            }
+            1 => return decode_cb(address, n8),
+            6 => set("di", vec![], 1, 1),
+            7 => set("ei", vec![], 1, 1),
+            _ => set("db", vec![Operand::Immediate8(opcode)], 1, 1),
+        },
+        (3, 4) if y < 4 => {
+            set("call", vec![Operand::Condition(CC[y as usize]), Operand::Target(n16)], 3, 6);
+            instruction.cycles_not_taken = Some(3);
+            instruction.target = Some(n16);
+        }
+        (3, 5) if q == 0 => set("push", vec![Operand::Register(RP2[p])], 1, 4),
+        (3, 5) if p == 0 => {
+            set("call", vec![Operand::Target(n16)], 3, 6);
+            instruction.target = Some(n16);
+        }
+        (3, 6) => set(ALU[y as usize], alu_operands(y, Operand::Immediate8(n8)), 2, 2),
+        (3, 7) => {
+            set("rst", vec![Operand::Immediate8(y * 8)], 1, 4);
+            instruction.target = Some(y as u16 * 8);
+        }
+        // the opcodes that lock up the CPU
+        _ => set("db", vec![Operand::Immediate8(opcode)], 1, 1),
+    }
+    instruction
+}
+
+// RGBDS spells out the accumulator for add, adc and sbc, but not for the others
+fn alu_operands(operation: u8, operand: Operand) -> Vec<Operand> {
+    match ALU[operation as usize] {
+        "add" | "adc" | "sbc" => vec![Operand::Register("a"), operand],
+        _ => vec![operand],
+    }
+}
+
+fn decode_cb(address: u16, opcode: u8) -> Instruction {
+    let (x, y, z) = (opcode >> 6, (opcode >> 3) & 7, opcode & 7);
+    let (mnemonic, operands) = match x {
+        0 => (ROT[y as usize], vec![r(z)]),
+        1 => ("bit", vec![Operand::Bit(y), r(z)]),
+        2 => ("res", vec![Operand::Bit(y), r(z)]),
+        _ => ("set", vec![Operand::Bit(y), r(z)]),
+    };
+    let cycles = match (z, x) {
+        (6, 1) => 3,
+        (6, _) => 4,
+        _ => 2,
+    };
+    Instruction {
+        address,
+        mnemonic,
+        operands,
+        length: 2,
+        cycles,
+        cycles_not_taken: None,
+        target: None,
+    }
+}
+
+impl GameBoy {
+    // The instruction at an address, as the CPU currently sees memory.
+    pub fn disassemble(&self, address: u16) -> Instruction {
+        decode(address, |address| self.peek(address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_bytes(address: u16, bytes: &[u8]) -> Instruction {
+        decode(address, |a| bytes.get(a.wrapping_sub(address) as usize).copied().unwrap_or(0))
+    }
+
+    fn text(address: u16, bytes: &[u8]) -> String {
+        decode_bytes(address, bytes).to_string()
+    }
+
+    #[test]
+    fn rgbds_syntax() {
+        assert_eq!(text(0, &[0x00]), "nop");
+        assert_eq!(text(0, &[0x2A]), "ld a, [hl+]");
+        assert_eq!(text(0, &[0x36, 0x12]), "ld [hl], $12");
+        assert_eq!(text(0, &[0x08, 0x34, 0x12]), "ld [$1234], sp");
+        assert_eq!(text(0, &[0xE0, 0x80]), "ldh [$FF80], a");
+        assert_eq!(text(0, &[0xF2]), "ldh a, [c]");
+        assert_eq!(text(0, &[0xF8, 0xFE]), "ld hl, sp + -$02");
+        assert_eq!(text(0, &[0xE8, 0x05]), "add sp, $05");
+        assert_eq!(text(0, &[0x86]), "add a, [hl]");
+        assert_eq!(text(0, &[0xAF]), "xor a");
+        assert_eq!(text(0, &[0xFE, 0x90]), "cp $90");
+        assert_eq!(text(0, &[0xF5]), "push af");
+        assert_eq!(text(0, &[0xFF]), "rst $38");
+        assert_eq!(text(0, &[0xD3]), "db $D3");
+        assert_eq!(text(0, &[0xCB, 0x7E]), "bit 7, [hl]");
+        assert_eq!(text(0, &[0xCB, 0x37]), "swap a");
+    }
+
+    #[test]
+    fn lengths_timing_and_targets() {
+        let jr = decode_bytes(0x150, &[0x20, 0xFB]);
+        assert_eq!(jr.to_string(), "jr nz, $014D");
+        assert_eq!((jr.length, jr.cycles, jr.cycles_not_taken, jr.target), (2, 3, Some(2), Some(0x14D)));
+
+        let call = decode_bytes(0, &[0xCD, 0x00, 0x40]);
+        assert_eq!((call.length, call.cycles, call.target), (3, 6, Some(0x4000)));
+
+        let set = decode_bytes(0, &[0xCB, 0xC6]);
+        assert_eq!((set.length, set.cycles), (2, 4));
+
+        // every opcode decodes to something the right length
+        for opcode in 0..=0xFFu8 {
+            let instruction = decode_bytes(0, &[opcode]);
+            assert!(!instruction.mnemonic.is_empty(), "{opcode:02X}");
+            assert!((1..=3).contains(&instruction.length), "{opcode:02X}");
+        }
+    }
+}
@@ -0,0 +1,115 @@
+// OAM DMA: a write to FF46 copies 160 bytes from XX00 to OAM, one byte per M-cycle, starting the
+// M-cycle after the next one. While it runs the DMA owns the external and VRAM buses, so the CPU can
+// only reach the IO registers and HRAM. That's why games copy their DMA routine to HRAM and wait
+// there.
+use crate::gb::GameBoy;
+use serde::{Deserialize, Serialize};
+
+const OAM_SIZE: u8 = 160;
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct OamDma {
+    pub source: u16,
+    // next byte to copy while a transfer is running
+    pub position: Option<u8>,
+    // transfer waiting to start, and M-cycles until it does. A transfer that is already running
+    // carries on until then.
+    pending: Option<(u16, u8)>,
+}
+
+impl OamDma {
+    pub fn active(&self) -> bool {
+        self.position.is_some()
+    }
+}
+
+impl GameBoy {
+    pub(crate) fn start_oam_dma(&mut self, data: u8) {
+        // there's no RAM past 0xE000 to copy from, the DMA sees work RAM there instead
+        let page = if data >= 0xE0 { data - 0x20 } else { data };
+        self.dma.pending = Some(((page as u16) << 8, 1));
+    }
+
+    // One M-cycle of OAM DMA.
+    pub(crate) fn step_oam_dma(&mut self) {
+        match self.dma.pending {
+            Some((source, 0)) => {
+                self.dma.source = source;
+                self.dma.position = Some(0);
+                self.dma.pending = None;
+            }
+            Some((source, delay)) => self.dma.pending = Some((source, delay - 1)),
+            None => {}
+        }
+
+        let Some(position) = self.dma.position else { return };
+        let byte = self.bus_read(self.dma.source + position as u16);
+        self.memory.main[0xFE00 + position as usize] = byte;
+        self.dma.position = (position + 1 < OAM_SIZE).then_some(position + 1);
+    }
+
+    // Whether the CPU is locked out of this address by a running DMA.
+    pub(crate) fn oam_dma_blocks(&self, address: u16) -> bool {
+        self.dma.active() && address < 0xFF00
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gb;
+
+    fn dma_gameboy() -> gb::GameBoy {
+        let mut gameboy = gb::init();
+        for i in 0..160 {
+            gameboy.memory.main[0xC000 + i] = i as u8;
+        }
+        gameboy.memory.main[0xFF80] = 0x42;
+        gameboy
+    }
+
+    fn step(gameboy: &mut gb::GameBoy, cycles: u32) {
+        for _ in 0..cycles {
+            gameboy.step_oam_dma();
+        }
+    }
+
+    #[test]
+    fn copies_one_byte_per_cycle() {
+        let mut gameboy = dma_gameboy();
+        gameboy.write(0xFF46, 0xC0);
+        step(&mut gameboy, 1);
+        assert!(!gameboy.dma.active());
+        assert_eq!(gameboy.read(0xC000), 0x00);
+
+        step(&mut gameboy, 10);
+        assert_eq!(gameboy.memory.main[0xFE09], 9);
+        assert_eq!(gameboy.memory.main[0xFE0A], 0);
+        // only HRAM and IO are reachable, everything else reads 0xFF and ignores writes
+        assert_eq!(gameboy.read(0xFE00), 0xFF);
+        assert_eq!(gameboy.read(0xC001), 0xFF);
+        assert_eq!(gameboy.read(0xFF80), 0x42);
+        gameboy.write(0xC001, 0x99);
+        assert_eq!(gameboy.memory.main[0xC001], 1);
+
+        step(&mut gameboy, 150);
+        assert!(!gameboy.dma.active());
+        assert_eq!(gameboy.read(0xFE9F), 159);
+    }
+
+    #[test]
+    fn restarting_starts_over_from_the_new_source() {
+        let mut gameboy = dma_gameboy();
+        gameboy.memory.main[0xD000..0xD0A0].fill(0xAB);
+        gameboy.write(0xFF46, 0xC0);
+        step(&mut gameboy, 51);
+        gameboy.write(0xFF46, 0xD0);
+        // the first transfer carries on until the new one starts
+        step(&mut gameboy, 1);
+        assert_eq!(gameboy.memory.main[0xFE32], 50);
+        assert!(gameboy.dma.active());
+
+        step(&mut gameboy, 160);
+        assert!(!gameboy.dma.active());
+        assert!(gameboy.memory.main[0xFE00..0xFEA0].iter().all(|&byte| byte == 0xAB));
+    }
+}
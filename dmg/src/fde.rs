@@ -4,12 +4,10 @@ impl GameBoy {
     pub fn fetch_decode_execute(&mut self, opcode: u8) -> Option<u8> {
         match opcode {
             0x00 => {
-                self.logger.log_disassembly("NOP");
                 Some(1)
             }
             0x07 => {
                 // RLCA
-                self.logger.log_disassembly("RLCA");
                 let ms_bit = self.r.a & 0x80;
                 self.r.a = (self.r.a << 1) | (ms_bit >> 7);
                 self.set_flag_c(ms_bit != 0);
@@ -21,7 +19,6 @@ impl GameBoy {
 
             0x0F => {
                 // RRCA
-                self.logger.log_disassembly("RRCA");
                 let ls_bit = self.r.a & 1;
                 self.r.a = (self.r.a >> 1) | (ls_bit << 7);
                 self.set_flag_c(ls_bit != 0);
@@ -32,7 +29,6 @@ impl GameBoy {
             }
             0x17 => {
                 // RLA
-                self.logger.log_disassembly("RLA");
                 let msb = self.r.a & 0x80;
                 self.r.a = (self.r.a << 1) | self.get_flag_c();
                 self.set_flag_c(msb != 0);
@@ -43,7 +39,6 @@ impl GameBoy {
             }
             0x1F => {
                 // RRA
-                self.logger.log_disassembly("RRA");
                 let lsb = self.r.a & 1;
                 self.r.a = (self.r.a >> 1) | (self.get_flag_c() << 7);
                 self.set_flag_c(lsb != 0);
@@ -54,7 +49,6 @@ impl GameBoy {
             }
             0x27 => {
                 // DAA
-                self.logger.log_disassembly("DAA");
                 if self.get_flag_n() != 0 {
                     let mut adjustment = 0;
                     if self.get_flag_h() != 0 {
@@ -81,7 +75,6 @@ impl GameBoy {
             }
             0x2F => {
                 // CPL
-                self.logger.log_disassembly("CPL");
                 self.r.a = !self.r.a;
                 self.set_flag_n(true);
                 self.set_flag_h(true);
@@ -89,7 +82,6 @@ impl GameBoy {
             }
             0x37 => {
                 // SCF
-                self.logger.log_disassembly("SCF");
                 self.set_flag_n(false);
                 self.set_flag_h(false);
                 self.set_flag_c(true);
@@ -97,7 +89,6 @@ impl GameBoy {
             }
             0x3F => {
                 // CCF
-                self.logger.log_disassembly("CCF");
                 self.set_flag_n(false);
                 self.set_flag_h(false);
                 self.set_flag_c(self.get_flag_c() == 0);
@@ -105,7 +96,6 @@ impl GameBoy {
             }
 
             0x08 => {
-                self.logger.log_disassembly("LD (u16), SP");
                 let nn_lsb: u8 = self.read(self.r.pc);
                 self.r.pc += 1;
                 let nn_msb: u8 = self.read(self.r.pc);
@@ -117,19 +107,18 @@ impl GameBoy {
                 Some(5)
             }
             0x10 => {
-                self.logger.log_disassembly("STOP");
-                self.state = State::Halted;
+                if !self.try_speed_switch() {
+                    self.state = State::Halted;
+                }
                 Some(1)
             }
             0x18 => {
-                self.logger.log_disassembly("JR i8");
                 let e = self.read(self.r.pc) as i8;
                 self.r.pc += 1;
                 self.r.pc = (self.r.pc as i16 + e as i16) as u16;
                 Some(3)
             }
             0x76 => {
-                self.logger.log_disassembly("HALT");
                 self.state = State::Halted;
                 None
             }
@@ -143,7 +132,6 @@ impl GameBoy {
                         match (cb_opcode >> 3) & 0b111 {
                             0 => {
                                 // RLC
-                                self.logger.log_disassembly("RLC");
                                 self.r.pc += 1;
                                 let ms_bit = self.get_r8(r8) & 0x80;
                                 self.set_flag_c(ms_bit != 0);
@@ -161,7 +149,6 @@ impl GameBoy {
                             }
                             1 => {
                                 // RRC
-                                self.logger.log_disassembly("RRC");
                                 self.r.pc += 1;
                                 let ls_bit = self.get_r8(r8) & 0x01;
                                 self.set_flag_c(ls_bit != 0);
@@ -179,7 +166,6 @@ impl GameBoy {
                             }
                             2 => {
                                 // RL
-                                self.logger.log_disassembly("RL");
                                 self.r.pc += 1;
                                 let ms_bit = self.get_r8(r8) & 0x80;
                                 let r8_val_1 = self.get_r8(r8);
@@ -197,7 +183,6 @@ impl GameBoy {
                             }
                             3 => {
                                 // RR
-                                self.logger.log_disassembly("RR");
                                 self.r.pc += 1;
                                 let ls_bit = self.get_r8(r8) & 0x01;
                                 let r8_val_1 = self.get_r8(r8);
@@ -211,7 +196,6 @@ impl GameBoy {
                             }
                             4 => {
                                 // SLA
-                                self.logger.log_disassembly("SLA");
                                 self.r.pc += 1;
                                 let ms_bit = self.get_r8(r8) & 0x80;
                                 let r8_val_1 = self.get_r8(r8);
@@ -229,7 +213,6 @@ impl GameBoy {
                             }
                             5 => {
                                 // SRA
-                                self.logger.log_disassembly("SRA");
                                 self.r.pc += 1;
                                 let ms_bit = self.get_r8(r8) & 0x80;
                                 let ls_bit = self.get_r8(r8) & 0x01;
@@ -244,7 +227,6 @@ impl GameBoy {
                             }
                             6 => {
                                 // SWAP
-                                self.logger.log_disassembly("SWAP");
                                 self.r.pc += 1;
                                 let r8 = cb_opcode & 0b111;
                                 let r8_value = self.get_r8(r8);
@@ -264,7 +246,6 @@ impl GameBoy {
                             }
                             7 => {
                                 // SRL
-                                self.logger.log_disassembly("SRL");
                                 self.r.pc += 1;
                                 let r8 = cb_opcode & 0b111;
                                 let ls_bit = self.get_r8(r8) & 0x01;
@@ -285,7 +266,6 @@ impl GameBoy {
                         }
                     }
                     0b01 => {
-                        self.logger.log_disassembly("BIT");
                         self.r.pc += 1;
                         let r8_val = self.get_r8(r8);
                         self.set_flag_z((r8_val & (1 << bit)) == 0);
@@ -298,14 +278,12 @@ impl GameBoy {
                         }
                     }
                     0b10 => {
-                        self.logger.log_disassembly("RES");
                         self.r.pc += 1;
                         let r8_val = self.get_r8(r8);
                         self.set_r8(r8, r8_val & !(1 << bit));
                         Some(4)
                     }
                     0b11 => {
-                        self.logger.log_disassembly("SET");
                         self.r.pc += 1;
                         let r8_val = self.get_r8(r8);
                         self.set_r8(r8, r8_val | (1 << bit));
@@ -315,7 +293,6 @@ impl GameBoy {
                 }
             }
             0xE0 => {
-                self.logger.log_disassembly("LDH (u8), A");
                 let n = self.read(self.r.pc);
                 self.r.pc += 1;
                 if n == 0x50 {
@@ -326,7 +303,6 @@ impl GameBoy {
             }
             0xE8 => {
                 // ADD SP i8
-                self.logger.log_disassembly("ADD SP, i8");
                 let e: i8 = self.read(self.r.pc) as i8;
                 self.r.pc += 1;
                 self.set_flag_z(false);
@@ -339,7 +315,6 @@ impl GameBoy {
             }
             0xEA => {
                 // LD (u16), A
-                self.logger.log_disassembly("LD (u16), A");
                 let lsb = self.read(self.r.pc);
                 self.r.pc += 1;
                 let msb = self.read(self.r.pc);
@@ -352,20 +327,17 @@ impl GameBoy {
             }
             0xF0 => {
                 // LD A FF00 + u8
-                self.logger.log_disassembly("LDH A, (u8)");
                 let lsb = self.read(self.r.pc);
                 self.r.pc += 1;
                 self.r.a = self.read(unsigned_16(0xFF, lsb));
                 Some(3)
             }
             0xF2 => {
-                self.logger.log_disassembly("LDH A, C");
                 self.r.a = self.read(unsigned_16(0xFF, self.r.c));
                 Some(2)
             }
             0xF8 => {
                 // LD HL SP + i8
-                self.logger.log_disassembly("LD HL, SP+i8");
                 let e: i8 = self.read(self.r.pc) as i8;
                 self.r.pc += 1;
                 self.set_flag_z(false);
@@ -378,19 +350,16 @@ impl GameBoy {
                 Some(3)
             }
             0xF9 => {
-                self.logger.log_disassembly("LD SP, HL");
                 self.r.sp = self.get_hl();
                 Some(2)
             }
             0xE2 => {
-                self.logger.log_disassembly("LDH (C), A");
 
                 self.write(unsigned_16(0xFF, self.r.c), self.r.a);
 
                 Some(2)
             }
             0xFA => {
-                self.logger.log_disassembly("LD A, (u16)");
                 let lsb = self.read(self.r.pc);
                 self.r.pc += 1;
                 let msb = self.read(self.r.pc);
@@ -400,7 +369,6 @@ impl GameBoy {
             }
             0xCD => {
                 // CALL u16
-                self.logger.log_disassembly("CALL u16");
                 let ls_byte = self.read(self.r.pc);
                 self.r.pc += 1;
                 let ms_byte = self.read(self.r.pc);
@@ -415,7 +383,6 @@ impl GameBoy {
 
             0xC9 => {
                 // RET
-                self.logger.log_disassembly("RET");
                 let lsb = self.read(self.r.sp);
                 self.r.sp += 1;
                 let msb = self.read(self.r.sp);
@@ -426,7 +393,6 @@ impl GameBoy {
             0xD9 => {
                 // RETI
                 self.ime = true; // "it works as ei ret, so the ret eats whatever cycle delay ei had"
-                self.logger.log_disassembly("RETI");
                 let lsb = self.read(self.r.sp);
                 self.r.sp += 1;
                 let msb = self.read(self.r.sp);
@@ -436,14 +402,12 @@ impl GameBoy {
             }
             0xE9 => {
                 // JP HL
-                self.logger.log_disassembly("JP HL");
                 self.r.pc = self.get_hl();
                 Some(1)
             }
 
             0xC6 => {
                 // ADD
-                self.logger.log_disassembly("ADD u8");
                 let left: u8 = self.r.a;
                 let right: u8 = self.read(self.r.pc);
                 self.r.pc += 1;
@@ -456,7 +420,6 @@ impl GameBoy {
             }
             0xCE => {
                 // ADC
-                self.logger.log_disassembly("ADC u8");
                 let c_save: u8 = self.get_flag_c();
                 let left: u8 = self.r.a;
                 let right: u8 = self.read(self.r.pc);
@@ -470,7 +433,6 @@ impl GameBoy {
             }
             0xD6 => {
                 // SUB
-                self.logger.log_disassembly("SUB u8");
                 let left: u8 = self.r.a;
                 let right: u8 = self.read(self.r.pc);
                 self.r.pc += 1;
@@ -483,7 +445,6 @@ impl GameBoy {
             }
             0xDE => {
                 // SBC
-                self.logger.log_disassembly("SBC u8");
                 let c_save: u8 = self.get_flag_c();
                 let left: u8 = self.r.a;
                 let right: u8 = self.read(self.r.pc);
@@ -497,7 +458,6 @@ impl GameBoy {
             }
             0xE6 => {
                 // AND
-                self.logger.log_disassembly("AND u8");
                 self.r.a &= self.read(self.r.pc);
                 self.r.pc += 1;
                 self.set_flag_z(self.r.a == 0);
@@ -508,7 +468,6 @@ impl GameBoy {
             }
             0xEE => {
                 // XOR
-                self.logger.log_disassembly("XOR u8");
                 self.r.a ^= self.read(self.r.pc);
                 self.r.pc += 1;
                 self.set_flag_z(self.r.a == 0);
@@ -519,7 +478,6 @@ impl GameBoy {
             }
             0xF6 => {
                 // Or
-                self.logger.log_disassembly("OR u8");
                 self.r.a |= self.read(self.r.pc);
                 self.r.pc += 1;
                 self.set_flag_z(self.r.a == 0);
@@ -530,7 +488,6 @@ impl GameBoy {
             }
             0xFE => {
                 // CP
-                self.logger.log_disassembly("CP u8");
                 let left: u8 = self.r.a;
                 let right: u8 = self.read(self.r.pc);
                 self.r.pc += 1;
@@ -542,14 +499,12 @@ impl GameBoy {
                 Some(2)
             }
             0xF3 => {
-                self.logger.log_disassembly("DI");
                 self.ime_dispatch = None;
                 self.ime = false;
                 Some(1)
             }
 
             0xFB => {
-                self.logger.log_disassembly("EI");
                 self.ime_dispatch = Some(2);
                 self.logger.log_info(&format!("EI hit: dispatch: {:?}", self.ime_dispatch));
                 Some(1)
@@ -559,7 +514,6 @@ impl GameBoy {
                 let r16 = (opcode >> 4) & 0b11;
                 if (opcode & 0b11_00_1111) == 0b00_00_0001 {
                     // LD r16 u16
-                    self.logger.log_disassembly("LD r16, u16");
                     let lsb = self.read(self.r.pc);
                     self.r.pc += 1;
                     let msb = self.read(self.r.pc);
@@ -568,19 +522,16 @@ impl GameBoy {
                     return Some(3);
                 } else if (opcode & 0b11_00_1111) == 0b00_00_0011 {
                     // INC r16
-                    self.logger.log_disassembly("INC r16");
                     let r16_value = self.get_r16_group_1(r16);
                     self.set_r16_group_1(r16, r16_value + 1);
                     return Some(2);
                 } else if (opcode & 0b11_00_1111) == 0b00_00_1011 {
                     // DEC r16
-                    self.logger.log_disassembly("DEC r16");
                     let r16_value = self.get_r16_group_1(r16);
                     self.set_r16_group_1(r16, r16_value - 1);
                     return Some(2);
                 } else if (opcode & 0b11_00_1111) == 0b00_00_1001 {
                     // Add HL r16
-                    self.logger.log_disassembly("ADD HL, r16");
                     let left = self.get_hl();
                     let right = self.get_r16_group_1(r16);
                     let result = left + right;
@@ -591,13 +542,11 @@ impl GameBoy {
                     return Some(2);
                 } else if (opcode & 0b11_00_1111) == 0b00_00_0010 {
                     // LD (r16), A
-                    self.logger.log_disassembly("LD (r16), A");
                     let r16_value = self.get_r16_group_2(r16);
                     self.write(r16_value, self.r.a);
                     return Some(2);
                 } else if (opcode & 0b11_00_1111) == 0b00_00_1010 {
                     // LD A, (r16)
-                    self.logger.log_disassembly("LD A, (r16)");
                     let r16_value: u16 = self.get_r16_group_2(r16);
                     self.r.a = self.read(r16_value);
                     return Some(2);
@@ -605,7 +554,6 @@ impl GameBoy {
 
                 if (opcode & 0b11_000_111) == 0b00_000_110 {
                     // LD r8, u8
-                    self.logger.log_disassembly("LD r8, u8");
                     let r8 = opcode >> 3;
                     let pc = self.read(self.r.pc);
                     self.set_r8(r8, pc);
@@ -613,7 +561,6 @@ impl GameBoy {
                     return Some(3);
                 } else if (opcode & 0b11_000_111) == 0b00_000_100 {
                     // INC r8
-                    self.logger.log_disassembly("INC r8");
                     let r8 = opcode >> 3;
                     let r8_old = self.get_r8(r8);
                     let result = self.get_r8(r8) + 1;
@@ -624,7 +571,6 @@ impl GameBoy {
                     return if r8 == 6 { Some(3) } else { Some(1) };
                 } else if (opcode & 0b11_000_111) == 0b00_000_101 {
                     // DEC r8
-                    self.logger.log_disassembly("DEC r8");
                     let r8 = opcode >> 3;
                     let r8_old = self.get_r8(r8);
                     let result = self.get_r8(r8) - 1;
@@ -637,7 +583,6 @@ impl GameBoy {
 
                 if (opcode >> 5) == 0b001 {
                     // JR conditional
-                    self.logger.log_disassembly("JR conditional");
                     let condition;
                     match (opcode >> 3) & 0b11 {
                         0 => condition = self.get_flag_z() == 0,
@@ -657,7 +602,6 @@ impl GameBoy {
                 }
 
                 if (opcode >> 6) == 0b01 {
-                    self.logger.log_disassembly("LD r8, r8");
                     let r8_source: u8 = opcode & 0b111;
                     let r8_dest: u8 = (opcode >> 3) & 0b111;
                     let r8_source_val = self.get_r8(r8_source);
@@ -668,7 +612,6 @@ impl GameBoy {
                 if (opcode >> 6) == 0b10 {
                     if (opcode >> 3) & 0b111 == 0 {
                         // ADD
-                        self.logger.log_disassembly("ADD r8");
                         let r8: u8 = opcode & 0b111;
                         let left: u8 = self.r.a;
                         let right: u8 = self.get_r8(r8);
@@ -680,7 +623,6 @@ impl GameBoy {
                         return if r8 == 6 { Some(2) } else { Some(1) };
                     } else if (opcode >> 3) & 0b111 == 1 {
                         // ADC
-                        self.logger.log_disassembly("ADC r8");
                         let r8: u8 = opcode & 0b111;
                         let c_save: u8 = self.get_flag_c();
                         let left: u8 = self.r.a;
@@ -693,7 +635,6 @@ impl GameBoy {
                         return if r8 == 6 { Some(2) } else { Some(1) };
                     } else if (opcode >> 3) & 0b111 == 2 {
                         // SUB A, r8
-                        self.logger.log_disassembly("SUB r8");
                         let r8: u8 = opcode & 0b111;
                         let left: u8 = self.r.a;
                         let right: u8 = self.get_r8(r8);
@@ -705,7 +646,6 @@ impl GameBoy {
                         return if r8 == 6 { Some(2) } else { Some(1) };
                     } else if (opcode >> 3) & 0b111 == 3 {
                         // SBC
-                        self.logger.log_disassembly("SBC r8");
                         let r8: u8 = opcode & 0b111;
                         let c_save: u8 = self.get_flag_c();
                         let left: u8 = self.r.a;
@@ -718,7 +658,6 @@ impl GameBoy {
                         return Some(2);
                     } else if (opcode >> 3) & 0b111 == 4 {
                         // AND r8
-                        self.logger.log_disassembly("AND r8");
                         self.r.a &= self.get_r8(opcode & 0b111);
                         self.set_flag_z(self.r.a == 0);
                         self.set_flag_n(false);
@@ -730,7 +669,6 @@ impl GameBoy {
                             Some(1)
                         };
                     } else if (opcode >> 3) & 0b111 == 5 {
-                        self.logger.log_disassembly("XOR r8");
                         self.r.a ^= self.get_r8(opcode & 0b111);
                         self.set_flag_z(self.r.a == 0);
                         self.set_flag_n(false);
@@ -743,7 +681,6 @@ impl GameBoy {
                         };
                     } else if (opcode >> 3) & 0b111 == 6 {
                         // OR r
-                        self.logger.log_disassembly("OR r8");
                         self.r.a |= self.get_r8(opcode & 0b111);
                         self.set_flag_z(self.r.a == 0);
                         self.set_flag_n(false);
@@ -755,7 +692,6 @@ impl GameBoy {
                             Some(1)
                         };
                     } else if (opcode >> 3) & 0b111 == 7 {
-                        self.logger.log_disassembly("CP r8");
                         let r8: u8 = opcode & 0b111;
                         let left: u8 = self.r.a;
                         let right: u8 = self.get_r8(r8);
@@ -772,7 +708,6 @@ impl GameBoy {
                     match opcode & 0b1111 {
                         0b0001 => {
                             // POP r16
-                            self.logger.log_disassembly("POP r16");
                             let r16 = (opcode >> 4) & 0b11;
                             let mask;
                             if r16 == 3 {
@@ -789,7 +724,6 @@ impl GameBoy {
                         }
                         0b0101 => {
                             // PUSH r16
-                            self.logger.log_disassembly("PUSH r16");
                             let r16 = (opcode >> 4) & 0b11;
                             let r16_value = self.get_r16_group_3(r16);
                             let mask;
@@ -810,7 +744,6 @@ impl GameBoy {
 
                 if (opcode & 0b111_00_111) == 0b110_00_000 {
                     // RET conditional
-                    self.logger.log_disassembly("RET conditional");
                     let condition;
                     match (opcode >> 3) & 0b11 {
                         0 => condition = self.get_flag_z() == 0,
@@ -830,7 +763,6 @@ impl GameBoy {
                         Some(2)
                     };
                 } else if (opcode & 0b111_00_111) == 0b110_00_100 {
-                    self.logger.log_disassembly("CALL conditional");
                     let ls_byte = self.read(self.r.pc);
                     self.r.pc += 1;
                     let ms_byte = self.read(self.r.pc);
@@ -858,7 +790,6 @@ impl GameBoy {
                 }
 
                 if (opcode & 0b111_00_111) == 0b110_00_010 {
-                    self.logger.log_disassembly("JP conditional");
                     let condition;
                     match (opcode >> 3) & 0b11 {
                         // JP conditional
@@ -881,7 +812,6 @@ impl GameBoy {
                     };
                 } else if (opcode & 0b11_000_111) == 0b11_000_111 {
                     // RST
-                    self.logger.log_disassembly("RST");
                     let exp = opcode & 0b00_111_000;
                     self.r.sp -= 1;
                     self.write(self.r.sp, msb(self.r.pc));
@@ -893,7 +823,6 @@ impl GameBoy {
 
                 if (opcode & 0b11_000_111) == 0b11_000_011 {
                     if (opcode >> 3) & 0b111 == 0 {
-                        self.logger.log_disassembly("JP unconditional");
                         let lsb = self.read(self.r.pc);
                         self.r.pc += 1;
                         let msb = self.read(self.r.pc);
@@ -0,0 +1,408 @@
+// Dot accurate PPU: the background fetcher and pixel FIFOs, run one dot at a time, so that mode 3
+// gets longer with SCX, the window and sprites, and registers written in the middle of a line take
+// effect from the next pixel. Slower than the scanline renderer, select it with
+// GameBoy::renderer_kind.
+use crate::gb::{GameBoy, InterruptType, Sprite};
+use crate::renderer::LCDStatus;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+const DOTS_PER_LINE: u16 = 456;
+const OAM_SCAN_DOTS: u16 = 80;
+// dots the PPU stalls for once the BG fetcher is done, while it fetches a sprite's tile
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum RendererKind {
+    // the whole line at once, at a fixed point in the line
+    #[default]
+    Scanline,
+    Fifo,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct BgPixel {
+    colour: u8,
+    // CGB BG map attributes, 0 on a DMG
+    attributes: u8,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct ObjPixel {
+    colour: u8,
+    sprite: Sprite,
+    oam_index: u8,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Fetcher {
+    // dots into the current tile fetch, a push is attempted from dot 7 on
+    dot: u8,
+    // tile column, counted from where the background or window started on this line
+    x: u8,
+    window: bool,
+    tile_num: u8,
+    attributes: u8,
+    low: u8,
+    high: u8,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Fifo {
+    dot: u16,
+    // mode 3 in progress
+    drawing: bool,
+    lcd_x: u8,
+    // pixels still to be thrown away at the start of the line (SCX % 8) or window (WX < 7)
+    discard: u8,
+    fetcher: Fetcher,
+    bg_fifo: VecDeque<BgPixel>,
+    obj_fifo: VecDeque<ObjPixel>,
+    // sprites on this line in OAM order, with their OAM index and whether they've been fetched
+    sprites: Vec<(Sprite, u8, bool)>,
+    // sprite being fetched, and dots left once the BG fetcher is done
+    sprite_fetch: Option<(usize, u8)>,
+    // the first tile of a line is fetched twice, the first one is thrown away
+    startup_dots: u8,
+    // WY matched LY at some point this frame
+    window_y_triggered: bool,
+    window_on_line: bool,
+}
+
+impl GameBoy {
+    pub(crate) fn fifo_renderer(&mut self) {
+        for _ in 0..4 {
+            let old_stat = self.r.stat;
+            self.fifo_dot();
+            self.request_stat_interrupts(old_stat);
+        }
+    }
+
+    // LCDC bit 7 cleared: the PPU stops and resets to the start of line 0.
+    pub(crate) fn fifo_lcd_off(&mut self) {
+        self.fifo = Default::default();
+        self.r.ly = 0;
+        self.window_line_counter = 0;
+        self.update_stat(LCDStatus::PPUModeHBlank);
+    }
+
+    fn fifo_dot(&mut self) {
+        let dot = self.fifo.dot;
+        if self.r.ly < 144 {
+            if dot == 0 {
+                if self.r.ly == self.r.wy {
+                    self.fifo.window_y_triggered = true;
+                }
+                self.update_stat(LCDStatus::PPUModeOAMScan);
+            } else if dot == OAM_SCAN_DOTS {
+                self.fifo_start_line();
+                self.update_stat(LCDStatus::PPUModeDrawing);
+            } else if self.fifo.drawing {
+                self.fifo_pixel_pipeline();
+                if self.fifo.lcd_x == 160 {
+                    self.fifo.drawing = false;
+                    self.update_stat(LCDStatus::PPUModeHBlank);
+                    self.hblank_dma();
+                }
+            }
+        }
+
+        self.fifo.dot += 1;
+        if self.fifo.dot < DOTS_PER_LINE {
+            return;
+        }
+        self.fifo.dot = 0;
+        if self.fifo.window_on_line {
+            self.window_line_counter += 1;
+            self.fifo.window_on_line = false;
+        }
+        self.r.ly += 1;
+        if self.r.ly == 144 {
+            self.update_stat(LCDStatus::PPUModeVBlank);
+            self.request_interrupt(InterruptType::VBlank);
+            if self.sgb.enabled {
+                self.sgb_vblank();
+            }
+        } else if self.r.ly == 154 {
+            self.end_frame();
+            self.r.ly = 0;
+            self.window_line_counter = 0;
+            self.fifo.window_y_triggered = false;
+        }
+    }
+
+    // End of OAM scan: pick this line's sprites and reset the fetcher.
+    fn fifo_start_line(&mut self) {
+        let sprites = self.scan_oam().into_iter().map(|(sprite, index)| (sprite, index, false)).collect();
+        let fifo = &mut self.fifo;
+        fifo.sprites = sprites;
+        fifo.drawing = true;
+        fifo.lcd_x = 0;
+        fifo.discard = self.r.scx & 7;
+        fifo.fetcher = Default::default();
+        fifo.bg_fifo.clear();
+        fifo.obj_fifo.clear();
+        fifo.sprite_fetch = None;
+        fifo.startup_dots = 6;
+    }
+
+    fn fifo_pixel_pipeline(&mut self) {
+        if self.fifo.startup_dots > 0 {
+            self.fifo.startup_dots -= 1;
+            return;
+        }
+        if let Some((index, dots)) = self.fifo.sprite_fetch {
+            return self.step_sprite_fetch(index, dots);
+        }
+
+        self.step_fetcher();
+        if self.fifo.bg_fifo.is_empty() {
+            return;
+        }
+
+        if self.fifo.discard == 0 && self.window_starts_here() {
+            let fifo = &mut self.fifo;
+            fifo.window_on_line = true;
+            fifo.bg_fifo.clear();
+            fifo.fetcher = Fetcher {
+                window: true,
+                ..Default::default()
+            };
+            if fifo.lcd_x == 0 && self.r.wx < 7 {
+                fifo.discard = 7 - self.r.wx;
+            }
+            return;
+        }
+
+        if self.fifo.discard == 0 && self.r.lcdc & 0x02 != 0 {
+            let lcd_x = self.fifo.lcd_x as i16;
+            let next = self.fifo.sprites.iter().position(|&(sprite, _, fetched)| {
+                !fetched && (sprite.x == lcd_x || (sprite.x < 0 && lcd_x == 0))
+            });
+            if let Some(index) = next {
+                self.fifo.sprites[index].2 = true;
+                return self.step_sprite_fetch(index, SPRITE_FETCH_DOTS);
+            }
+        }
+
+        let bg = self.fifo.bg_fifo.pop_front().unwrap();
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+        let obj = self.fifo.obj_fifo.pop_front();
+        let x = self.fifo.lcd_x as usize;
+        let colour = self.mix_pixel(x, bg, obj);
+        let index = self.r.ly as usize * 160 + x;
+        self.backbuf()[index] = colour;
+        self.fifo.lcd_x += 1;
+    }
+
+    // The BG fetcher gets the tile it's on nearly done before the sprite is fetched, which costs up
+    // to 5 dots on top of the sprite fetch itself.
+    fn step_sprite_fetch(&mut self, index: usize, dots: u8) {
+        if self.fifo.fetcher.dot < 5 {
+            self.step_fetcher();
+            self.fifo.sprite_fetch = Some((index, dots));
+        } else if dots > 1 {
+            self.fifo.sprite_fetch = Some((index, dots - 1));
+        } else {
+            self.fifo.sprite_fetch = None;
+            self.fetch_sprite(index);
+        }
+    }
+
+    fn window_starts_here(&self) -> bool {
+        self.r.lcdc & 0x20 != 0
+            && self.fifo.window_y_triggered
+            && !self.fifo.fetcher.window
+            && self.fifo.lcd_x as u16 + 7 >= self.r.wx as u16
+            && self.r.wx < 167
+    }
+
+    fn mix_pixel(&mut self, x: usize, bg: BgPixel, obj: Option<ObjPixel>) -> u16 {
+        // On a DMG LCDC bit 0 turns the background and window off, in CGB mode it only takes away
+        // their priority over sprites.
+        let bg_enabled = self.r.lcdc & 1 != 0;
+        let bg_colour = if bg_enabled || self.cgb.enabled { bg.colour } else { 0 };
+        if let Some(obj) = obj.filter(|obj| obj.colour != 0 && self.r.lcdc & 0x02 != 0) {
+            let cgb_master_priority = self.cgb.enabled && !bg_enabled;
+            let behind_bg = (obj.sprite.priority != 0 || bg.attributes & 0x80 != 0) && bg_colour != 0;
+            if cgb_master_priority || !behind_bg {
+                return self.sprite_colour(x, obj.sprite, obj.colour);
+            }
+        }
+        self.background_colour(x, bg.attributes, bg_colour)
+    }
+
+    // One dot of the BG fetcher: tile number, low byte and high byte take two dots each, then it
+    // waits for the BG FIFO to empty and pushes a row of 8 pixels.
+    fn step_fetcher(&mut self) {
+        self.fifo.fetcher.dot += 1;
+        match self.fifo.fetcher.dot {
+            2 => self.fetch_tile_number(),
+            4 => self.fifo.fetcher.low = self.fetch_tile_data(0),
+            6 => self.fifo.fetcher.high = self.fetch_tile_data(1),
+            7.. if self.fifo.bg_fifo.is_empty() => {
+                let fetcher = &mut self.fifo.fetcher;
+                let (mut low, mut high) = (fetcher.low, fetcher.high);
+                if fetcher.attributes & 0x20 != 0 {
+                    low = low.reverse_bits();
+                    high = high.reverse_bits();
+                }
+                for bit in (0..8).rev() {
+                    let colour = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+                    self.fifo.bg_fifo.push_back(BgPixel {
+                        colour,
+                        attributes: fetcher.attributes,
+                    });
+                }
+                fetcher.dot = 0;
+                fetcher.x = fetcher.x.wrapping_add(1);
+            }
+            7.. => self.fifo.fetcher.dot = 7,
+            _ => {}
+        }
+    }
+
+    fn fetch_tile_number(&mut self) {
+        let fetcher = &self.fifo.fetcher;
+        let address = if fetcher.window {
+            let map = if self.r.lcdc & 0x40 != 0 { 0x9C00 } else { 0x9800 };
+            map + (self.window_line_counter as u16 / 8) * 32 + (fetcher.x as u16 & 0x1F)
+        } else {
+            let map = if self.r.lcdc & 0x08 != 0 { 0x9C00 } else { 0x9800 };
+            let y = self.r.ly.wrapping_add(self.r.scy) as u16;
+            let x = ((self.r.scx / 8) as u16 + fetcher.x as u16) & 0x1F;
+            map + (y / 8) * 32 + x
+        };
+        self.fifo.fetcher.tile_num = self.vram(0, address);
+        self.fifo.fetcher.attributes = if self.cgb.enabled { self.vram(1, address) } else { 0 };
+    }
+
+    fn fetch_tile_data(&mut self, byte: u16) -> u8 {
+        let fetcher = &self.fifo.fetcher;
+        let mut tile_y = if fetcher.window {
+            self.window_line_counter % 8
+        } else {
+            self.r.ly.wrapping_add(self.r.scy) % 8
+        };
+        if fetcher.attributes & 0x40 != 0 {
+            tile_y = 7 - tile_y;
+        }
+        let bank = (fetcher.attributes >> 3) & 1;
+        let mut tile_num = fetcher.tile_num;
+        let mut tile_data_base = 0;
+        self.select_tile_addressing_method(&mut tile_num, &mut tile_data_base);
+        self.vram(bank, tile_data_base + tile_num as u16 * 16 + tile_y as u16 * 2 + byte)
+    }
+
+    // Mixes a sprite's row into the OBJ FIFO. On a DMG the sprite fetched first (leftmost, then
+    // lowest in OAM) wins, in CGB mode the one lowest in OAM does.
+    fn fetch_sprite(&mut self, index: usize) {
+        let (sprite, oam_index, _) = self.fifo.sprites[index];
+        let mut row = self.r.ly as i16 - sprite.y;
+        if sprite.y_flip != 0 {
+            row = sprite.size_y as i16 - 1 - row;
+        }
+        let tile_num = if sprite.size_y == 16 { sprite.tile_num & !1 } else { sprite.tile_num };
+        let address = 0x8000 + tile_num as u16 * 16 + row as u16 * 2;
+        let low = self.vram(sprite.vram_bank, address);
+        let high = self.vram(sprite.vram_bank, address + 1);
+
+        // pixels off the left edge of the screen are never shown
+        let skip = (-sprite.x).max(0) as usize;
+        for i in skip..8 {
+            let bit = if sprite.x_flip != 0 { i } else { 7 - i };
+            let pixel = ObjPixel {
+                colour: ((low >> bit) & 1) | (((high >> bit) & 1) << 1),
+                sprite,
+                oam_index,
+            };
+            let position = i - skip;
+            match self.fifo.obj_fifo.get_mut(position) {
+                Some(existing) => {
+                    let wins = existing.colour == 0
+                        || (self.cgb.enabled && pixel.colour != 0 && oam_index < existing.oam_index);
+                    if wins {
+                        *existing = pixel;
+                    }
+                }
+                None => self.fifo.obj_fifo.push_back(pixel),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RendererKind;
+    use crate::gb;
+
+    // A GameBoy with the LCD on and a blank screen, one line into the frame.
+    fn fifo_gameboy() -> gb::GameBoy {
+        let mut gameboy = gb::init();
+        gameboy.test_mode = false;
+        gameboy.renderer_kind = RendererKind::Fifo;
+        gameboy.r.lcdc = 0x93;
+        gameboy.r.bg_pal = 0xE4;
+        gameboy.r.obp0 = 0xE4;
+        gameboy
+    }
+
+    // Runs the PPU alone until the given line is in mode 0, and returns how many dots mode 3 took.
+    fn mode_3_length(gameboy: &mut gb::GameBoy, line: u8) -> u16 {
+        while gameboy.r.ly != line || gameboy.fifo.dot != super::OAM_SCAN_DOTS {
+            gameboy.fifo_dot();
+        }
+        let mut dots = 0;
+        while gameboy.r.stat & 3 != 0 {
+            gameboy.fifo_dot();
+            if gameboy.r.stat & 3 == 3 {
+                dots += 1;
+            }
+        }
+        dots
+    }
+
+    #[test]
+    fn mode_3_gets_longer_with_scx_and_sprites() {
+        let mut gameboy = fifo_gameboy();
+        let plain = mode_3_length(&mut gameboy, 1);
+        assert!((172..=176).contains(&plain), "mode 3 took {plain} dots");
+
+        gameboy.r.scx = 5;
+        assert_eq!(mode_3_length(&mut gameboy, 2), plain + 5);
+
+        gameboy.r.scx = 0;
+        // a sprite on line 3, at x = 40
+        gameboy.memory.main[0xFE00..0xFE04].copy_from_slice(&[16 + 3, 48, 0, 0]);
+        let with_sprite = mode_3_length(&mut gameboy, 3);
+        assert!((plain + 6..=plain + 11).contains(&with_sprite), "mode 3 took {with_sprite} dots");
+    }
+
+    #[test]
+    fn scx_written_mid_line_takes_effect() {
+        let mut gameboy = fifo_gameboy();
+        // tile 1 is solid colour 3, placed in map column 3 only
+        gameboy.memory.main[0x8010..0x8020].fill(0xFF);
+        gameboy.memory.main[0x9803] = 1;
+        gameboy.r.lcdc = 0x91;
+
+        while gameboy.r.ly != 0 || gameboy.fifo.dot != 0 {
+            gameboy.fifo_dot();
+        }
+        while gameboy.fifo.lcd_x < 8 {
+            gameboy.fifo_dot();
+        }
+        // the fetcher is about to fetch column 2, scrolling makes it fetch column 3 instead
+        gameboy.r.scx = 8;
+        while gameboy.r.ly == 0 {
+            gameboy.fifo_dot();
+        }
+        let line = &gameboy.backbuf()[..160];
+        let black = crate::renderer::DMG_COLOURS[3];
+        assert!(line[16..24].iter().all(|&pixel| pixel == black));
+        assert!(line[24..32].iter().all(|&pixel| pixel != black));
+    }
+}
@@ -1,5 +1,9 @@
 use crate::memory::{self, MappedRAM, MappingType};
-use crate::{log, mbc, util};
+use crate::state::big_array;
+use crate::{apu, cgb, debugger, dma, fifo, log, mbc, serial, sgb, util};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
 pub struct Registers {
     pub a: u8,
     pub f: u8,
@@ -31,7 +35,7 @@ pub struct Registers {
     pub obp1: u8,
     pub obp0: u8,
 }
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Sprite {
     pub size_y: u8,
     pub x: i16,
@@ -41,18 +45,19 @@ pub struct Sprite {
     pub pal: u8,
     pub priority: u8,
     pub tile_num: u8,
+    pub cgb_pal: u8,
+    pub vram_bank: u8,
 }
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Serialize, Deserialize)]
 pub enum State {
     Execute,
     Halted,
     Stopped, // yes these are distinct!
     InterruptHandler,
-    DmaTransfer,
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Debug, Serialize, Deserialize)]
 pub enum IsrState {
     Wait1,
     Wait2,
@@ -69,11 +74,31 @@ pub enum InterruptType {
     Joypad = 4,
 }
 
+// A key on one of the controllers going down or up, at the M-cycle it happened before.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct KeyEvent {
+    pub clock: u128,
+    pub player: u8,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Timer {
     prev_and_result: u8,
     pub(crate) wait_reload: i32,
 }
 
+// One read or write the CPU made, as recorded for the SingleStepTests
+#[cfg(test)]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub(crate) struct BusAccess {
+    pub address: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct GameBoy {
     pub clock: u128, // m-cycles
     pub r: Registers,
@@ -81,25 +106,39 @@ pub struct GameBoy {
     pub memory: MappedRAM,
     pub ime: bool,
     pub ime_dispatch: Option<u8>,
-    pub displaybuf_0: [u8; 160 * 144],
-    pub displaybuf_1: [u8; 160 * 144],
+    #[serde(with = "big_array")]
+    pub displaybuf_0: Box<[u16; 160 * 144]>, // RGB555
+    #[serde(with = "big_array")]
+    pub displaybuf_1: Box<[u16; 160 * 144]>,
     pub backbuf_id: u8,
+    #[serde(skip)]
     pub logger: log::Logger,
     pub isr_state: IsrState,
     pub test_mode: bool,
     pub(crate) keys_ssba: u8,
     pub(crate) keys_dulr: u8,
-    pub(crate) oam_base: u16,
     pub sprites: [Sprite; 10],
     pub num_sprites: usize,
-    pub background: [u8; 256 * 256],
-    pub window: [u8; 256 * 256],
     pub state: State,
     pub timer: Timer,
-    pub dma_transfer_bytes_copied: u8,
-    pub(crate) dma_base: usize,
+    pub dma: dma::OamDma,
     pub(crate) window_line_counter: u8,
     pub mbc: mbc::MBC,
+    pub apu: apu::Apu,
+    pub serial: serial::Serial,
+    pub cgb: cgb::Cgb,
+    pub sgb: sgb::Sgb,
+    pub renderer_kind: fifo::RendererKind,
+    pub fifo: fifo::Fifo,
+    #[serde(skip)]
+    pub debugger: debugger::Debugger,
+    // key presses and releases on every controller, while Some, see take_key_events
+    #[serde(skip)]
+    pub(crate) key_log: Option<Vec<KeyEvent>>,
+    // every CPU bus access and the M-cycle it happened on, while Some
+    #[cfg(test)]
+    #[serde(skip)]
+    pub(crate) bus_log: Option<Vec<(u128, BusAccess)>>,
 }
 
 pub fn init() -> GameBoy {
@@ -136,7 +175,7 @@ pub fn init() -> GameBoy {
     };
 
     let memory: MappedRAM = MappedRAM {
-        main: [0u8; memory::GB_RAM_SIZE],
+        main: Box::new([0u8; memory::GB_RAM_SIZE]),
         boot_rom: [0; memory::GB_ROM_SIZE],
         mapping_type: MappingType::Default,
         cartridge: vec![],
@@ -145,6 +184,7 @@ pub fn init() -> GameBoy {
 
     let logger = log::Logger {
         level: log::LogLevel::None,
+        trace: None,
     };
 
     GameBoy {
@@ -155,15 +195,14 @@ pub fn init() -> GameBoy {
         memory,
         ime: false,
         ime_dispatch: None,
-        displaybuf_0: [0; 160 * 144],
-        displaybuf_1: [0; 160 * 144],
+        displaybuf_0: Box::new([0; 160 * 144]),
+        displaybuf_1: Box::new([0; 160 * 144]),
         backbuf_id: 0,
         logger,
         isr_state: IsrState::Wait1,
         test_mode: false,
         keys_ssba: 0xF,
         keys_dulr: 0xF,
-        oam_base: 0,
         sprites: [Sprite {
             size_y: 0,
             x: 0,
@@ -173,23 +212,66 @@ pub fn init() -> GameBoy {
             pal: 0,
             priority: 0,
             tile_num: 0,
+            cgb_pal: 0,
+            vram_bank: 0,
         }; 10],
         num_sprites: 0,
-        background: [0; 256 * 256],
-        window: [0; 256 * 256],
         timer: Timer {
             prev_and_result: 0,
             wait_reload: 0,
         },
-        dma_transfer_bytes_copied: 0,
-        dma_base: 0,
+        dma: Default::default(),
         window_line_counter: 0,
         mbc: Default::default(),
+        apu: Default::default(),
+        serial: Default::default(),
+        cgb: Default::default(),
+        sgb: Default::default(),
+        renderer_kind: Default::default(),
+        fifo: Default::default(),
+        debugger: Default::default(),
+        key_log: None,
+        #[cfg(test)]
+        bus_log: None,
     }
 }
 
 impl GameBoy {
     pub fn tick(&mut self) {
+        if self.serial_stalled() {
+            return;
+        }
+        if !std::mem::take(&mut self.debugger.second_half) {
+            if self.debugger_stopped() {
+                return;
+            }
+            if self.mbc.has_rtc {
+                self.mbc.rtc.tick();
+            }
+            self.apu.tick();
+            self.cpu_tick();
+        }
+        if self.cgb.double_speed {
+            if self.debugger_stopped() {
+                self.debugger.second_half = true;
+                return;
+            }
+            self.cpu_tick();
+        }
+
+        let lcd_enable = (self.r.lcdc >> 7) & 1 != 0;
+
+        if !self.test_mode && lcd_enable {
+            self.renderer();
+        };
+
+        self.clock += 1;
+    }
+
+    // One M-cycle of everything on the CPU's clock, which runs twice per tick in double speed.
+    fn cpu_tick(&mut self) {
+        self.step_oam_dma();
+        self.poll_serial();
         self.update_ime(false);
         self.check_and_trigger_ly_coincidence();
 
@@ -214,19 +296,38 @@ impl GameBoy {
             self.cycles_to_idle = Some(0);
         }
 
-        if self.state == State::Execute || self.state == State::DmaTransfer {
+        if self.cgb.cpu_stall > 0 {
+            self.cgb.cpu_stall -= 1;
+        } else if self.state == State::Execute {
             self.execute();
         }
 
         self.update_ime(true);
+    }
 
-        let lcd_enable = (self.r.lcdc >> 7) & 1 != 0;
-
-        if !self.test_mode && lcd_enable {
-            self.renderer();
-        };
-
-        self.clock += 1;
+    // Puts the registers where the boot ROM leaves them and starts at the cartridge entry point.
+    pub fn skip_boot_rom(&mut self) {
+        if self.cgb.enabled {
+            (self.r.a, self.r.f, self.r.b, self.r.c) = (0x11, 0x80, 0x00, 0x00);
+            (self.r.d, self.r.e, self.r.h, self.r.l) = (0xFF, 0x56, 0x00, 0x0D);
+            // the boot ROM leaves the background palettes white
+            self.cgb.bg_palettes.fill(0xFF);
+            for i in (1..64).step_by(2) {
+                self.cgb.bg_palettes[i] = 0x7F;
+            }
+        } else {
+            (self.r.a, self.r.f, self.r.b, self.r.c) = (0x01, 0xB0, 0x00, 0x13);
+            (self.r.d, self.r.e, self.r.h, self.r.l) = (0x00, 0xD8, 0x01, 0x4D);
+        }
+        self.r.sp = 0xFFFE;
+        self.r.pc = 0x100;
+        self.r.bank = 1;
+        self.r.lcdc = 0x91;
+        self.r.bg_pal = 0xFC;
+        self.r.div_16 = 0xABCC;
+        self.write(0xFF26, 0x80);
+        self.write(0xFF25, 0xF3);
+        self.write(0xFF24, 0x77);
     }
 
     pub fn check_and_trigger_ly_coincidence(&mut self) {
@@ -243,6 +344,15 @@ impl GameBoy {
 
     fn update_timers(&mut self) {
         self.r.div_16 += 1;
+        // falling edge of DIV bit 4 (bit 5 in double speed)
+        let frame_sequencer_mask = if self.cgb.double_speed { 0x3FFF } else { 0x1FFF };
+        if self.r.div_16 & frame_sequencer_mask == 0 {
+            self.apu.clock_frame_sequencer();
+        }
+        if self.r.div_16 & 0x1FF == 0 {
+            // falling edge of DIV bit 8
+            self.clock_serial();
+        }
 
         if self.timer.wait_reload > 0 && self.timer.wait_reload < 5 {
             self.timer.wait_reload += 1;
@@ -282,7 +392,11 @@ impl GameBoy {
     fn execute(&mut self) {
         if let Some(cycles_to_idle) = self.cycles_to_idle {
             if cycles_to_idle == 0 {
+                if self.logger.trace.is_some() {
+                    self.trace_instruction();
+                }
                 let opcode: u8 = self.read(self.r.pc);
+                self.debugger.last_opcode = opcode;
                 self.r.pc += 1;
                 self.cycles_to_idle = self.fetch_decode_execute(opcode);
             } else {
@@ -292,7 +406,6 @@ impl GameBoy {
     }
 
     fn handle_interrupts(&mut self) {
-        self.clock += 1;
         match self.isr_state {
             IsrState::Wait1 => {
                 self.logger.log_info("ISR Wait1");
@@ -351,6 +464,7 @@ impl GameBoy {
 
     pub fn press_key(&mut self, mut key_id: u8) {
         // 3: start, 2: select 1: b, 0: a, 7: down, 6: up, 5: left, 4: right
+        self.log_key_event(0, key_id, true);
         let mut keys: &mut u8 = &mut self.keys_ssba;
         if key_id > 3 {
             keys = &mut self.keys_dulr;
@@ -360,6 +474,7 @@ impl GameBoy {
     }
 
     pub fn unpress_key(&mut self, mut key_id: u8) {
+        self.log_key_event(0, key_id, false);
         let mut keys: &mut u8 = &mut self.keys_ssba;
         if key_id > 3 {
             keys = &mut self.keys_dulr;
@@ -8,6 +8,13 @@ enum Destination {
     Undefined,
 }
 
+#[derive(Debug)]
+enum CgbFlag {
+    DmgOnly,
+    CgbCompatible, // 0x80
+    CgbOnly,       // 0xC0
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum NewLicensee {
     None,                      // "00"
@@ -402,6 +409,7 @@ pub struct CartridgeHeader {
     title: String,
     manufacturer_code: String,
     new_licensee: NewLicensee,
+    cgb_flag: CgbFlag,
     sgb_flag: bool,
     mbc: mbc::MBC,
     destination: Destination,
@@ -415,7 +423,12 @@ impl GameBoy {
         let title = String::from_utf8(self.memory.cartridge[0x0134..0x0143].to_vec()).unwrap();
         let manufacturer_code = String::from_utf8(self.memory.cartridge[0x013F..=0x0142].to_vec()).unwrap();
         let new_licensee = NewLicensee::from_bytes(&self.memory.cartridge[0x0144..=0x0145].try_into().unwrap());
-        let sgb_flag = self.memory.cartridge[0x0147] == 0x03;
+        let cgb_flag = match self.memory.cartridge[0x0143] {
+            0x80 => CgbFlag::CgbCompatible,
+            0xC0 => CgbFlag::CgbOnly,
+            _ => CgbFlag::DmgOnly,
+        };
+        let sgb_flag = self.memory.cartridge[0x0146] == 0x03;
         let destination = match self.memory.cartridge[0x014A] {
             0x00 => {Destination::Japan},
             0x01 => {Destination::RestOfWorld},
@@ -428,6 +441,7 @@ impl GameBoy {
             title,
             manufacturer_code,
             new_licensee,
+            cgb_flag,
             sgb_flag,
             mbc,
             destination,
@@ -1,12 +1,25 @@
-//#![feature(custom_test_frameworks)]
-//#![test_runner(datatest::runner)]
+pub mod apu;
+pub mod cgb;
+pub mod debugger;
+pub mod disasm;
+pub mod dma;
 pub mod fde;
+pub mod fifo;
 pub mod gb;
 mod log;
 pub mod memory;
-//pub mod single_step_tests;
+mod single_step_tests;
 pub mod isr;
 pub mod renderer;
+pub mod rewind;
+pub mod runner;
 pub mod util;
 pub mod mbc;
+pub mod movie;
+pub mod save;
+pub mod serial;
+pub mod sgb;
+pub mod state;
+pub mod trace;
+pub mod vram;
 mod header;
\ No newline at end of file
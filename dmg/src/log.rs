@@ -1,14 +1,18 @@
-#[derive(PartialEq, PartialOrd)]
+use crate::trace::Trace;
+
+#[derive(PartialEq, PartialOrd, Default)]
 pub enum LogLevel {
+    #[default]
     None = 0,
     Info = 1,
     Warning = 2,
     Error = 3,
-    Disassembly = 4,
 }
 
+#[derive(Default)]
 pub struct Logger {
     pub level: LogLevel,
+    pub(crate) trace: Option<Trace>,
 }
 
 impl Logger {
@@ -29,10 +33,4 @@ impl Logger {
             println!("{}", message)
         }
     }
-
-    pub fn log_disassembly(&self, message: &str) {
-        if self.level >= LogLevel::Disassembly {
-            println!("{}", message)
-        }
-    }
 }
@@ -1,19 +1,22 @@
+pub mod rtc;
+
 use crate::gb::GameBoy;
+use serde::{Deserialize, Serialize};
 
-#[derive(Default, Debug, PartialEq)]
+#[derive(Default, Debug, PartialEq, Serialize, Deserialize)]
 pub(crate) enum CartridgeType {
     #[default]
     NoMBC,
     MBC1,
-    MBC3, // NO RTC
+    MBC3,
     MBC5,
 }
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Serialize, Deserialize)]
 pub struct MBC {
     pub(crate) cartridge_type: CartridgeType,
     pub(crate) rom_size: usize,
     pub(crate) ram_size: usize,
-    has_battery: bool,
+    pub(crate) has_battery: bool,
     pub(crate) has_ram: bool,
     pub(crate) ram_enabled: bool,
     pub(crate) rom_bank_number: u8,
@@ -21,6 +24,9 @@ pub struct MBC {
     pub(crate) banking_mode_1_select: bool,
     pub rom_bank_high: u8,
     pub rom_bank_low: u8,
+    pub(crate) ram_dirty: bool, // set on writes to external RAM, cleared when the .sav is flushed
+    pub(crate) has_rtc: bool,
+    pub rtc: rtc::Rtc,
 }
 impl GameBoy {
     pub fn detect_mbc(&mut self) -> MBC {
@@ -29,6 +35,7 @@ impl GameBoy {
         let ram_size: usize;
         let has_battery: bool;
         let has_ram: bool;
+        let mut has_rtc = false;
         match self.memory.cartridge[0x147] {
             0 => {
                 cartridge_type = CartridgeType::NoMBC;
@@ -50,6 +57,20 @@ impl GameBoy {
                 has_battery = true;
                 has_ram = true;
             }
+            0x0F => {
+                // MBC3 + TIMER + BATTERY
+                cartridge_type = CartridgeType::MBC3;
+                has_battery = true;
+                has_ram = false;
+                has_rtc = true;
+            }
+            0x10 => {
+                // MBC3 + TIMER + RAM + BATTERY
+                cartridge_type = CartridgeType::MBC3;
+                has_battery = true;
+                has_ram = true;
+                has_rtc = true;
+            }
             0x11 => {
                 cartridge_type = CartridgeType::MBC3;
                 has_battery = false;
@@ -107,7 +128,7 @@ impl GameBoy {
             }
             _ => panic!("Invalid RAM size (cartridge byte 0x149)")
         }
-        self.memory.switchable_ram = vec![0; self.mbc.ram_size];
+        self.memory.switchable_ram = vec![0; ram_size];
 
         MBC {
             cartridge_type,
@@ -121,6 +142,9 @@ impl GameBoy {
             banking_mode_1_select: false,
             rom_bank_high: 0,
             rom_bank_low: 0,
+            ram_dirty: false,
+            has_rtc,
+            rtc: Default::default(),
         }
     }
 
@@ -140,10 +164,24 @@ impl GameBoy {
         }
     }
 
+    // MBC3 maps the RTC registers into 0xA000-0xBFFF when bank 0x08-0x0C is selected
+    pub fn mbc_rtc_selected(&self) -> bool {
+        self.mbc.has_rtc && self.mbc.ram_bank_number >= 0x08
+    }
+
     pub fn mbc_switchable_ram(&mut self) -> &mut [u8] {
+        let range = self.mbc_ram_range();
+        &mut self.memory.switchable_ram[range]
+    }
+
+    pub(crate) fn mbc_switchable_ram_ref(&self) -> &[u8] {
+        &self.memory.switchable_ram[self.mbc_ram_range()]
+    }
+
+    fn mbc_ram_range(&self) -> std::ops::Range<usize> {
         match self.mbc.has_ram {
             false => panic!("mbc_switchable_ram can only be called if MBC has RAM associated with it."),
-            true  => &mut self.memory.switchable_ram[self.mbc.ram_bank_number as usize*8*1024..(self.mbc.ram_bank_number as usize+1)*8*1024],
+            true  => self.mbc.ram_bank_number as usize*8*1024..(self.mbc.ram_bank_number as usize+1)*8*1024,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,287 @@
+// MBC3 real time clock. The clock is advanced from emulated M-cycles so that it stays in step with
+// the emulation, and catches up with the host clock when a save file is loaded.
+use serde::{Deserialize, Serialize};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) const M_CYCLES_PER_SECOND: u32 = 1 << 20;
+
+// Size of the RTC trailer appended to the SRAM in a .sav file. Older emulators wrote a 32 bit
+// timestamp (44 bytes), most current ones write a 64 bit timestamp (48 bytes).
+pub const RTC_SAVE_SIZE: usize = 48;
+pub const RTC_SAVE_SIZE_LEGACY: usize = 44;
+
+const HALT: u8 = 1 << 6;
+const DAY_CARRY: u8 = 1 << 7;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RtcRegisters {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    pub days_low: u8,
+    pub days_high: u8, // bit 0: day counter bit 8, bit 6: halt, bit 7: day counter carry
+}
+
+impl RtcRegisters {
+    fn get(&self, register: u8) -> u8 {
+        match register {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.days_low,
+            0x0C => self.days_high,
+            _ => 0xFF,
+        }
+    }
+
+    fn days(&self) -> u16 {
+        ((self.days_high as u16 & 1) << 8) | self.days_low as u16
+    }
+
+    fn set_days(&mut self, days: u16) {
+        self.days_low = days as u8;
+        self.days_high = (self.days_high & !1) | ((days >> 8) as u8 & 1);
+    }
+
+    fn halted(&self) -> bool {
+        self.days_high & HALT != 0
+    }
+
+    fn advance_second(&mut self) {
+        // The counters only roll over when they hit their natural limit. Out of range values
+        // (which games can write) count up to the register width and wrap without a carry.
+        self.seconds = (self.seconds + 1) & 0x3F;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3F;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1F;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        let days = self.days() + 1;
+        if days > 0x1FF {
+            self.days_high |= DAY_CARRY;
+        }
+        self.set_days(days & 0x1FF);
+    }
+
+    fn advance(&mut self, mut seconds: u64) {
+        // Step one second at a time until every counter is in range, then do the rest at once.
+        while seconds > 0 && (self.seconds > 59 || self.minutes > 59 || self.hours > 23) {
+            self.advance_second();
+            seconds -= 1;
+        }
+        let total = self.seconds as u64
+            + 60 * self.minutes as u64
+            + 3600 * self.hours as u64
+            + 86400 * self.days() as u64
+            + seconds;
+        let days = total / 86400;
+        if days > 0x1FF {
+            self.days_high |= DAY_CARRY;
+        }
+        self.set_days((days & 0x1FF) as u16);
+        self.hours = ((total / 3600) % 24) as u8;
+        self.minutes = ((total / 60) % 60) as u8;
+        self.seconds = (total % 60) as u8;
+    }
+}
+
+#[derive(Default, Debug, Serialize, Deserialize)]
+pub struct Rtc {
+    pub live: RtcRegisters,
+    pub latched: RtcRegisters,
+    pub(crate) cycles: u32, // M-cycles into the current second
+    pub(crate) latch_armed: bool, // a 0x00 has been written to 0x6000-0x7FFF, a 0x01 will latch
+}
+
+impl Rtc {
+    pub fn tick(&mut self) {
+        if self.live.halted() {
+            return;
+        }
+        self.cycles += 1;
+        if self.cycles >= M_CYCLES_PER_SECOND {
+            self.cycles = 0;
+            self.live.advance_second();
+        }
+    }
+
+    pub fn write_latch(&mut self, data: u8) {
+        if self.latch_armed && data == 0x01 {
+            self.latched = self.live;
+        }
+        self.latch_armed = data == 0x00;
+    }
+
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched.get(register)
+    }
+
+    pub fn write(&mut self, register: u8, data: u8) {
+        match register {
+            0x08 => {
+                self.live.seconds = data & 0x3F;
+                self.cycles = 0;
+            }
+            0x09 => self.live.minutes = data & 0x3F,
+            0x0A => self.live.hours = data & 0x1F,
+            0x0B => self.live.days_low = data,
+            0x0C => self.live.days_high = data & (DAY_CARRY | HALT | 1),
+            _ => {}
+        }
+        // the latched registers read back what was just written
+        self.latched = self.live;
+    }
+
+    // Layout (all little endian): 5 x u32 live registers, 5 x u32 latched registers, u64 unix time.
+    pub fn to_save(&self) -> [u8; RTC_SAVE_SIZE] {
+        let mut out = [0u8; RTC_SAVE_SIZE];
+        for i in 0..5u8 {
+            out[i as usize * 4] = self.live.get(0x08 + i);
+            out[20 + i as usize * 4] = self.latched.get(0x08 + i);
+        }
+        out[40..48].copy_from_slice(&unix_time().to_le_bytes());
+        out
+    }
+
+    pub fn restore_save(&mut self, data: &[u8]) {
+        for (registers, base) in [(&mut self.live, 0), (&mut self.latched, 20)] {
+            registers.seconds = data[base] & 0x3F;
+            registers.minutes = data[base + 4] & 0x3F;
+            registers.hours = data[base + 8] & 0x1F;
+            registers.days_low = data[base + 12];
+            registers.days_high = data[base + 16] & (DAY_CARRY | HALT | 1);
+        }
+        let saved_at = match data.len() {
+            RTC_SAVE_SIZE => u64::from_le_bytes(data[40..48].try_into().unwrap()),
+            _ => u32::from_le_bytes(data[40..44].try_into().unwrap()) as u64,
+        };
+        // catch up with however long the emulator was closed for
+        if !self.live.halted() {
+            self.live.advance(unix_time().saturating_sub(saved_at));
+        }
+        self.cycles = 0;
+    }
+}
+
+// There's no system clock on the web, so there the RTC only counts while the game runs.
+#[cfg(target_arch = "wasm32")]
+fn unix_time() -> u64 {
+    0
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tick_seconds(rtc: &mut Rtc, seconds: u32) {
+        for _ in 0..seconds * M_CYCLES_PER_SECOND {
+            rtc.tick();
+        }
+    }
+
+    #[test]
+    fn counters_roll_over() {
+        let mut rtc = Rtc::default();
+        rtc.write(0x08, 59);
+        rtc.write(0x09, 59);
+        rtc.write(0x0A, 23);
+        rtc.write(0x0B, 0xFF);
+        tick_seconds(&mut rtc, 1);
+        assert_eq!(rtc.live, RtcRegisters { days_high: 1, ..Default::default() });
+
+        // out of range values count up to the register width and wrap without a carry
+        rtc.write(0x08, 63);
+        tick_seconds(&mut rtc, 1);
+        assert_eq!((rtc.live.seconds, rtc.live.minutes), (0, 0));
+    }
+
+    #[test]
+    fn day_counter_carries_and_halts() {
+        let mut rtc = Rtc {
+            live: RtcRegisters { seconds: 59, minutes: 59, hours: 23, days_low: 0xFF, days_high: 1 },
+            ..Default::default()
+        };
+        tick_seconds(&mut rtc, 1);
+        assert_eq!(rtc.live.days(), 0);
+        assert_eq!(rtc.live.days_high, DAY_CARRY);
+        // the carry stays set until it's written
+        rtc.live.advance(86400);
+        assert_eq!((rtc.live.days(), rtc.live.days_high & DAY_CARRY), (1, DAY_CARRY));
+        rtc.write(0x0C, 0);
+        assert_eq!(rtc.live.days_high, 0);
+
+        rtc.write(0x0C, HALT);
+        tick_seconds(&mut rtc, 2);
+        assert_eq!(rtc.live.seconds, 0);
+        rtc.write(0x0C, 0);
+        tick_seconds(&mut rtc, 1);
+        assert_eq!(rtc.live.seconds, 1);
+    }
+
+    #[test]
+    fn latches_on_0_then_1() {
+        let mut rtc = Rtc::default();
+        tick_seconds(&mut rtc, 3);
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 0);
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 3);
+        // the latched value holds while the clock keeps going
+        tick_seconds(&mut rtc, 1);
+        assert_eq!((rtc.read(0x08), rtc.live.seconds), (3, 4));
+        // a 1 without a 0 before it doesn't latch again
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(0x08), 3);
+    }
+
+    #[test]
+    fn save_trailer_round_trips() {
+        let rtc = Rtc {
+            live: RtcRegisters { seconds: 12, minutes: 34, hours: 5, days_low: 0x67, days_high: 1 | HALT },
+            latched: RtcRegisters { seconds: 1, minutes: 2, hours: 3, days_low: 4, days_high: DAY_CARRY },
+            ..Default::default()
+        };
+        let save = rtc.to_save();
+
+        let mut restored = Rtc::default();
+        restored.restore_save(&save);
+        assert_eq!((restored.live, restored.latched), (rtc.live, rtc.latched));
+
+        // the older format has a 32 bit timestamp
+        let mut legacy = save[..RTC_SAVE_SIZE_LEGACY].to_vec();
+        legacy[40..44].copy_from_slice(&(unix_time() as u32).to_le_bytes());
+        let mut restored = Rtc::default();
+        restored.restore_save(&legacy);
+        assert_eq!((restored.live, restored.latched), (rtc.live, rtc.latched));
+    }
+
+    #[test]
+    fn catches_up_with_the_time_saved() {
+        let mut rtc = Rtc::default();
+        let mut save = rtc.to_save();
+        save[40..48].copy_from_slice(&(unix_time() - 2 * 86400 - 3600).to_le_bytes());
+        rtc.restore_save(&save);
+        // give or take the second that may have passed since
+        assert_eq!((rtc.live.days(), rtc.live.hours, rtc.live.minutes), (2, 1, 0));
+    }
+}
@@ -1,32 +1,92 @@
+use crate::fifo::RendererKind;
+use crate::debugger::Access;
 use crate::gb::GameBoy;
 use crate::mbc;
+use crate::state::big_array;
+use serde::{Deserialize, Serialize};
 use std::cmp::{max, min, PartialEq};
 
 pub const GB_RAM_SIZE: usize = 0x10000;
 pub const GB_ROM_SIZE: usize = 0x100;
+const ROM_BANK_SIZE: usize = 0x4000;
+const SRAM_BANK_SIZE: usize = 0x2000;
 pub fn init() -> FlatRAM {
     [0; GB_RAM_SIZE]
 }
 
 pub type FlatRAM = [u8; GB_RAM_SIZE];
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Serialize, Deserialize)]
 pub enum MappingType {
     Flat,    // all addresses readable and writable (for SST)
     Default, // normal DMG behaviour with no MBCs (wip)
 }
+// Memory that can be viewed without the banking applied, see GameBoy::region.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MemoryRegion {
+    Rom,
+    Sram,
+    Vram,
+    Oam,
+}
+
+// The boot ROM and cartridge ROM are not part of a save state, the ones already loaded are kept.
+#[derive(Serialize, Deserialize)]
 pub struct MappedRAM {
     pub mapping_type: MappingType,
-    pub main: [u8; GB_RAM_SIZE], // VRAM, work RAM, etc.
+    #[serde(with = "big_array")]
+    pub main: Box<[u8; GB_RAM_SIZE]>, // VRAM, work RAM, etc.
+    #[serde(skip, default = "empty_boot_rom")]
     pub boot_rom: [u8; GB_ROM_SIZE],
+    #[serde(skip)]
     pub cartridge: Vec<u8>,
     pub switchable_ram: Vec<u8>,
 }
 
+fn empty_boot_rom() -> [u8; GB_ROM_SIZE] {
+    [0; GB_ROM_SIZE]
+}
+
 impl GameBoy {
+    // Memory as the CPU sees it.
     pub fn read(&mut self, address: u16) -> u8 {
+        if self.oam_dma_blocks(address) {
+            return 0xFF;
+        }
+        let value = self.bus_read(address);
+        self.check_watchpoints(address, value, Access::Read);
+        #[cfg(test)]
+        self.log_bus_access(address, value, false);
+        value
+    }
+
+    #[cfg(test)]
+    fn log_bus_access(&mut self, address: u16, value: u8, write: bool) {
+        let clock = self.clock;
+        if let Some(log) = &mut self.bus_log {
+            log.push((clock, crate::gb::BusAccess { address, value, write }));
+        }
+    }
+
+    // What's on the bus at an address, as the DMA sees it (without the CPU's DMA lockout).
+    pub(crate) fn bus_read(&self, address: u16) -> u8 {
+        self.read_byte(address, true)
+    }
+
+    // Memory as the CPU sees it, without side effects: no watchpoints, no DMA lockout and no
+    // logging. For debuggers and memory viewers.
+    pub fn peek(&self, address: u16) -> u8 {
+        self.read_byte(address, false)
+    }
+
+    fn read_byte(&self, address: u16, log: bool) -> u8 {
         match address {
+            // SGB multiplayer: with neither half selected the low nibble is the controller number
+            0xFF00 if self.sgb.players > 1 && self.r.joypad & 0x30 == 0x30 => {
+                (self.r.joypad & 0xF0) | (0xF - self.sgb.current_player)
+            }
             0xFF00 => {
+                let (keys_ssba, keys_dulr) = self.current_player_keys();
                 let not_select_buttons = if (self.r.joypad >> 5) & 1 == 0 {
                     0
                 } else {
@@ -37,11 +97,13 @@ impl GameBoy {
                 } else {
                     0xFF
                 };
-                let lower_nibble = ((not_select_buttons & self.keys_dulr)
-                    | (not_select_dpad & self.keys_ssba))
+                let lower_nibble = ((not_select_buttons & keys_dulr)
+                    | (not_select_dpad & keys_ssba))
                     & 0x0F;
                 (self.r.joypad & 0xF0) | lower_nibble
             }
+            0xFF01 => self.serial.sb,
+            0xFF02 => self.serial.read_sc(),
             0xFF04 => (self.r.div_16 >> 8) as u8,
             0xFF05 => self.r.tima,
             0xFF06 => self.r.tma,
@@ -50,6 +112,7 @@ impl GameBoy {
             0xFF41 => self.r.stat,
             0xFF42 => self.r.scy,
             0xFF43 => self.r.scx,
+            0xFF44 if self.logger.trace.as_ref().is_some_and(|trace| trace.stub_ly) => 0x90,
             0xFF44 => self.r.ly,
             0xFF45 => self.r.lyc,
             0xFF47 => self.r.bg_pal,
@@ -58,12 +121,17 @@ impl GameBoy {
             0xFF4A => self.r.wy,
             0xFF4B => self.r.wx,
             0xFF50 => self.r.bank,
+            0xFF10..=0xFF3F => self.apu.read(address),
+            0xFF4D | 0xFF4F | 0xFF51..=0xFF55 | 0xFF68..=0xFF6B | 0xFF70 => self.cgb_read(address),
             0xFF0F => self.r.r#if,
             0xFFFF => self.r.ie,
             _ => match self.memory.mapping_type {
                 MappingType::Flat => self.memory.main[address as usize],
                 MappingType::Default => {
-                    if self.r.bank == 0 && (address as usize) < GB_ROM_SIZE {
+                    if let Some(byte) = self.cgb_banked_byte(address) {
+                        // CGB VRAM bank 1, WRAM banks 2-7
+                        byte
+                    } else if self.r.bank == 0 && (address as usize) < GB_ROM_SIZE {
                         self.memory.boot_rom[address as usize]
                     } else if (0x0000..=0x3FFF).contains(&address) {
                         // ROM bank 0
@@ -76,8 +144,10 @@ impl GameBoy {
                         self.memory.main[address as usize]
                     } else if (0xA000..=0xBFFF).contains(&address) {
                         // External RAM
-                        if self.mbc.has_ram && self.mbc.ram_enabled {
-                            self.mbc_switchable_ram()[address as usize - 0xA000]
+                        if self.mbc_rtc_selected() && self.mbc.ram_enabled {
+                            self.mbc.rtc.read(self.mbc.ram_bank_number)
+                        } else if self.mbc.has_ram && self.mbc.ram_enabled {
+                            self.mbc_switchable_ram_ref()[address as usize - 0xA000]
                         } else {
                             0xFF
                         }
@@ -91,15 +161,19 @@ impl GameBoy {
                         // OAM
                         self.memory.main[address as usize]
                     } else if (0xFEA0..=0xFEFF).contains(&address) {
-                        self.logger
-                            .log_error("Read from 0xFEA0-0xFEFF prohibited memory area");
+                        if log {
+                            self.logger
+                                .log_error("Read from 0xFEA0-0xFEFF prohibited memory area");
+                        }
                         0xFF
                     } else if (0xFF00..=0xFF7F).contains(&address) {
                         // IO registers
-                        self.logger.log_error(&format!(
-                            "Read from unimplemented IO register at address {:#x}",
-                            address
-                        ));
+                        if log {
+                            self.logger.log_error(&format!(
+                                "Read from unimplemented IO register at address {:#x}",
+                                address
+                            ));
+                        }
                         0xFF
                     } else if (0xFF80..=0xFFFE).contains(&address) {
                         // HRAM
@@ -113,6 +187,97 @@ impl GameBoy {
     }
 
     pub fn write(&mut self, address: u16, data: u8) {
+        if self.oam_dma_blocks(address) {
+            return;
+        }
+        self.check_watchpoints(address, data, Access::Write);
+        #[cfg(test)]
+        self.log_bus_access(address, data, true);
+        self.bus_write(address, data);
+    }
+
+    // Changes the byte the CPU sees at an address without the side effects of a write: ROM is
+    // patched rather than switching banks, and watchpoints don't fire. IO registers are written
+    // normally, since the side effects are all there is to them.
+    pub fn poke(&mut self, address: u16, value: u8) {
+        if self.memory.mapping_type == MappingType::Flat {
+            self.memory.main[address as usize] = value;
+            return;
+        }
+        if let Some(byte) = self.cgb_banked_ram(address) {
+            *byte = value;
+            return;
+        }
+        match address {
+            0x0000..=0x00FF if self.r.bank == 0 => self.memory.boot_rom[address as usize] = value,
+            0x0000..=0x7FFF => {
+                let offset = match address {
+                    0x0000..=0x3FFF => address as usize,
+                    _ => self.bank_at(address) as usize * ROM_BANK_SIZE + address as usize - 0x4000,
+                };
+                if let Some(byte) = self.memory.cartridge.get_mut(offset) {
+                    *byte = value;
+                }
+            }
+            0xA000..=0xBFFF if self.mbc.has_ram && !self.mbc_rtc_selected() => {
+                self.mbc_switchable_ram()[address as usize - 0xA000] = value;
+                self.mbc.ram_dirty = true;
+            }
+            0xA000..=0xBFFF => {}
+            0xE000..=0xFDFF => self.memory.main[address as usize - 0x2000] = value,
+            0xFF00..=0xFF7F | 0xFFFF => self.bus_write(address, value),
+            _ => self.memory.main[address as usize] = value,
+        }
+    }
+
+    pub fn region_banks(&self, region: MemoryRegion) -> usize {
+        match region {
+            MemoryRegion::Rom => self.memory.cartridge.len().div_ceil(ROM_BANK_SIZE),
+            MemoryRegion::Sram => self.memory.switchable_ram.len() / SRAM_BANK_SIZE,
+            MemoryRegion::Vram if self.cgb.enabled => 2,
+            MemoryRegion::Vram | MemoryRegion::Oam => 1,
+        }
+    }
+
+    // A bank of a region, empty if there's no such bank.
+    pub fn region(&self, region: MemoryRegion, bank: usize) -> &[u8] {
+        let bank_range = |size: usize| bank * size..(bank + 1) * size;
+        match region {
+            MemoryRegion::Rom => {
+                let range = bank_range(ROM_BANK_SIZE);
+                let end = range.end.min(self.memory.cartridge.len());
+                self.memory.cartridge.get(range.start..end).unwrap_or_default()
+            }
+            MemoryRegion::Sram => self.memory.switchable_ram.get(bank_range(SRAM_BANK_SIZE)).unwrap_or_default(),
+            MemoryRegion::Vram if bank == 1 => &self.cgb.vram_1[..],
+            MemoryRegion::Vram => &self.memory.main[0x8000..0xA000],
+            MemoryRegion::Oam => &self.memory.main[0xFE00..0xFEA0],
+        }
+    }
+
+    pub fn region_mut(&mut self, region: MemoryRegion, bank: usize) -> &mut [u8] {
+        let bank_range = |size: usize| bank * size..(bank + 1) * size;
+        match region {
+            MemoryRegion::Rom => {
+                let range = bank_range(ROM_BANK_SIZE);
+                let end = range.end.min(self.memory.cartridge.len());
+                self.memory.cartridge.get_mut(range.start..end).unwrap_or_default()
+            }
+            MemoryRegion::Sram => {
+                self.mbc.ram_dirty = true;
+                self.memory.switchable_ram.get_mut(bank_range(SRAM_BANK_SIZE)).unwrap_or_default()
+            }
+            MemoryRegion::Vram if bank == 1 => &mut self.cgb.vram_1[..],
+            MemoryRegion::Vram => &mut self.memory.main[0x8000..0xA000],
+            MemoryRegion::Oam => &mut self.memory.main[0xFE00..0xFEA0],
+        }
+    }
+
+    fn bus_write(&mut self, address: u16, data: u8) {
+        if let Some(byte) = self.cgb_banked_ram(address) {
+            *byte = data;
+            return;
+        }
         match address {
             0xFF05 => {
                 self.timer.wait_reload = 0;
@@ -120,12 +285,26 @@ impl GameBoy {
             }
             0xFF06 => self.r.tma = data,
             0xFF07 => self.r.tac = data,
-            0xFF04 => self.r.div_16 = 0,
+            0xFF04 => {
+                if (self.r.div_16 >> 12) & 1 != 0 {
+                    self.apu.clock_frame_sequencer();
+                }
+                if (self.r.div_16 >> 8) & 1 != 0 {
+                    self.clock_serial();
+                }
+                self.r.div_16 = 0
+            }
             0xFF44 => {
                 self.r.ly = data;
                 self.check_and_trigger_ly_coincidence();
             }
-            0xFF40 => self.r.lcdc = data,
+            0xFF40 => {
+                let lcd_off = self.r.lcdc & 0x80 != 0 && data & 0x80 == 0;
+                if lcd_off && self.renderer_kind == RendererKind::Fifo {
+                    self.fifo_lcd_off();
+                }
+                self.r.lcdc = data;
+            }
             0xFF4A => self.r.wy = data,
             0xFF4B => self.r.wx = data,
             0xFF42 => self.r.scy = data,
@@ -140,14 +319,18 @@ impl GameBoy {
                 self.check_and_trigger_ly_coincidence();
             }
             0xFF41 => self.r.stat = data,
-            0xFF00 => self.r.joypad = data & 0xF0,
-            0xFF46 => {
-                self.dma_base = ((data as u16) << 8) as usize;
-                for i in 0..160 {
-                    self.memory.main[0xFE00 + i] = self.read((self.dma_base + i) as u16)
+            0xFF00 => {
+                self.r.joypad = data & 0xF0;
+                if self.sgb.enabled {
+                    self.sgb_joypad_write(data);
                 }
             }
+            0xFF01 => self.serial.sb = data,
+            0xFF02 => self.serial.write_sc(data),
+            0xFF46 => self.start_oam_dma(data),
             0xFF50 => self.r.bank = data,
+            0xFF10..=0xFF3F => self.apu.write(address, data),
+            0xFF4D | 0xFF4F | 0xFF51..=0xFF55 | 0xFF68..=0xFF6B | 0xFF70 => self.cgb_write(address, data),
             _ => match self.memory.mapping_type {
                 MappingType::Flat => {}
                 MappingType::Default => match self.mbc.cartridge_type {
@@ -195,6 +378,7 @@ impl GameBoy {
 
                         if (0xA000..=0xBFFF).contains(&address) && self.mbc.has_ram && self.mbc.ram_enabled {
                             self.mbc_switchable_ram()[address as usize - 0xA000] = data;
+                            self.mbc.ram_dirty = true;
                         }
                     }
                     mbc::CartridgeType::MBC3 => {
@@ -203,10 +387,20 @@ impl GameBoy {
                         } else if (0x2000..=0x3FFF).contains(&address) {
                             self.mbc.rom_bank_number = max(data & 0x7F, 1);
                         } else if (0x4000..=0x5FFF).contains(&address) {
-                            self.mbc.ram_bank_number = data & 0x03;
+                            if self.mbc.has_rtc && (0x08..=0x0C).contains(&data) {
+                                self.mbc.ram_bank_number = data;
+                            } else {
+                                self.mbc.ram_bank_number = data & 0x03;
+                            }
+                        } else if (0x6000..=0x7FFF).contains(&address) && self.mbc.has_rtc {
+                            self.mbc.rtc.write_latch(data);
                         }
-                        if (0xA000..=0xBFFF).contains(&address) && self.mbc.has_ram && self.mbc.ram_enabled {
+                        if (0xA000..=0xBFFF).contains(&address) && self.mbc_rtc_selected() && self.mbc.ram_enabled {
+                            self.mbc.rtc.write(self.mbc.ram_bank_number, data);
+                            self.mbc.ram_dirty = true;
+                        } else if (0xA000..=0xBFFF).contains(&address) && self.mbc.has_ram && self.mbc.ram_enabled {
                             self.mbc_switchable_ram()[address as usize - 0xA000] = data;
+                            self.mbc.ram_dirty = true;
                         }
                     }
                     mbc::CartridgeType::MBC5 => {
@@ -252,6 +446,7 @@ impl GameBoy {
 
                         if (0xA000..=0xBFFF).contains(&address) && self.mbc.has_ram && self.mbc.ram_enabled {
                             self.mbc_switchable_ram()[address as usize - 0xA000] = data;
+                            self.mbc.ram_dirty = true;
                         }
                     }
                 },
@@ -261,3 +456,31 @@ impl GameBoy {
         self.memory.main[address as usize] = data;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::debugger;
+    use crate::runner::load_rom;
+
+    #[test]
+    fn peek_and_poke_have_no_side_effects() {
+        // MBC1 with 4 ROM banks, bank 2 switched in
+        let mut rom = vec![0; 0x10000];
+        rom[0x147] = 0x01;
+        rom[0x148] = 0x01;
+        rom[0x8000] = 0x22;
+        let mut gameboy = load_rom(rom, None);
+        gameboy.write(0x2000, 2);
+        gameboy.debugger.watchpoints.push(debugger::parse_watchpoint("0000-ffff rw").unwrap());
+
+        assert_eq!(gameboy.peek(0x4000), 0x22);
+        // a write here would switch banks, a poke patches the ROM
+        gameboy.poke(0x2000, 0x33);
+        gameboy.poke(0x4000, 0x44);
+        assert_eq!(gameboy.peek(0x2000), 0x33);
+        assert_eq!(gameboy.memory.cartridge[0x8000], 0x44);
+        gameboy.poke(0xE010, 0x55);
+        assert_eq!(gameboy.peek(0xC010), 0x55);
+        assert!(gameboy.debugger.stop_reason().is_none());
+    }
+}
@@ -0,0 +1,264 @@
+// Input movies: the keys pressed while recording, each with the M-cycle it was pressed before, and
+// the save state recording started from. Playing one back from that state presses the same keys at
+// the same clocks, so the run goes exactly the same way for as long as the emulator does. The
+// headless runner replays movies to check accuracy changes against known-good end states.
+//
+// The initial state has no boot ROM in it, so a movie recorded during the boot ROM needs the same
+// boot ROM loaded to play back.
+use crate::gb::{GameBoy, KeyEvent};
+use crate::runner::M_CYCLES_PER_FRAME;
+use crate::state::StateError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const MOVIE_MAGIC: &[u8; 8] = b"SCGBMOVI";
+// Bump this whenever Movie changes shape. The initial state is checked against its own version.
+const MOVIE_VERSION: u32 = 1;
+const HEADER_SIZE: usize = 12;
+
+#[derive(Serialize, Deserialize)]
+pub struct Movie {
+    // CRC32 of the whole cartridge ROM, as ROM databases list it
+    pub rom_crc32: u32,
+    pub initial_state: Vec<u8>,
+    pub start_clock: u128,
+    pub keys: Vec<KeyEvent>,
+    // where recording stopped, and playback ends
+    pub end_clock: u128,
+}
+
+#[derive(Debug)]
+pub enum MovieError {
+    NotAMovie,
+    UnsupportedVersion(u32),
+    WrongRom { movie: u32, loaded: u32 },
+    Decode(bincode::Error),
+    State(StateError),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::NotAMovie => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(v) => {
+                write!(f, "movie version {v} is not supported (expected {MOVIE_VERSION})")
+            }
+            MovieError::WrongRom { movie, loaded } => {
+                write!(f, "movie was recorded with ROM {movie:08x}, but {loaded:08x} is loaded")
+            }
+            MovieError::Decode(e) => write!(f, "couldn't decode movie: {e}"),
+            MovieError::State(e) => write!(f, "couldn't load the movie's initial state: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+impl Movie {
+    // Starts recording from the GameBoy's current state. Pass it the keys from take_key_events()
+    // as it runs.
+    pub fn record(gameboy: &mut GameBoy) -> Self {
+        // anything pressed before now isn't part of it
+        gameboy.take_key_events();
+        Self {
+            rom_crc32: gameboy.rom_crc32(),
+            initial_state: gameboy.save_state(),
+            start_clock: gameboy.clock,
+            keys: vec![],
+            end_clock: gameboy.clock,
+        }
+    }
+
+    pub fn extend(&mut self, gameboy: &GameBoy, keys: &[KeyEvent]) {
+        self.keys.extend_from_slice(keys);
+        self.end_clock = gameboy.clock;
+    }
+
+    // After the GameBoy went back in time, e.g. by rewinding, drops whatever came after. Going back
+    // to before the movie started starts it again from there.
+    pub fn rewound(&mut self, gameboy: &mut GameBoy) {
+        if gameboy.clock < self.start_clock {
+            *self = Movie::record(gameboy);
+            return;
+        }
+        self.keys.retain(|key| key.clock < gameboy.clock);
+        self.end_clock = gameboy.clock;
+    }
+
+    pub fn cycles(&self) -> u64 {
+        (self.end_clock - self.start_clock) as u64
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.cycles() / M_CYCLES_PER_FRAME
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_SIZE + self.initial_state.len() + self.keys.len() * 24);
+        out.extend_from_slice(MOVIE_MAGIC);
+        out.extend_from_slice(&MOVIE_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut out, self).expect("a movie should always serialise");
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MovieError> {
+        if data.len() < HEADER_SIZE || &data[0..8] != MOVIE_MAGIC {
+            return Err(MovieError::NotAMovie);
+        }
+        let version = u32::from_le_bytes(data[8..12].try_into().unwrap());
+        if version != MOVIE_VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        bincode::deserialize(&data[HEADER_SIZE..]).map_err(MovieError::Decode)
+    }
+}
+
+// Presses a movie's keys at their clocks while the GameBoy runs.
+pub struct MoviePlayer {
+    keys: Vec<KeyEvent>,
+    next: usize,
+    pub end_clock: u128,
+}
+
+impl MoviePlayer {
+    // Call before every tick.
+    pub fn apply(&mut self, gameboy: &mut GameBoy) {
+        while let Some(key) = self.keys.get(self.next).filter(|key| key.clock <= gameboy.clock) {
+            match key.pressed {
+                true => gameboy.press_player_key(key.player, key.key),
+                false => gameboy.unpress_player_key(key.player, key.key),
+            }
+            self.next += 1;
+        }
+    }
+
+    pub fn finished(&self, gameboy: &GameBoy) -> bool {
+        gameboy.clock >= self.end_clock
+    }
+}
+
+impl GameBoy {
+    // Keys pressed and released since the last call, through press_key or press_player_key. Nothing
+    // is logged until the first call.
+    pub fn take_key_events(&mut self) -> Vec<KeyEvent> {
+        std::mem::take(self.key_log.get_or_insert_with(Vec::new))
+    }
+
+    // Called before a key changes. Only changes are logged, the frontend presses held keys again
+    // every repaint.
+    pub(crate) fn log_key_event(&mut self, player: u8, key: u8, pressed: bool) {
+        let Some((ssba, dulr)) = self.player_keys(player) else { return };
+        let (keys, bit) = if key > 3 { (dulr, key - 4) } else { (ssba, key) };
+        let was_pressed = keys & (1 << bit) == 0;
+        let clock = self.clock;
+        if let Some(log) = self.key_log.as_mut().filter(|_| was_pressed != pressed) {
+            log.push(KeyEvent { clock, player, key, pressed });
+        }
+    }
+
+    pub fn rom_crc32(&self) -> u32 {
+        crc32fast::hash(&self.memory.cartridge)
+    }
+
+    // Loads the movie's initial state, after checking the same ROM is loaded. On error the running
+    // GameBoy is left untouched.
+    pub fn play_movie(&mut self, movie: &Movie) -> Result<MoviePlayer, MovieError> {
+        let loaded = self.rom_crc32();
+        if loaded != movie.rom_crc32 {
+            return Err(MovieError::WrongRom {
+                movie: movie.rom_crc32,
+                loaded,
+            });
+        }
+        self.load_state(&movie.initial_state).map_err(MovieError::State)?;
+        Ok(MoviePlayer {
+            keys: movie.keys.clone(),
+            next: 0,
+            end_clock: movie.end_clock,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::load_rom;
+
+    // 0x100: ldh a, (0x00); ld (hl), a; inc l; jr -6, so with the buttons selected the joypad
+    // register ends up all over C000-C0FF
+    fn joypad_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x106].copy_from_slice(&[0xF0, 0x00, 0x77, 0x2C, 0x18, 0xFA]);
+        rom
+    }
+
+    fn run_frames(gameboy: &mut GameBoy, frames: u64, mut each_frame: impl FnMut(u64, &mut GameBoy)) {
+        for frame in 0..frames {
+            each_frame(frame, gameboy);
+            for _ in 0..M_CYCLES_PER_FRAME {
+                gameboy.tick();
+            }
+        }
+    }
+
+    #[test]
+    fn playback_reproduces_the_recording() {
+        let mut gameboy = load_rom(joypad_rom(), None);
+        gameboy.write(0xFF00, 0x10);
+        gameboy.set_hl(0xC000);
+        run_frames(&mut gameboy, 3, |_, _| {});
+
+        let mut movie = Movie::record(&mut gameboy);
+        run_frames(&mut gameboy, 20, |frame, gameboy| {
+            match frame {
+                2 | 9 => gameboy.press_player_key(0, 0),
+                5 | 12 => gameboy.unpress_player_key(0, 0),
+                _ => {}
+            }
+            // held keys are pressed again every frame, which isn't logged
+            if (9..12).contains(&frame) {
+                gameboy.press_player_key(0, 0);
+            }
+            let keys = gameboy.take_key_events();
+            movie.extend(gameboy, &keys);
+        });
+        movie.extend(&gameboy, &[]);
+        assert_eq!(movie.frames(), 20);
+        assert_eq!(movie.keys.len(), 4);
+        let expected = gameboy.save_state();
+
+        let movie = Movie::from_bytes(&movie.to_bytes()).unwrap();
+        let mut replay = load_rom(joypad_rom(), None);
+        let mut player = replay.play_movie(&movie).unwrap();
+        while !player.finished(&replay) {
+            player.apply(&mut replay);
+            replay.tick();
+        }
+        assert!(replay.save_state() == expected, "the replay went differently");
+    }
+
+    #[test]
+    fn press_key_is_recorded() {
+        let mut gameboy = load_rom(joypad_rom(), None);
+        gameboy.take_key_events();
+        gameboy.press_key(7);
+        gameboy.press_key(7);
+        gameboy.unpress_key(7);
+        gameboy.press_player_key(1, 0);
+        let keys: Vec<(u8, u8, bool)> = gameboy
+            .take_key_events()
+            .iter()
+            .map(|event| (event.player, event.key, event.pressed))
+            .collect();
+        assert_eq!(keys, [(0, 7, true), (0, 7, false), (1, 0, true)]);
+    }
+
+    #[test]
+    fn playback_needs_the_same_rom() {
+        let mut gameboy = load_rom(joypad_rom(), None);
+        let movie = Movie::record(&mut gameboy);
+        let mut other = load_rom(vec![0; 0x8000], None);
+        assert!(matches!(other.play_movie(&movie), Err(MovieError::WrongRom { .. })));
+        assert!(matches!(Movie::from_bytes(b"SCGBSTAT"), Err(MovieError::NotAMovie)));
+    }
+}
@@ -1,8 +1,25 @@
+use crate::fifo::RendererKind;
 use crate::gb::{GameBoy, InterruptType, Sprite};
+use crate::sgb;
 use std::cmp::max;
-use std::mem::swap;
 
-enum LCDStatus {
+// DMG shades as RGB555, so that DMG and CGB mode share a framebuffer.
+pub const DMG_COLOURS: [u16; 4] = [
+    rgb555(155, 188, 15),
+    rgb555(139, 172, 15),
+    rgb555(48, 98, 48),
+    rgb555(15, 56, 15),
+];
+
+pub const fn rgb555(r: u8, g: u8, b: u8) -> u16 {
+    (r as u16 >> 3) | ((g as u16 >> 3) << 5) | ((b as u16 >> 3) << 10)
+}
+
+// Per pixel of the current line: background colour number in bits 0-1, and in CGB mode the BG map
+// attribute priority bit in bit 7. Decides whether sprites end up in front of the background.
+type BgLine = [u8; 160];
+
+pub(crate) enum LCDStatus {
     PPUModeDrawing,
     PPUModeHBlank,
     PPUModeVBlank,
@@ -11,25 +28,38 @@ enum LCDStatus {
 
 impl GameBoy {
     pub fn renderer(&mut self) {
+        if self.renderer_kind == RendererKind::Fifo {
+            return self.fifo_renderer();
+        }
         let old_stat = self.r.stat;
         if self.clock % 114 == 0 && self.r.ly == 144 {
             self.update_stat(LCDStatus::PPUModeVBlank);
             self.request_interrupt(InterruptType::VBlank);
+            if self.sgb.enabled {
+                self.sgb_vblank();
+            }
         } else if self.clock % 114 == 0 && self.r.ly < 144 {
             self.update_stat(LCDStatus::PPUModeOAMScan);
         } else if self.clock % 114 == 20 && self.r.ly < 144 {
             self.render_scanline();
             self.update_stat(LCDStatus::PPUModeDrawing)
         } else if self.clock % 114 == 63 {
+            let visible_line = self.r.ly < 144;
             self.r.ly += 1;
             self.update_stat(LCDStatus::PPUModeHBlank);
+            if visible_line {
+                self.hblank_dma();
+            }
         } else if self.clock % 114 == 113 && self.r.ly == 154 {
-            self.backbuf_id += 1;
-            self.backbuf_id %= 2;
+            self.end_frame();
             self.r.ly = 0;
             self.window_line_counter = 0;
         }
-        // check if theres any interrupts we need to trigger based on state changes
+        self.request_stat_interrupts(old_stat);
+    }
+
+    // check if theres any interrupts we need to trigger based on state changes
+    pub(crate) fn request_stat_interrupts(&mut self, old_stat: u8) {
         if (((self.r.stat >> 5) & 1) != 0) && ((self.r.stat & 3) == 2) && ((old_stat & 3) != 2) {
             self.request_interrupt(InterruptType::LCD);
         }
@@ -41,7 +71,7 @@ impl GameBoy {
         }
     }
 
-    fn update_stat(&mut self, lcd_status: LCDStatus) {
+    pub(crate) fn update_stat(&mut self, lcd_status: LCDStatus) {
         match lcd_status {
             LCDStatus::PPUModeDrawing => {
                 self.r.stat = (self.r.stat & 0xFC) | 3;
@@ -58,7 +88,24 @@ impl GameBoy {
         }
     }
 
-    fn backbuf(&mut self) -> &mut [u8; 160 * 144] {
+    // Shows the frame just drawn.
+    pub(crate) fn end_frame(&mut self) {
+        if self.finish_frame() {
+            self.backbuf_id += 1;
+            self.backbuf_id %= 2;
+        }
+    }
+
+    // Whether the frame just drawn should be shown, which the SGB can veto.
+    fn finish_frame(&mut self) -> bool {
+        if !self.sgb.enabled {
+            return true;
+        }
+        let (mask, colour_0) = (self.sgb.mask, self.sgb.palettes[0][0]);
+        sgb::mask_frame(mask, colour_0, self.backbuf())
+    }
+
+    pub(crate) fn backbuf(&mut self) -> &mut [u16; 160 * 144] {
         match self.backbuf_id {
             0 => &mut self.displaybuf_0,
             1 => &mut self.displaybuf_1,
@@ -66,7 +113,8 @@ impl GameBoy {
         }
     }
 
-    pub fn display(&self) -> &[u8; 160 * 144] {
+    // The last complete frame, as RGB555.
+    pub fn display(&self) -> &[u16; 160 * 144] {
         match self.backbuf_id {
             0 => &self.displaybuf_1,
             1 => &self.displaybuf_0,
@@ -74,94 +122,140 @@ impl GameBoy {
         }
     }
 
-    fn scan_oam(&mut self) -> Vec<Sprite> {
-        self.oam_base = 0xFE00;
+    // OAM entry `index` (0-39), with the sprite height LCDC currently selects.
+    pub fn oam_sprite(&self, index: u8) -> Sprite {
+        let entry = &self.memory.main[0xFE00 + index as usize * 4..][..4];
+        Sprite {
+            size_y: (((self.r.lcdc >> 2) & 1) + 1) * 8,
+            x: entry[1] as i16 - 8,
+            y: entry[0] as i16 - 16,
+            x_flip: (entry[3] >> 5) & 1,
+            y_flip: (entry[3] >> 6) & 1,
+            pal: (entry[3] >> 4) & 1,
+            priority: (entry[3] >> 7) & 1,
+            tile_num: entry[2],
+            cgb_pal: entry[3] & 7,
+            vram_bank: if self.cgb.enabled { (entry[3] >> 3) & 1 } else { 0 },
+        }
+    }
+
+    // The first 10 sprites in OAM that cover this line, with their OAM index. Sprites off the left
+    // or right edge of the screen still count towards the 10.
+    pub(crate) fn scan_oam(&mut self) -> Vec<(Sprite, u8)> {
         self.num_sprites = 0;
         let mut sprite_buffer = vec![];
-        for _ in 0..40 {
-            let sprite = Sprite {
-                size_y: (((self.r.lcdc >> 2) & 1) + 1) * 8,
-                x: self.oam(1) as i16 - 8,
-                y: self.oam(0) as i16 - 16,
-                x_flip: (self.oam(3) >> 5) & 1,
-                y_flip: (self.oam(3) >> 6) & 1,
-                pal: (self.oam(3) >> 4) & 1,
-                priority: (self.oam(3) >> 7) & 1,
-                tile_num: self.oam(2),
-            };
-            if (sprite.x > 0)
-                && (self.r.ly as i16 >= sprite.y)
+        for index in 0..40 {
+            let sprite = self.oam_sprite(index);
+            if (self.r.ly as i16 >= sprite.y)
                 && ((self.r.ly as i16) < sprite.y + sprite.size_y as i16)
                 && (sprite_buffer.len() < 10)
             {
-                sprite_buffer.push(sprite);
+                sprite_buffer.push((sprite, index));
             }
-            self.oam_base += 4;
         }
         sprite_buffer
     }
 
-    fn render_sprite(&mut self, sprite: Sprite) {
-        let mut tile_num_top = sprite.tile_num;
-        let mut tile_num_bottom = sprite.tile_num;
-
-        if sprite.size_y == 16 {
-            tile_num_top = sprite.tile_num & !1; // The top tile num, For the bottom tile we add 1
-            tile_num_bottom = sprite.tile_num | 1;
+    // Where sprites overlap, on a DMG the one with the lowest X wins, and the one first in OAM if
+    // they have the same X. In CGB mode only the OAM order counts. The winning pixel is the only
+    // one considered, even if the background then hides it.
+    fn render_sprites(&mut self, bg_line: &BgLine) {
+        let mut sprites = self.scan_oam();
+        if !self.cgb.enabled {
+            sprites.sort_by_key(|(sprite, _)| sprite.x);
         }
-
-        if sprite.y_flip != 0 {
-            swap(&mut tile_num_top, &mut tile_num_bottom);
+        let mut taken = [false; 160];
+        for (sprite, _) in sprites {
+            self.render_sprite(sprite, bg_line, &mut taken);
         }
+    }
 
-        let mut tile_addr_top = 0x8000 + tile_num_top as u16 * 16;
-
-        let mut sprite_y = self.r.ly as i16 - sprite.y; // should be non-negative and less than sprite.size_y
-
+    fn render_sprite(&mut self, sprite: Sprite, bg_line: &BgLine, taken: &mut [bool; 160]) {
+        // In 8x16 mode bit 0 of the tile number is ignored, the bottom half is the next tile.
+        let tile_num = if sprite.size_y == 16 { sprite.tile_num & !1 } else { sprite.tile_num };
+        let mut row = self.r.ly as i16 - sprite.y; // should be non-negative and less than sprite.size_y
         if sprite.y_flip != 0 {
-            sprite_y = 7 - sprite_y;
+            row = sprite.size_y as i16 - 1 - row;
         }
+        let tile_addr = 0x8000 + tile_num as u16 * 16 + 2 * row as u16;
 
-        tile_addr_top += 2 * sprite_y as u16;
-
-        let tile_data_top_low_bits = self.read(tile_addr_top);
-        let tile_data_top_high_bits = self.read(tile_addr_top + 1);
+        let tile_data_low_bits = self.vram(sprite.vram_bank, tile_addr);
+        let tile_data_high_bits = self.vram(sprite.vram_bank, tile_addr + 1);
 
-        self.render_sprite_tile(sprite, tile_data_top_low_bits, tile_data_top_high_bits);
-    }
-
-    fn render_sprite_tile(
-        &mut self,
-        sprite: Sprite,
-        tile_data_low_bits: u8,
-        tile_data_high_bits: u8,
-    ) {
         for x in 0..8 {
             let screen_x = sprite.x + x;
-
-            if screen_x < 0 {
+            if !(0..160).contains(&screen_x) || taken[screen_x as usize] {
                 continue;
             }
 
             let shift = if sprite.x_flip != 0 { x } else { 7 - x };
+            let pixel_data_low = (tile_data_low_bits >> shift) & 1;
+            let pixel_data_high = (tile_data_high_bits >> shift) & 1;
+            let colour = pixel_data_low | (pixel_data_high << 1);
+            if colour == 0 {
+                continue;
+            }
+            taken[screen_x as usize] = true;
+
+            let index = (self.r.ly as usize) * 160 + screen_x as usize;
+            // bg_line holds the raw colour number, before BGP, so colour 0 is always behind sprites
+            let bg = bg_line[screen_x as usize];
+            // In CGB mode LCDC bit 0 clear puts sprites in front of everything.
+            let cgb_master_priority = self.cgb.enabled && self.r.lcdc & 1 == 0;
+            let behind_bg = (sprite.priority != 0 || bg & 0x80 != 0) && bg & 3 != 0;
+            if index < 160 * 144 && (cgb_master_priority || !behind_bg) {
+                self.backbuf()[index] = self.sprite_colour(screen_x as usize, sprite, colour);
+            }
+        }
+    }
 
-            let pixel_data_low = (tile_data_low_bits >> (shift)) & 1;
-            let pixel_data_high = (tile_data_high_bits >> (shift)) & 1;
+    pub(crate) fn sprite_colour(&self, x: usize, sprite: Sprite, colour: u8) -> u16 {
+        if self.cgb.enabled {
+            self.cgb_obj_colour(sprite.cgb_pal, colour)
+        } else {
+            let shade = self.map_sprite_palette(sprite.pal, colour);
+            self.dmg_colour(x, shade)
+        }
+    }
 
-            let index = (self.r.ly as usize) * 160 + screen_x as usize; // screen_x should be non-negative
-            let colour = pixel_data_low | (pixel_data_high << 1);
+    pub(crate) fn background_colour(&self, x: usize, attributes: u8, colour: u8) -> u16 {
+        if self.cgb.enabled {
+            self.cgb_bg_colour(attributes & 7, colour)
+        } else {
+            self.dmg_colour(x, self.map_background_palette(colour))
+        }
+    }
 
-            if index < 160 * 144 && colour != 0 {
-                if sprite.priority == 0 {
-                    self.backbuf()[index] = self.map_sprite_palette(sprite.pal, colour);
-                } else if self.backbuf()[index] == 0 {
-                    self.backbuf()[index] = self.map_sprite_palette(sprite.pal, colour);
-                }
-            }
+    fn dmg_colour(&self, x: usize, shade: u8) -> u16 {
+        if self.sgb.enabled {
+            self.sgb_colour(x, shade)
+        } else {
+            DMG_COLOURS[shade as usize]
+        }
+    }
+
+    // Tile number and (in CGB mode) attributes for a BG map entry, and the two bytes of tile data
+    // for row `tile_y` of it, with the attribute flips applied.
+    fn fetch_bg_tile(&mut self, tile_num_addr: u16, tile_y: u8) -> (u8, u8, u8) {
+        let mut tile_num = self.vram(0, tile_num_addr);
+        let attributes = if self.cgb.enabled { self.vram(1, tile_num_addr) } else { 0 };
+        let bank = (attributes >> 3) & 1;
+        let tile_y = if attributes & 0x40 != 0 { 7 - tile_y } else { tile_y };
+
+        let mut tile_data_base = 0;
+        self.select_tile_addressing_method(&mut tile_num, &mut tile_data_base);
+        let tile_addr = tile_data_base + (tile_num as u16) * 16 + 2 * tile_y as u16;
+
+        let mut low = self.vram(bank, tile_addr);
+        let mut high = self.vram(bank, tile_addr + 1);
+        if attributes & 0x20 != 0 {
+            low = low.reverse_bits();
+            high = high.reverse_bits();
         }
+        (attributes, low, high)
     }
 
-    fn render_background(&mut self) {
+    fn render_background(&mut self, bg_line: &mut BgLine) {
         let tilemap_base_addr: u16 = match (self.r.lcdc >> 3) & 1 {
             0 => 0x9800,
             1 => 0x9C00,
@@ -174,34 +268,24 @@ impl GameBoy {
             let x_off: u16 = ((screen_x as u16 + self.r.scx as u16) / 8) & 0x1F;
             let y_off: u16 = 32 * (((self.r.ly as u16 + self.r.scy as u16) & 0xFF) / 8);
             let tile_num_addr = tilemap_base_addr + ((x_off + y_off) & 0x3ff);
-            let mut tile_num = self.read(tile_num_addr);
-
-            let mut tile_addr: u16;
-            let mut tile_data_base = 0;
-            self.select_tile_addressing_method(&mut tile_num, &mut tile_data_base);
-            tile_addr = tile_data_base + (tile_num as u16) * 16;
-
-            tile_addr += 2 * tile_y as u16;
-
-            let tile_data_low = self.read(tile_addr);
-            let tile_data_high = self.read(tile_addr + 1);
+            let (attributes, tile_data_low, tile_data_high) = self.fetch_bg_tile(tile_num_addr, tile_y);
 
             let tile_x = (screen_x + self.r.scx) % 8;
 
             let pixel_data_low = (tile_data_low >> (7 - tile_x)) & 1;
             let pixel_data_high = (tile_data_high >> (7 - tile_x)) & 1;
+            let colour = pixel_data_low | (pixel_data_high << 1);
 
             let index: usize = self.r.ly as usize * 160 + screen_x as usize;
 
             if index < 160 * 144 {
-                self.backbuf()[index] =
-                    self.map_background_palette(pixel_data_low | (pixel_data_high << 1))
+                bg_line[screen_x as usize] = colour | (attributes & 0x80);
+                self.backbuf()[index] = self.background_colour(screen_x as usize, attributes, colour);
             }
         }
     }
 
-    fn render_window(&mut self) {
-        // I think i'm fetching the corect tile data but drawing it to the wrong place
+    fn render_window(&mut self, bg_line: &mut BgLine) {
         if self.r.ly < self.r.wy {
             return;
         }
@@ -212,33 +296,28 @@ impl GameBoy {
             _ => unreachable!(),
         };
 
-        let lb = max(0, self.r.wx as i16 - 7);
+        // the window's left edge, off screen to the left when WX is below 7
+        let window_x = self.r.wx as i16 - 7;
+        let lb = max(0, window_x);
         let mut drew_pixels = false;
         for screen_x in lb..160 {
             drew_pixels = true;
             let index: usize = (self.r.ly as usize) * 160 + screen_x as usize;
-            let x_off: u16 = (((screen_x - lb) as u16) / 8) & 0x1f;
+            let x_off: u16 = (((screen_x - window_x) as u16) / 8) & 0x1f;
             let y_off: u16 = 32 * ((self.window_line_counter as u16) / 8);
             let tile_num_addr = tilemap_base_addr + ((x_off + y_off) & 0x3ff);
-            let mut tile_num = self.read(tile_num_addr);
-
-            let mut tile_addr: u16;
-            let mut tile_data_base = 0;
-            self.select_tile_addressing_method(&mut tile_num, &mut tile_data_base);
-            tile_addr = tile_data_base + (tile_num as u16) * 16;
-            tile_addr += 2 * (self.window_line_counter as u16 % 8);
+            let (attributes, tile_data_low, tile_data_high) =
+                self.fetch_bg_tile(tile_num_addr, self.window_line_counter % 8);
 
-            let tile_data_low = self.read(tile_addr);
-            let tile_data_high = self.read(tile_addr + 1);
-
-            let tile_x = (screen_x) % 8;
+            let tile_x = (screen_x - window_x) % 8;
 
             let pixel_data_low = (tile_data_low >> (7 - tile_x)) & 1;
             let pixel_data_high = (tile_data_high >> (7 - tile_x)) & 1;
+            let colour = pixel_data_low | (pixel_data_high << 1);
 
             if index < 160 * 144 {
-                self.backbuf()[index] =
-                    self.map_background_palette(pixel_data_low | (pixel_data_high << 1))
+                bg_line[screen_x as usize] = colour | (attributes & 0x80);
+                self.backbuf()[index] = self.background_colour(screen_x as usize, attributes, colour);
             }
         }
         if drew_pixels {
@@ -246,7 +325,7 @@ impl GameBoy {
         }
     }
 
-    fn select_tile_addressing_method(&mut self, tile_num: &mut u8, tile_data_base: &mut u16) {
+    pub(crate) fn select_tile_addressing_method(&self, tile_num: &mut u8, tile_data_base: &mut u16) {
         match self.r.lcdc & 0x10 {
             0 => {
                 if *tile_num <= 127 {
@@ -267,18 +346,117 @@ impl GameBoy {
             return;
         }
 
-        if (self.r.lcdc & 1) != 0 {
-            self.render_background();
+        let mut bg_line: BgLine = [0; 160];
+        // On a DMG LCDC bit 0 turns the background and window off, in CGB mode it only takes away
+        // their priority over sprites.
+        if (self.r.lcdc & 1) != 0 || self.cgb.enabled {
+            self.render_background(&mut bg_line);
             if (self.r.lcdc >> 5) & 1 != 0 {
-                self.render_window();
+                self.render_window(&mut bg_line);
+            }
+        } else {
+            let line = self.r.ly as usize * 160;
+            if line < 160 * 144 {
+                for x in 0..160 {
+                    self.backbuf()[line + x] = self.dmg_colour(x, 0);
+                }
             }
         }
 
         if ((self.r.lcdc >> 1) & 1) != 0 {
-            let sprites = self.scan_oam();
-            for sprite in sprites {
-                self.render_sprite(sprite)
-            }
+            self.render_sprites(&bg_line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DMG_COLOURS;
+    use crate::gb;
+
+    // Tile 1 is solid colour 3, tile 2 solid colour 1, and the background is tile 0.
+    fn sprite_gameboy() -> gb::GameBoy {
+        let mut gameboy = gb::init();
+        gameboy.r.lcdc = 0x93;
+        gameboy.r.bg_pal = 0xE4;
+        gameboy.r.obp0 = 0xE4;
+        gameboy.memory.main[0x8010..0x8020].fill(0xFF);
+        for row in 0..8 {
+            gameboy.memory.main[0x8020 + row * 2] = 0xFF;
+        }
+        gameboy
+    }
+
+    fn set_sprite(gameboy: &mut gb::GameBoy, index: usize, oam: [u8; 4]) {
+        gameboy.memory.main[0xFE00 + index * 4..0xFE04 + index * 4].copy_from_slice(&oam);
+    }
+
+    fn line_0(gameboy: &mut gb::GameBoy) -> Vec<u16> {
+        gameboy.r.ly = 0;
+        gameboy.render_scanline();
+        gameboy.backbuf()[..160].to_vec()
+    }
+
+    #[test]
+    fn lower_x_wins_where_sprites_overlap() {
+        let mut gameboy = sprite_gameboy();
+        set_sprite(&mut gameboy, 0, [16, 8 + 16, 1, 0]);
+        set_sprite(&mut gameboy, 1, [16, 8 + 20, 2, 0]);
+        set_sprite(&mut gameboy, 2, [16, 8 + 60, 2, 0]);
+        set_sprite(&mut gameboy, 3, [16, 8 + 56, 1, 0]);
+        let line = line_0(&mut gameboy);
+        assert_eq!(line[16..24], [DMG_COLOURS[3]; 8]);
+        assert_eq!(line[24..28], [DMG_COLOURS[1]; 4]);
+        assert_eq!(line[56..64], [DMG_COLOURS[3]; 8]);
+    }
+
+    #[test]
+    fn tall_sprites_use_both_tiles() {
+        let mut gameboy = sprite_gameboy();
+        gameboy.r.lcdc |= 0x04;
+        // line 0 is row 8 of the sprite, the bottom tile. Bit 0 of the tile number is ignored.
+        set_sprite(&mut gameboy, 0, [8, 8, 3, 0]);
+        gameboy.memory.main[0x8030..0x8040].fill(0xFF);
+        assert_eq!(line_0(&mut gameboy)[..8], [DMG_COLOURS[3]; 8]);
+
+        // flipped, row 8 comes from row 7 of the top tile
+        set_sprite(&mut gameboy, 0, [8, 8, 3, 0x40]);
+        assert_eq!(line_0(&mut gameboy)[..8], [DMG_COLOURS[1]; 8]);
+    }
+
+    #[test]
+    fn bg_priority_uses_the_colour_number() {
+        let mut gameboy = sprite_gameboy();
+        // background colour 1, which BGP shows as white
+        gameboy.r.bg_pal = 0xE0;
+        for row in 0..8 {
+            gameboy.memory.main[0x8000 + row * 2] = 0xFF;
         }
+        set_sprite(&mut gameboy, 0, [16, 8, 1, 0x80]);
+        set_sprite(&mut gameboy, 1, [16, 8 + 8, 1, 0]);
+        let line = line_0(&mut gameboy);
+        assert_eq!(line[..8], [DMG_COLOURS[0]; 8]);
+        assert_eq!(line[8..16], [DMG_COLOURS[3]; 8]);
+    }
+
+    #[test]
+    fn window_tiles_start_at_wx() {
+        let mut gameboy = sprite_gameboy();
+        // window on, from the 9C00 map, 3 pixels in
+        gameboy.r.lcdc |= 0x60;
+        gameboy.r.wx = 7 + 3;
+        // tile 3 is colour 3 on its left half
+        gameboy.memory.main[0x8030..0x8040].fill(0xF0);
+        gameboy.memory.main[0x9C00] = 3;
+        let line = line_0(&mut gameboy);
+        assert_eq!(line[..3], [DMG_COLOURS[0]; 3]);
+        assert_eq!(line[3..7], [DMG_COLOURS[3]; 4]);
+        assert_eq!(line[7..11], [DMG_COLOURS[0]; 4]);
+
+        // WX below 7 cuts off the left of the first tile
+        gameboy.r.wx = 7 - 2;
+        let line = line_0(&mut gameboy);
+        assert_eq!(line[..2], [DMG_COLOURS[3]; 2]);
+        assert_eq!(line[2..6], [DMG_COLOURS[0]; 4]);
     }
 }
@@ -0,0 +1,241 @@
+// Rewinding: a snapshot of the GameBoy every few frames going back up to a minute, and the keys
+// pressed since the oldest one, so that any frame in between can be got back to by loading the
+// snapshot before it and replaying the keys. Snapshots are save states, so the cartridge ROM isn't in
+// them. Only the newest is kept whole, each older one is stored as the bytes that differ from the one
+// after it, which is mostly the framebuffers and a few pages of RAM.
+use crate::gb::{GameBoy, KeyEvent};
+use crate::runner::M_CYCLES_PER_FRAME;
+use std::collections::VecDeque;
+use std::mem;
+
+const FRAME: u128 = M_CYCLES_PER_FRAME as u128;
+const FRAMES_PER_SNAPSHOT: u128 = 10;
+// longest run of same or different bytes in a delta
+const MAX_RUN: usize = u16::MAX as usize;
+
+struct Snapshot {
+    clock: u128,
+    // a save state for the newest snapshot, a delta from the next newer one for the rest
+    data: Vec<u8>,
+}
+
+pub struct Rewind {
+    capacity: usize,
+    newest: Option<Snapshot>,
+    // oldest first
+    older: VecDeque<Snapshot>,
+    // key presses and releases since the oldest snapshot
+    keys: VecDeque<KeyEvent>,
+}
+
+impl Rewind {
+    pub fn new(seconds: u32) -> Self {
+        Self {
+            // one more snapshot than fits in the time, which is the span between the oldest and newest
+            capacity: (seconds as usize * 60).div_ceil(FRAMES_PER_SNAPSHOT as usize) + 1,
+            newest: None,
+            older: VecDeque::new(),
+            keys: VecDeque::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.older.clear();
+        self.keys.clear();
+    }
+
+    fn oldest_clock(&self) -> Option<u128> {
+        self.older.front().or(self.newest.as_ref()).map(|snapshot| snapshot.clock)
+    }
+
+    // How many frames back it can go.
+    pub fn frames(&self, gameboy: &GameBoy) -> u128 {
+        self.oldest_clock().map_or(0, |oldest| gameboy.clock.saturating_sub(oldest) / FRAME)
+    }
+
+    // Call after running the GameBoy, e.g. once a frame, with what take_key_events() returned.
+    pub fn record(&mut self, gameboy: &GameBoy, keys: &[KeyEvent]) {
+        // after loading an older save state the history belongs to another timeline
+        if self.newest.as_ref().is_some_and(|newest| gameboy.clock < newest.clock) {
+            self.clear();
+        }
+        self.keys.extend(keys);
+        let due = self.newest.as_ref().map_or(true, |newest| gameboy.clock >= newest.clock + FRAMES_PER_SNAPSHOT * FRAME);
+        if due {
+            self.snapshot(gameboy);
+        }
+    }
+
+    fn snapshot(&mut self, gameboy: &GameBoy) {
+        let state = gameboy.save_state();
+        if let Some(newest) = self.newest.take() {
+            let delta = encode_delta(&state, &newest.data);
+            self.older.push_back(Snapshot { clock: newest.clock, data: delta });
+        }
+        self.newest = Some(Snapshot { clock: gameboy.clock, data: state });
+        while self.older.len() >= self.capacity {
+            self.older.pop_front();
+        }
+        let oldest = self.oldest_clock().unwrap();
+        while self.keys.front().is_some_and(|key| key.clock < oldest) {
+            self.keys.pop_front();
+        }
+    }
+
+    // Goes back one frame, or to the oldest snapshot if that's less than a frame back. Returns false
+    // when there's nothing left to go back to.
+    pub fn rewind_frame(&mut self, gameboy: &mut GameBoy) -> bool {
+        // keys pressed since the last record() are ahead of where this goes back to
+        gameboy.take_key_events();
+        let Some(oldest) = self.oldest_clock() else { return false };
+        if gameboy.clock <= oldest {
+            return false;
+        }
+        let target = gameboy.clock.saturating_sub(FRAME).max(oldest);
+        while self.newest.as_ref().is_some_and(|newest| newest.clock > target) {
+            let newer = self.newest.take().unwrap();
+            self.newest = self.older.pop_back().map(|older| Snapshot {
+                clock: older.clock,
+                data: apply_delta(&newer.data, &older.data),
+            });
+        }
+        // play carries on from the target, so anything pressed after it never happened
+        while self.keys.back().is_some_and(|key| key.clock >= target) {
+            self.keys.pop_back();
+        }
+
+        let newest = self.newest.as_ref().unwrap();
+        if gameboy.load_state(&newest.data).is_err() {
+            self.clear();
+            return false;
+        }
+        self.replay(gameboy, target);
+        true
+    }
+
+    // Runs from the snapshot just loaded up to `clock`, pressing keys when they were pressed before.
+    fn replay(&self, gameboy: &mut GameBoy, clock: u128) {
+        // going over old ground, so no breakpoints, and the keys are logged already
+        let debugger = mem::take(&mut gameboy.debugger);
+        let key_log = gameboy.key_log.take();
+        let start = gameboy.clock;
+        let mut keys = self.keys.iter().skip_while(|key| key.clock < start).peekable();
+        while gameboy.clock < clock {
+            while let Some(key) = keys.next_if(|key| key.clock <= gameboy.clock) {
+                match key.pressed {
+                    true => gameboy.press_player_key(key.player, key.key),
+                    false => gameboy.unpress_player_key(key.player, key.key),
+                }
+            }
+            gameboy.tick();
+        }
+        gameboy.debugger = debugger;
+        gameboy.key_log = key_log;
+    }
+}
+
+// `to` as the length of `to`, then alternating u16 counts of bytes the same as in `from` and of bytes
+// that aren't, each count of changed bytes followed by the bytes.
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut delta = (to.len() as u32).to_le_bytes().to_vec();
+    let same = |i: usize| from.get(i) == Some(&to[i]);
+    let mut i = 0;
+    while i < to.len() {
+        let start = i;
+        while i < to.len() && i - start < MAX_RUN && same(i) {
+            i += 1;
+        }
+        delta.extend_from_slice(&((i - start) as u16).to_le_bytes());
+        let start = i;
+        while i < to.len() && i - start < MAX_RUN && !same(i) {
+            i += 1;
+        }
+        delta.extend_from_slice(&((i - start) as u16).to_le_bytes());
+        delta.extend_from_slice(&to[start..i]);
+    }
+    delta
+}
+
+fn apply_delta(from: &[u8], delta: &[u8]) -> Vec<u8> {
+    let len = u32::from_le_bytes(delta[0..4].try_into().unwrap()) as usize;
+    let mut to = Vec::with_capacity(len);
+    let count = |pos: usize| u16::from_le_bytes([delta[pos], delta[pos + 1]]) as usize;
+    let mut pos = 4;
+    while to.len() < len {
+        let same = count(pos);
+        to.extend_from_slice(&from[to.len()..to.len() + same]);
+        let changed = count(pos + 2);
+        to.extend_from_slice(&delta[pos + 4..pos + 4 + changed]);
+        pos += 4 + changed;
+    }
+    to
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::load_rom;
+
+    #[test]
+    fn deltas_round_trip() {
+        let from: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+        let mut to = from.clone();
+        to[5] = 0;
+        to[70_000..140_000].fill(0xAA);
+        to.truncate(150_000);
+        assert_eq!(apply_delta(&from, &encode_delta(&from, &to)), to);
+        assert_eq!(apply_delta(&to, &encode_delta(&to, &from)), from);
+        // unchanged is just the length and a pair of counts per 64 KiB
+        assert_eq!(encode_delta(&from, &from).len(), 4 + 4 * 4);
+    }
+
+    #[test]
+    fn rewinds_to_every_frame() {
+        let mut rom = vec![0; 0x8000];
+        // 0x100: inc a; ld (hl), a; inc l; jr -5
+        rom[0x100..0x105].copy_from_slice(&[0x3C, 0x77, 0x2C, 0x18, 0xFB]);
+        let mut gameboy = load_rom(rom, None);
+        gameboy.set_hl(0xC000);
+        let mut rewind = Rewind::new(60);
+        let keys = gameboy.take_key_events();
+        rewind.record(&gameboy, &keys);
+
+        let mut states = vec![];
+        for frame in 0..25 {
+            states.push(gameboy.save_state());
+            if frame == 13 {
+                gameboy.press_player_key(0, 3);
+            }
+            if frame == 17 {
+                gameboy.unpress_player_key(0, 3);
+            }
+            for _ in 0..M_CYCLES_PER_FRAME {
+                gameboy.tick();
+            }
+            let keys = gameboy.take_key_events();
+            rewind.record(&gameboy, &keys);
+        }
+        assert_eq!(rewind.frames(&gameboy), 25);
+
+        for frame in (0..25).rev() {
+            assert!(rewind.rewind_frame(&mut gameboy));
+            assert!(gameboy.save_state() == states[frame], "frame {frame} came back different");
+        }
+        assert!(!rewind.rewind_frame(&mut gameboy));
+    }
+
+    #[test]
+    fn keeps_only_so_many_seconds() {
+        let mut gameboy = load_rom(vec![0; 0x8000], None);
+        let mut rewind = Rewind::new(1);
+        for _ in 0..200 {
+            for _ in 0..M_CYCLES_PER_FRAME {
+                gameboy.tick();
+            }
+            rewind.record(&gameboy, &[]);
+        }
+        // a second, and the frames since the newest snapshot
+        assert!((60..70).contains(&rewind.frames(&gameboy)));
+    }
+}
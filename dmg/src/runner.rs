@@ -0,0 +1,223 @@
+// Running a ROM without a frontend, for scripts and CI: a frame or cycle limit, scripted input, and
+// a success condition checked once a frame. The scgb-headless binary is a command line around this.
+use crate::gb::{self, GameBoy};
+use crate::memory::GB_ROM_SIZE;
+use crate::movie::MoviePlayer;
+use crate::serial::Recorder;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+pub const M_CYCLES_PER_FRAME: u64 = 17556;
+
+// In key id order, as used by press_key
+pub const KEY_NAMES: [&str; 8] = ["a", "b", "select", "start", "right", "left", "up", "down"];
+
+// Puts a ROM in a fresh GameBoy. Without a boot ROM it starts straight at the cartridge entry point.
+pub fn load_rom(rom: Vec<u8>, boot_rom: Option<&[u8]>) -> GameBoy {
+    let mut gameboy = gb::init();
+    gameboy.memory.cartridge = rom;
+    gameboy.mbc = gameboy.detect_mbc();
+    let cart_decode = gameboy.decode_cart_header();
+    gameboy.logger.log_info(&format!("{cart_decode:?}"));
+    match boot_rom {
+        Some(boot_rom) => {
+            let len = boot_rom.len().min(GB_ROM_SIZE);
+            gameboy.memory.boot_rom[..len].copy_from_slice(&boot_rom[..len]);
+        }
+        None => gameboy.skip_boot_rom(),
+    }
+    if gameboy.cartridge_supports_cgb() {
+        gameboy.start_cgb_mode();
+    } else if gameboy.cartridge_supports_sgb() {
+        gameboy.start_sgb_mode();
+    }
+    gameboy
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InputEvent {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+// One event per line, `<frame> <press|release> <key>`, e.g. `60 press start`. Blank lines and
+// lines starting with # are skipped.
+pub fn parse_input_script(script: &str) -> Result<Vec<InputEvent>, String> {
+    let mut events = vec![];
+    for (number, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {message}: {line}", number + 1);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let [frame, action, key] = words[..] else {
+            return Err(error("expected <frame> <press|release> <key>"));
+        };
+        let frame = frame.parse().map_err(|_| error("not a frame number"))?;
+        let pressed = match action {
+            "press" => true,
+            "release" => false,
+            _ => return Err(error("expected press or release")),
+        };
+        let key = KEY_NAMES
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(key))
+            .ok_or_else(|| error("unknown key"))? as u8;
+        events.push(InputEvent { frame, key, pressed });
+    }
+    events.sort_by_key(|event| event.frame);
+    Ok(events)
+}
+
+pub enum Condition {
+    // the ROM has printed this over the serial port
+    Serial(String),
+    // the byte at this address has this value
+    Memory { address: u16, value: u8 },
+}
+
+pub struct Runner {
+    pub gameboy: GameBoy,
+    serial: Recorder,
+    input: Vec<InputEvent>,
+    next_input: usize,
+    movie: Option<MoviePlayer>,
+    pub cycles: u64,
+}
+
+impl Runner {
+    // Plugs a recorder into the link port, so the serial output can be checked.
+    pub fn new(mut gameboy: GameBoy, echo_serial: bool) -> Self {
+        let mut serial = Recorder::default();
+        serial.echo = echo_serial;
+        gameboy.connect_serial(Box::new(serial.clone()));
+        Self {
+            gameboy,
+            serial,
+            input: vec![],
+            next_input: 0,
+            movie: None,
+            cycles: 0,
+        }
+    }
+
+    pub fn with_input(mut self, input: Vec<InputEvent>) -> Self {
+        self.input = input;
+        self
+    }
+
+    // The movie's initial state should be loaded already, see GameBoy::play_movie.
+    pub fn with_movie(mut self, movie: MoviePlayer) -> Self {
+        self.movie = Some(movie);
+        self
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.cycles / M_CYCLES_PER_FRAME
+    }
+
+    pub fn serial_output(&self) -> String {
+        String::from_utf8_lossy(&self.serial.output()).into_owned()
+    }
+
+    pub fn check(&self, condition: &Condition) -> bool {
+        match condition {
+            Condition::Serial(text) => self.serial_output().contains(text.as_str()),
+            Condition::Memory { address, value } => self.gameboy.peek(*address) == *value,
+        }
+    }
+
+    // Runs until the condition holds or `max_cycles` M-cycles in total have run. Returns whether
+    // the condition held, or true if there isn't one.
+    pub fn run(&mut self, max_cycles: u64, until: Option<&Condition>) -> bool {
+        while self.cycles < max_cycles {
+            if self.cycles % M_CYCLES_PER_FRAME == 0 {
+                self.apply_input();
+                if until.is_some_and(|condition| self.check(condition)) {
+                    return true;
+                }
+            }
+            if let Some(movie) = &mut self.movie {
+                movie.apply(&mut self.gameboy);
+            }
+            self.gameboy.tick();
+            self.cycles += 1;
+        }
+        until.map_or(true, |condition| self.check(condition))
+    }
+
+    fn apply_input(&mut self) {
+        let frame = self.frames();
+        while let Some(event) = self.input.get(self.next_input).filter(|event| event.frame <= frame) {
+            if event.pressed {
+                self.gameboy.press_key(event.key);
+            } else {
+                self.gameboy.unpress_key(event.key);
+            }
+            self.next_input += 1;
+        }
+    }
+}
+
+// The last complete frame as an RGB PNG, with the SGB border if there is one.
+pub fn save_screenshot(gameboy: &GameBoy, path: &Path) -> io::Result<()> {
+    let (pixels, width, height) = match gameboy.sgb_frame() {
+        Some(frame) => (frame, crate::sgb::BORDER_WIDTH, crate::sgb::BORDER_HEIGHT),
+        None => (gameboy.display().to_vec(), 160, 144),
+    };
+    let expand = |channel: u16| ((channel << 3) | (channel >> 2)) as u8;
+    let rgb: Vec<u8> = pixels
+        .iter()
+        .flat_map(|&pixel| [expand(pixel & 0x1F), expand((pixel >> 5) & 0x1F), expand((pixel >> 10) & 0x1F)])
+        .collect();
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&rgb)?;
+    writer.finish()?;
+    Ok(())
+}
+
+// CRC32 of the last complete frame, to check a run ends on the same picture without keeping a PNG.
+pub fn frame_hash(gameboy: &GameBoy) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for pixel in gameboy.display() {
+        hasher.update(&pixel.to_le_bytes());
+    }
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_script() {
+        let events = parse_input_script("# title screen\n120 release START\n\n60 press start\n").unwrap();
+        assert_eq!(
+            events,
+            [
+                InputEvent { frame: 60, key: 3, pressed: true },
+                InputEvent { frame: 120, key: 3, pressed: false },
+            ]
+        );
+        assert!(parse_input_script("60 press turbo").unwrap_err().starts_with("line 1"));
+    }
+
+    #[test]
+    fn stops_once_the_condition_holds() {
+        let mut rom = vec![0; 0x8000];
+        // 0x100: ld a, 0x42; ld (0xC000), a; jr -2
+        rom[0x100..0x107].copy_from_slice(&[0x3E, 0x42, 0xEA, 0x00, 0xC0, 0x18, 0xFE]);
+        let mut runner = Runner::new(load_rom(rom, None), false);
+        let condition = Condition::Memory { address: 0xC000, value: 0x42 };
+        assert!(runner.run(100 * M_CYCLES_PER_FRAME, Some(&condition)));
+        assert_eq!(runner.frames(), 1);
+    }
+}
@@ -0,0 +1,143 @@
+// Battery-backed cartridge RAM. The .sav format is just the raw contents of the cartridge RAM,
+// which is what most other emulators read and write too. Cartridges with an MBC3 RTC get the
+// clock state appended as a 48 byte trailer, see mbc::rtc.
+use crate::gb::GameBoy;
+use crate::mbc::rtc::{RTC_SAVE_SIZE, RTC_SAVE_SIZE_LEGACY};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// The .sav file that belongs to a ROM lives next to it, e.g. `pokered.gb` -> `pokered.sav`.
+pub fn sav_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sav")
+}
+
+impl GameBoy {
+    pub fn has_battery(&self) -> bool {
+        self.mbc.has_battery && (self.mbc.has_ram || self.mbc.has_rtc)
+    }
+
+    pub fn sram_dirty(&self) -> bool {
+        self.mbc.ram_dirty
+    }
+
+    pub fn export_sram(&self) -> Vec<u8> {
+        self.memory.switchable_ram.clone()
+    }
+
+    pub fn import_sram(&mut self, data: &[u8]) {
+        let ram = &mut self.memory.switchable_ram;
+        if data.len() != ram.len() {
+            self.logger.log_warning(&format!(
+                "SRAM size mismatch: cartridge has {:#x} bytes, save has {:#x}",
+                ram.len(),
+                data.len()
+            ));
+        }
+        let len = ram.len().min(data.len());
+        ram[..len].copy_from_slice(&data[..len]);
+        self.mbc.ram_dirty = false;
+    }
+
+    // SRAM plus the RTC trailer if the cartridge has one, i.e. the contents of a .sav file.
+    pub fn export_save(&self) -> Vec<u8> {
+        let mut save = self.export_sram();
+        if self.mbc.has_rtc {
+            save.extend_from_slice(&self.mbc.rtc.to_save());
+        }
+        save
+    }
+
+    pub fn import_save(&mut self, data: &[u8]) {
+        let sram_len = self.memory.switchable_ram.len();
+        let trailer_len = data.len().saturating_sub(sram_len);
+        if self.mbc.has_rtc && (trailer_len == RTC_SAVE_SIZE || trailer_len == RTC_SAVE_SIZE_LEGACY) {
+            self.mbc.rtc.restore_save(&data[sram_len..]);
+            self.import_sram(&data[..sram_len]);
+        } else {
+            self.import_sram(data);
+        }
+    }
+
+    // Returns Ok(false) if there is no save file yet, which is normal the first time a game is played.
+    pub fn load_sav(&mut self, path: &Path) -> io::Result<bool> {
+        match fs::read(path) {
+            Ok(data) => {
+                self.import_save(&data);
+                Ok(true)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save_sav(&mut self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.export_save())?;
+        self.mbc.ram_dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gb::GameBoy;
+    use crate::runner::load_rom;
+
+    // MBC1 + RAM + battery with 8 KiB of RAM
+    fn battery_cartridge() -> GameBoy {
+        let mut rom = vec![0; 0x8000];
+        rom[0x147] = 0x03;
+        rom[0x149] = 0x02;
+        load_rom(rom, None)
+    }
+
+    #[test]
+    fn sram_writes_set_dirty_only_when_enabled() {
+        let mut gameboy = battery_cartridge();
+        assert!(gameboy.has_battery());
+        gameboy.write(0xA000, 0x12);
+        assert!(!gameboy.sram_dirty());
+        assert_eq!(gameboy.export_sram()[0], 0);
+
+        gameboy.write(0x0000, 0x0A);
+        gameboy.write(0xA000, 0x12);
+        assert!(gameboy.sram_dirty());
+
+        gameboy.write(0x0000, 0x00);
+        gameboy.mbc.ram_dirty = false;
+        gameboy.write(0xA001, 0x34);
+        assert!(!gameboy.sram_dirty());
+    }
+
+    #[test]
+    fn sav_round_trip() {
+        let path = std::env::temp_dir().join(format!("scgb-save-test-{}.sav", std::process::id()));
+        let mut gameboy = battery_cartridge();
+        gameboy.write(0x0000, 0x0A);
+        gameboy.write(0xA000, 0x12);
+        gameboy.write(0xBFFF, 0x34);
+        gameboy.save_sav(&path).unwrap();
+        assert!(!gameboy.sram_dirty());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0x2000);
+
+        let mut loaded = battery_cartridge();
+        assert!(loaded.load_sav(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.export_sram(), gameboy.export_sram());
+        assert!(!loaded.sram_dirty());
+        // no save file yet isn't an error
+        assert!(!loaded.load_sav(&path).unwrap());
+    }
+
+    #[test]
+    fn wrong_size_sav_loads_what_fits() {
+        let mut gameboy = battery_cartridge();
+        gameboy.import_save(&[0xAA; 0x100]);
+        let sram = gameboy.export_sram();
+        assert_eq!(sram.len(), 0x2000);
+        assert!(sram[..0x100].iter().all(|&b| b == 0xAA));
+        assert!(sram[0x100..].iter().all(|&b| b == 0));
+
+        gameboy.import_save(&[0x55; 0x3000]);
+        assert_eq!(gameboy.export_sram(), vec![0x55; 0x2000]);
+    }
+}
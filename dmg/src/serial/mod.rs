@@ -0,0 +1,348 @@
+// Serial port (SB 0xFF01, SC 0xFF02). Whatever is plugged into the link port is a SerialDevice.
+// Transfers are done a byte at a time: with the internal clock the byte is exchanged with the
+// device once all 8 bits have been clocked out, with the external clock the device decides when
+// the other end has clocked a byte through.
+use crate::gb::{GameBoy, InterruptType};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+pub mod printer;
+pub mod tcp;
+
+const TRANSFER_START: u8 = 1 << 7;
+const INTERNAL_CLOCK: u8 = 1 << 0;
+
+pub trait SerialDevice {
+    // This GameBoy is the clock master and has just shifted `byte` out. Returns the byte shifted in.
+    fn transfer(&mut self, byte: u8) -> u8;
+
+    // Like transfer, for devices that can't always answer straight away. With None the transfer
+    // stays open and finish_transfer is called before each tick until it returns the byte.
+    fn start_transfer(&mut self, byte: u8) -> Option<u8> {
+        Some(self.transfer(byte))
+    }
+
+    fn finish_transfer(&mut self) -> Option<u8> {
+        Some(0xFF)
+    }
+
+    // Whether the GameBoy has to wait for the other end before its next tick. Called before every
+    // tick, for devices that keep time with another emulator without blocking.
+    fn stalled(&mut self) -> bool {
+        false
+    }
+
+    // This GameBoy is waiting on an external clock with `byte` in SB. Called every M-cycle until it
+    // returns the byte the other end shifted in.
+    fn poll_external(&mut self, _byte: u8) -> Option<u8> {
+        None
+    }
+
+    // Called every M-cycle, for devices that need to keep time with the GameBoy.
+    fn tick(&mut self) {}
+
+    // Something the user should be told about, e.g. that the cable came unplugged. Frontends poll it.
+    fn take_status(&mut self) -> Option<String> {
+        None
+    }
+}
+
+// Nothing plugged in. The input line floats high, and no external clock ever arrives.
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn transfer(&mut self, _byte: u8) -> u8 {
+        0xFF
+    }
+}
+
+// Prints every byte sent to stdout, which is how test ROMs like Blargg's report their results.
+pub struct StdoutSink;
+
+impl SerialDevice for StdoutSink {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(&[byte]);
+        let _ = stdout.flush();
+        0xFF
+    }
+}
+
+// Keeps every byte sent, so what a test ROM printed can be checked afterwards. Clones share the
+// same output.
+#[derive(Clone, Default)]
+pub struct Recorder {
+    output: Rc<RefCell<Vec<u8>>>,
+    // also print it, like StdoutSink
+    pub echo: bool,
+}
+
+impl Recorder {
+    pub fn output(&self) -> Vec<u8> {
+        self.output.borrow().clone()
+    }
+}
+
+impl SerialDevice for Recorder {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        self.output.borrow_mut().push(byte);
+        if self.echo {
+            StdoutSink.transfer(byte);
+        }
+        0xFF
+    }
+}
+
+// Output wired straight back to input, every byte sent is received again.
+pub struct Loopback;
+
+impl SerialDevice for Loopback {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        byte
+    }
+}
+
+// A virtual link cable between two GameBoys in the same process. Both ends need to be ticked in
+// lockstep (one M-cycle each, alternately) for the timing to line up.
+#[derive(Default)]
+struct LinkCable {
+    // SB of each end while it is waiting on an external clock
+    waiting: [Option<u8>; 2],
+    // byte clocked into each end by the other, not yet picked up
+    received: [Option<u8>; 2],
+}
+
+pub struct LinkPort {
+    cable: Rc<RefCell<LinkCable>>,
+    end: usize,
+}
+
+pub fn link_cable() -> (LinkPort, LinkPort) {
+    let cable = Rc::new(RefCell::new(LinkCable::default()));
+    (
+        LinkPort { cable: Rc::clone(&cable), end: 0 },
+        LinkPort { cable, end: 1 },
+    )
+}
+
+impl SerialDevice for LinkPort {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let mut cable = self.cable.borrow_mut();
+        let other = 1 - self.end;
+        cable.waiting[self.end] = None;
+        match cable.waiting[other].take() {
+            Some(their_byte) => {
+                cable.received[other] = Some(byte);
+                their_byte
+            }
+            // the other end isn't listening, so its output line stays high
+            None => 0xFF,
+        }
+    }
+
+    fn poll_external(&mut self, byte: u8) -> Option<u8> {
+        let mut cable = self.cable.borrow_mut();
+        let received = cable.received[self.end].take();
+        cable.waiting[self.end] = match received {
+            Some(_) => None,
+            None => Some(byte),
+        };
+        received
+    }
+}
+
+fn disconnected() -> Box<dyn SerialDevice> {
+    Box::new(Disconnected)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Serial {
+    pub sb: u8,
+    pub sc: u8,
+    bits_shifted: u8,
+    #[serde(skip, default = "disconnected")]
+    pub device: Box<dyn SerialDevice>,
+    // the device hasn't answered the transfer yet, see SerialDevice::start_transfer
+    #[serde(skip)]
+    pending: bool,
+    // the last tick waited on the device instead of running
+    #[serde(skip)]
+    stalled: bool,
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self {
+            sb: 0,
+            sc: 0,
+            bits_shifted: 0,
+            device: disconnected(),
+            pending: false,
+            stalled: false,
+        }
+    }
+}
+
+impl Serial {
+    pub fn read_sc(&self) -> u8 {
+        self.sc | 0x7E
+    }
+
+    pub fn write_sc(&mut self, data: u8) {
+        self.sc = data & (TRANSFER_START | INTERNAL_CLOCK);
+        self.bits_shifted = 0;
+    }
+
+    fn transferring(&self, internal_clock: bool) -> bool {
+        self.sc & TRANSFER_START != 0 && (self.sc & INTERNAL_CLOCK != 0) == internal_clock
+    }
+
+    fn finish(&mut self, received: u8) {
+        self.sb = received;
+        self.sc &= !TRANSFER_START;
+        self.bits_shifted = 0;
+    }
+
+    // Returns true when a transfer completed.
+    fn clock_internal(&mut self) -> bool {
+        if !self.transferring(true) || self.pending {
+            return false;
+        }
+        self.bits_shifted += 1;
+        if self.bits_shifted < 8 {
+            return false;
+        }
+        match self.device.start_transfer(self.sb) {
+            Some(received) => {
+                self.finish(received);
+                true
+            }
+            None => {
+                self.pending = true;
+                false
+            }
+        }
+    }
+
+    fn poll_external(&mut self) -> bool {
+        if !self.transferring(false) {
+            return false;
+        }
+        match self.device.poll_external(self.sb) {
+            Some(received) => {
+                self.finish(received);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl GameBoy {
+    pub fn connect_serial(&mut self, device: Box<dyn SerialDevice>) {
+        self.serial.device = device;
+    }
+
+    pub fn take_serial_status(&mut self) -> Option<String> {
+        self.serial.device.take_status()
+    }
+
+    // Whether the last tick didn't run because the link cable is waiting on the other end. A frontend
+    // running the GameBoy on its UI thread can stop there and try again next repaint.
+    pub fn waiting_on_link(&self) -> bool {
+        self.serial.stalled
+    }
+
+    // Called at the start of every tick. Returns whether the tick has to wait for the device.
+    pub(crate) fn serial_stalled(&mut self) -> bool {
+        if self.serial.pending {
+            let Some(received) = self.serial.device.finish_transfer() else {
+                self.serial.stalled = true;
+                return true;
+            };
+            self.serial.pending = false;
+            self.serial.finish(received);
+            self.request_interrupt(InterruptType::Serial);
+        }
+        self.serial.stalled = self.serial.device.stalled();
+        self.serial.stalled
+    }
+
+    // Called on the falling edge of DIV bit 8, which is the 8192Hz internal serial clock.
+    pub(crate) fn clock_serial(&mut self) {
+        if self.serial.clock_internal() {
+            self.request_interrupt(InterruptType::Serial);
+        }
+    }
+
+    pub(crate) fn poll_serial(&mut self) {
+        self.serial.device.tick();
+        if self.serial.poll_external() {
+            self.request_interrupt(InterruptType::Serial);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gb;
+
+    pub(crate) fn gameboy_sending(byte: u8, sc: u8) -> GameBoy {
+        let mut gameboy = gb::init();
+        let mut rom = vec![0; 0x8000];
+        // 0x100: ld a, byte; ldh (01), a; ld a, sc; ldh (02), a; jr -2
+        rom[0x100..0x10A].copy_from_slice(&[0x3E, byte, 0xE0, 0x01, 0x3E, sc, 0xE0, 0x02, 0x18, 0xFE]);
+        gameboy.memory.cartridge = rom;
+        gameboy.mbc = gameboy.detect_mbc();
+        gameboy.r.bank = 1;
+        gameboy.r.pc = 0x100;
+        gameboy
+    }
+
+    #[test]
+    fn internal_clock_transfer_takes_1024_cycles() {
+        let mut gameboy = gameboy_sending(0x42, 0x81);
+        gameboy.connect_serial(Box::new(Loopback));
+        for _ in 0..100 {
+            gameboy.tick();
+        }
+        assert_eq!(gameboy.read(0xFF02), 0xFF);
+        for _ in 0..1024 {
+            gameboy.tick();
+        }
+        assert_eq!(gameboy.read(0xFF02), 0x7F);
+        assert_eq!(gameboy.read(0xFF01), 0x42);
+        assert_ne!(gameboy.r.r#if & (1 << InterruptType::Serial as u8), 0);
+    }
+
+    #[test]
+    fn linked_gameboys_exchange_bytes() {
+        let mut master = gameboy_sending(0x12, 0x81);
+        let mut slave = gameboy_sending(0x34, 0x80);
+        let (a, b) = link_cable();
+        master.connect_serial(Box::new(a));
+        slave.connect_serial(Box::new(b));
+        for _ in 0..1200 {
+            slave.tick();
+            master.tick();
+        }
+        assert_eq!(master.read(0xFF01), 0x34);
+        assert_eq!(slave.read(0xFF01), 0x12);
+        assert_eq!(master.read(0xFF02), 0x7F);
+        assert_eq!(slave.read(0xFF02), 0x7E);
+        assert_ne!(slave.r.r#if & (1 << InterruptType::Serial as u8), 0);
+    }
+
+    #[test]
+    fn external_clock_waits_for_the_other_end() {
+        let mut gameboy = gameboy_sending(0x42, 0x80);
+        for _ in 0..5000 {
+            gameboy.tick();
+        }
+        assert_eq!(gameboy.read(0xFF02), 0xFE);
+        assert_eq!(gameboy.r.r#if & (1 << InterruptType::Serial as u8), 0);
+    }
+}
@@ -0,0 +1,348 @@
+// Game Boy Printer. The GameBoy talks to it in packets:
+//   0x88 0x33 | command | compression | length (u16 LE) | data | checksum (u16 LE) | 0x00 0x00
+// The checksum is the sum of every byte from the command to the end of the data. While the GameBoy
+// sends the two 0x00 bytes at the end the printer answers with 0x81 ("I'm here") and its status.
+//
+// Printed images are built up a strip at a time and written out as PNG files once the paper is fed
+// past the bottom margin.
+use super::SerialDevice;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::iter;
+use std::path::{Path, PathBuf};
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+const HEADER_SIZE: usize = 6;
+const ALIVE: u8 = 0x81;
+
+const COMMAND_INIT: u8 = 0x01;
+const COMMAND_PRINT: u8 = 0x02;
+const COMMAND_DATA: u8 = 0x04;
+const COMMAND_STATUS: u8 = 0x0F;
+
+const STATUS_CHECKSUM_ERROR: u8 = 1 << 0;
+const STATUS_BUSY: u8 = 1 << 1;
+const STATUS_IMAGE_FULL: u8 = 1 << 2;
+const STATUS_UNPROCESSED: u8 = 1 << 3;
+const STATUS_PACKET_ERROR: u8 = 1 << 4;
+
+// The printer holds up to 9 DATA packets, 20x18 tiles.
+const IMAGE_BUFFER_SIZE: usize = 9 * 640;
+const WIDTH: usize = 160;
+const TILE_ROW_SIZE: usize = 20 * 16;
+// Rows of paper per line feed in the margins. The real printer feeds about this much.
+const FEED_HEIGHT: usize = 8;
+// How many STATUS packets a print job keeps the printer busy for. Games wait for busy to clear
+// before sending the next job.
+const BUSY_STATUS_POLLS: u8 = 4;
+
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+pub struct Printer {
+    output_dir: PathBuf,
+    position: usize,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    busy_polls: u8,
+    // 2bpp tile data received since the last print
+    image: Vec<u8>,
+    // greyscale pixels of the page being printed, WIDTH per row
+    page: Vec<u8>,
+    // where the last page went, or why it couldn't be saved, until the frontend picks it up
+    message: Option<String>,
+}
+
+impl Printer {
+    // Pages are saved to `output_dir` as print-0001.png, print-0002.png, ...
+    pub fn new(output_dir: &Path) -> Self {
+        Self {
+            output_dir: output_dir.to_path_buf(),
+            position: 0,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            busy_polls: 0,
+            image: Vec::new(),
+            page: Vec::new(),
+            message: None,
+        }
+    }
+
+    fn execute(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.command {
+            COMMAND_INIT => {
+                self.image.clear();
+                self.status = 0;
+                self.busy_polls = 0;
+            }
+            COMMAND_DATA => {
+                let data = std::mem::take(&mut self.data);
+                if self.compressed {
+                    decompress(&data, &mut self.image);
+                } else {
+                    self.image.extend_from_slice(&data);
+                }
+                self.image.truncate(IMAGE_BUFFER_SIZE);
+                if !self.image.is_empty() {
+                    self.status |= STATUS_UNPROCESSED;
+                }
+                if self.image.len() == IMAGE_BUFFER_SIZE {
+                    self.status |= STATUS_IMAGE_FULL;
+                }
+            }
+            COMMAND_PRINT if self.data.len() >= 4 => {
+                let (sheets, margins, palette) = (self.data[0], self.data[1], self.data[2]);
+                // data[3] is the exposure (darkness), which doesn't matter for a PNG
+                self.print(sheets, margins, palette);
+                self.status = (self.status & !STATUS_UNPROCESSED) | STATUS_BUSY;
+                self.busy_polls = BUSY_STATUS_POLLS;
+            }
+            COMMAND_STATUS => {
+                if self.busy_polls > 0 {
+                    self.busy_polls -= 1;
+                    if self.busy_polls == 0 {
+                        self.status &= !(STATUS_BUSY | STATUS_IMAGE_FULL);
+                    }
+                }
+            }
+            _ => self.status |= STATUS_PACKET_ERROR,
+        }
+    }
+
+    fn print(&mut self, sheets: u8, margins: u8, palette: u8) {
+        let (feed_before, feed_after) = ((margins >> 4) as usize, (margins & 0x0F) as usize);
+        // a top margin starts a new page
+        if feed_before > 0 && !self.page.is_empty() {
+            self.finish_page();
+        }
+        self.feed(feed_before);
+
+        // palette 0 behaves like the usual 0xE4 (white, light grey, dark grey, black)
+        let palette = if palette == 0 { 0xE4 } else { palette };
+        let image = std::mem::take(&mut self.image);
+        for _ in 0..sheets {
+            for tile_row in image.chunks_exact(TILE_ROW_SIZE) {
+                for y in 0..8 {
+                    for tile in tile_row.chunks_exact(16) {
+                        let (low, high) = (tile[y * 2], tile[y * 2 + 1]);
+                        for x in (0..8).rev() {
+                            let colour = (((high >> x) & 1) << 1) | ((low >> x) & 1);
+                            let shade = (palette >> (colour * 2)) & 3;
+                            self.page.push(SHADES[shade as usize]);
+                        }
+                    }
+                }
+            }
+        }
+
+        self.feed(feed_after);
+        if feed_after > 0 {
+            self.finish_page();
+        }
+    }
+
+    fn feed(&mut self, lines: usize) {
+        self.page.resize(self.page.len() + lines * FEED_HEIGHT * WIDTH, SHADES[0]);
+    }
+
+    fn finish_page(&mut self) {
+        let page = std::mem::take(&mut self.page);
+        if page.is_empty() {
+            return;
+        }
+        self.message = Some(match self.save_page(&page) {
+            Ok(path) => format!("Printed {}", path.display()),
+            Err(e) => format!("Couldn't save printed page to {}: {e}", self.output_dir.display()),
+        });
+    }
+
+    fn save_page(&self, page: &[u8]) -> io::Result<PathBuf> {
+        fs::create_dir_all(&self.output_dir)?;
+        let path = (1..)
+            .map(|n| self.output_dir.join(format!("print-{n:04}.png")))
+            .find(|path| !path.exists())
+            .unwrap();
+        let file = BufWriter::new(File::create(&path)?);
+        let mut encoder = png::Encoder::new(file, WIDTH as u32, (page.len() / WIDTH) as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(page)?;
+        writer.finish()?;
+        Ok(path)
+    }
+}
+
+impl SerialDevice for Printer {
+    // The printer only ever runs off the GameBoy's clock.
+    fn transfer(&mut self, byte: u8) -> u8 {
+        let position = self.position;
+        self.position += 1;
+        match position {
+            0 | 1 if byte != MAGIC[position] => {
+                // out of sync, wait for the start of the next packet
+                self.position = if byte == MAGIC[0] { 1 } else { 0 };
+            }
+            0 | 1 => {}
+            2 => {
+                self.command = byte;
+                self.checksum = byte as u16;
+            }
+            3 => {
+                self.compressed = byte & 1 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+            }
+            4 => {
+                self.length = byte as usize;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+            }
+            5 => {
+                self.length |= (byte as usize) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.data.clear();
+            }
+            p if p < HEADER_SIZE + self.length => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+            }
+            p if p == HEADER_SIZE + self.length => self.received_checksum = byte as u16,
+            p if p == HEADER_SIZE + self.length + 1 => self.received_checksum |= (byte as u16) << 8,
+            p if p == HEADER_SIZE + self.length + 2 => {
+                self.execute();
+                return ALIVE;
+            }
+            _ => {
+                self.position = 0;
+                return self.status;
+            }
+        }
+        0x00
+    }
+
+    fn take_status(&mut self) -> Option<String> {
+        self.message.take()
+    }
+}
+
+impl Drop for Printer {
+    // Don't lose a page that never got its bottom margin.
+    fn drop(&mut self) {
+        self.finish_page();
+    }
+}
+
+// Run length encoding: a control byte with bit 7 set is followed by one byte repeated
+// (control & 0x7F) + 2 times, otherwise it's followed by (control + 1) literal bytes.
+fn decompress(data: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+        if control & 0x80 != 0 {
+            let Some(&byte) = data.get(i) else { break };
+            out.extend(iter::repeat(byte).take((control & 0x7F) as usize + 2));
+            i += 1;
+        } else {
+            let end = (i + control as usize + 1).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns the printer's two answers at the end of the packet: ALIVE and the status.
+    fn send_packet(printer: &mut Printer, command: u8, compressed: bool, data: &[u8]) -> (u8, u8) {
+        let mut packet = vec![command, compressed as u8, data.len() as u8, (data.len() >> 8) as u8];
+        packet.extend_from_slice(data);
+        let checksum = packet.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&packet);
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        for byte in bytes {
+            assert_eq!(printer.transfer(byte), 0x00);
+        }
+        (printer.transfer(0), printer.transfer(0))
+    }
+
+    #[test]
+    fn rle_decompression() {
+        let mut out = Vec::new();
+        decompress(&[0x81, 0xAA, 0x01, 0x12, 0x34], &mut out);
+        assert_eq!(out, [0xAA, 0xAA, 0xAA, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn prints_a_page_to_png() {
+        let dir = std::env::temp_dir().join(format!("scgb-printer-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut printer = Printer::new(&dir);
+
+        assert_eq!(send_packet(&mut printer, COMMAND_INIT, false, &[]), (ALIVE, 0));
+        // two rows of black tiles, the second row compressed (129 + 129 + 62 bytes)
+        let (_, status) = send_packet(&mut printer, COMMAND_DATA, false, &[0xFF; TILE_ROW_SIZE]);
+        assert_eq!(status, STATUS_UNPROCESSED);
+        send_packet(&mut printer, COMMAND_DATA, true, &[0xFF, 0xFF, 0xFF, 0xFF, 0xBC, 0xFF]);
+        send_packet(&mut printer, COMMAND_DATA, false, &[]);
+        // 1 sheet, no top margin, 1 line feed after
+        let (_, status) = send_packet(&mut printer, COMMAND_PRINT, false, &[1, 0x01, 0xE4, 0x40]);
+        assert_eq!(status & STATUS_BUSY, STATUS_BUSY);
+
+        let decoder = png::Decoder::new(File::open(dir.join("print-0001.png")).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (160, 16 + FEED_HEIGHT as u32));
+        assert_eq!(pixels[0], 0x00);
+        assert_eq!(pixels[160 * 16], 0xFF);
+        let message = printer.take_status().unwrap();
+        assert!(message.starts_with("Printed") && message.ends_with("print-0001.png"), "{message}");
+
+        for _ in 0..BUSY_STATUS_POLLS {
+            send_packet(&mut printer, COMMAND_STATUS, false, &[]);
+        }
+        assert_eq!(send_packet(&mut printer, COMMAND_STATUS, false, &[]).1, 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bad_checksum_is_reported() {
+        let mut printer = Printer::new(Path::new("unused"));
+        for byte in [0x88, 0x33, COMMAND_STATUS, 0, 0, 0, 0x12, 0x34] {
+            printer.transfer(byte);
+        }
+        assert_eq!(printer.transfer(0), ALIVE);
+        assert_eq!(printer.transfer(0), STATUS_CHECKSUM_ERROR);
+    }
+
+    #[test]
+    fn failing_to_save_is_reported() {
+        // a file where the folder should be
+        let file = std::env::temp_dir().join(format!("scgb-printer-file-{}", std::process::id()));
+        fs::write(&file, []).unwrap();
+        let mut printer = Printer::new(&file);
+        send_packet(&mut printer, COMMAND_DATA, false, &[0xFF; TILE_ROW_SIZE]);
+        send_packet(&mut printer, COMMAND_PRINT, false, &[1, 0x01, 0xE4, 0x40]);
+        let message = printer.take_status().unwrap();
+        assert!(message.starts_with("Couldn't save printed page"), "{message}");
+        fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,372 @@
+// Link cable over TCP, to another emulator on the network.
+//
+// Both ends count M-cycles and send a SYNC message every SYNC_QUANTUM cycles. Neither end may get
+// more than MAX_LEAD_QUANTA ahead of the other, so the two emulations stay within a few
+// milliseconds of each other while leaving room for network latency.
+//
+// A transfer is a handshake between the clock master (the end that set SC to internal clock) and
+// the slave: when the master has clocked out its byte it sends DATA and blocks until the slave
+// answers with REPLY, carrying the byte the slave had in SB. A slave that isn't waiting on an
+// external clock answers 0xFF and doesn't receive anything, like real hardware with nobody
+// listening.
+//
+// Waiting on the other end blocks the emulation, so a peer that stops answering for LINK_TIMEOUT
+// is treated as unplugged rather than freezing this end for good. A frontend that runs the
+// GameBoy on its UI thread uses `non_blocking` instead: the GameBoy skips ticks while the link
+// waits, see GameBoy::waiting_on_link.
+use super::SerialDevice;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HELLO_MAGIC: &[u8; 8] = b"SCGBLINK";
+const PROTOCOL_VERSION: u8 = 1;
+const ROLE_HOST: u8 = 0;
+const ROLE_GUEST: u8 = 1;
+
+const SYNC_QUANTUM: u32 = 256;
+const MAX_LEAD_QUANTA: u64 = 16;
+const LINK_TIMEOUT: Duration = Duration::from_secs(5);
+
+const TAG_SYNC: u8 = 0;
+const TAG_DATA: u8 = 1;
+const TAG_REPLY: u8 = 2;
+
+enum Message {
+    Sync,
+    Data(u8),
+    Reply(u8),
+}
+
+pub struct TcpLink {
+    stream: TcpStream,
+    // filled by a thread reading the socket, so we can check for messages without blocking
+    messages: Receiver<Message>,
+    connected: bool,
+    // why it disconnected, until the frontend picks it up
+    status: Option<String>,
+    cycles: u32,
+    quanta_sent: u64,
+    quanta_received: u64,
+    // SB while this end is waiting on an external clock
+    waiting: Option<u8>,
+    // byte clocked in by the other end, not yet picked up
+    received: Option<u8>,
+    // sent DATA and waiting for the REPLY
+    awaiting_reply: bool,
+    non_blocking: bool,
+    // when a non-blocking wait on the other end started
+    waiting_since: Option<Instant>,
+}
+
+impl TcpLink {
+    // Waits for the other emulator to connect.
+    pub fn listen(listener: &TcpListener) -> io::Result<Self> {
+        let (mut stream, _) = listener.accept()?;
+        send_hello(&mut stream, ROLE_HOST)?;
+        expect_hello(&mut stream, ROLE_GUEST)?;
+        Self::start(stream)
+    }
+
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let mut stream = TcpStream::connect(address)?;
+        expect_hello(&mut stream, ROLE_HOST)?;
+        send_hello(&mut stream, ROLE_GUEST)?;
+        Self::start(stream)
+    }
+
+    fn start(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        let reader = stream.try_clone()?;
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || read_messages(reader, sender));
+        Ok(Self {
+            stream,
+            messages,
+            connected: true,
+            status: None,
+            cycles: 0,
+            quanta_sent: 0,
+            quanta_received: 0,
+            waiting: None,
+            received: None,
+            awaiting_reply: false,
+            non_blocking: false,
+            waiting_since: None,
+        })
+    }
+
+    // Never wait on the other end, the GameBoy waits a tick at a time instead.
+    pub fn non_blocking(mut self) -> Self {
+        self.non_blocking = true;
+        self
+    }
+
+    fn send(&mut self, tag: u8, value: u8) {
+        if let Err(e) = self.stream.write_all(&[tag, value]) {
+            self.disconnect(&e.to_string());
+        }
+    }
+
+    fn disconnect(&mut self, reason: &str) {
+        if self.connected {
+            self.status = Some(format!("Link cable disconnected: {reason}"));
+            self.connected = false;
+        }
+    }
+
+    fn handle(&mut self, message: Message) -> Option<u8> {
+        match message {
+            Message::Sync => self.quanta_received += 1,
+            Message::Data(byte) => {
+                let reply = match self.waiting.take() {
+                    Some(sb) => {
+                        self.received = Some(byte);
+                        sb
+                    }
+                    None => 0xFF,
+                };
+                self.send(TAG_REPLY, reply);
+            }
+            Message::Reply(byte) => {
+                self.awaiting_reply = false;
+                return Some(byte);
+            }
+        }
+        None
+    }
+
+    // The next message from the other end, waiting up to LINK_TIMEOUT for it. Non-blocking, None
+    // means there isn't one yet and the timeout counts from the first call that found nothing.
+    fn next_message(&mut self) -> Option<Message> {
+        let result = match self.non_blocking {
+            true => self.messages.try_recv().map_err(|e| match e {
+                TryRecvError::Empty => RecvTimeoutError::Timeout,
+                TryRecvError::Disconnected => RecvTimeoutError::Disconnected,
+            }),
+            false => self.messages.recv_timeout(LINK_TIMEOUT),
+        };
+        match result {
+            Ok(message) => {
+                self.waiting_since = None;
+                Some(message)
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                let since = *self.waiting_since.get_or_insert_with(Instant::now);
+                if !self.non_blocking || since.elapsed() >= LINK_TIMEOUT {
+                    self.disconnect("the other Game Boy stopped responding");
+                }
+                None
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                self.disconnect("connection closed");
+                None
+            }
+        }
+    }
+
+    // The other end's answer to DATA, 0xFF if it went away.
+    fn take_reply(&mut self) -> Option<u8> {
+        while self.connected && self.awaiting_reply {
+            let Some(message) = self.next_message() else { break };
+            if let Some(reply) = self.handle(message) {
+                return Some(reply);
+            }
+        }
+        (!self.connected).then_some(0xFF)
+    }
+
+    fn behind(&self) -> bool {
+        self.connected && self.quanta_sent > self.quanta_received + MAX_LEAD_QUANTA
+    }
+}
+
+impl SerialDevice for TcpLink {
+    fn transfer(&mut self, byte: u8) -> u8 {
+        self.start_transfer(byte).unwrap_or(0xFF)
+    }
+
+    fn start_transfer(&mut self, byte: u8) -> Option<u8> {
+        self.waiting = None;
+        self.send(TAG_DATA, byte);
+        self.awaiting_reply = true;
+        self.take_reply()
+    }
+
+    fn finish_transfer(&mut self) -> Option<u8> {
+        self.take_reply()
+    }
+
+    fn poll_external(&mut self, byte: u8) -> Option<u8> {
+        let received = self.received.take();
+        self.waiting = match received {
+            Some(_) => None,
+            None => Some(byte),
+        };
+        received
+    }
+
+    fn tick(&mut self) {
+        self.cycles += 1;
+        if !self.connected || self.cycles < SYNC_QUANTUM {
+            return;
+        }
+        self.cycles = 0;
+        self.send(TAG_SYNC, 0);
+        self.quanta_sent += 1;
+
+        while let Ok(message) = self.messages.try_recv() {
+            self.handle(message);
+        }
+    }
+
+    fn stalled(&mut self) -> bool {
+        while self.behind() {
+            let Some(message) = self.next_message() else { break };
+            self.handle(message);
+        }
+        self.behind()
+    }
+
+    fn take_status(&mut self) -> Option<String> {
+        self.status.take()
+    }
+}
+
+fn send_hello(stream: &mut TcpStream, role: u8) -> io::Result<()> {
+    let mut hello = HELLO_MAGIC.to_vec();
+    hello.extend_from_slice(&[PROTOCOL_VERSION, role]);
+    stream.write_all(&hello)
+}
+
+fn expect_hello(stream: &mut TcpStream, role: u8) -> io::Result<()> {
+    let mut hello = [0u8; 10];
+    stream.read_exact(&mut hello)?;
+    if &hello[0..8] != HELLO_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "the other end isn't a link cable"));
+    }
+    if hello[8] != PROTOCOL_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("link protocol version {} is not supported", hello[8]),
+        ));
+    }
+    if hello[9] != role {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "both ends are hosting"));
+    }
+    Ok(())
+}
+
+fn read_messages(mut stream: TcpStream, sender: Sender<Message>) {
+    let mut buf = [0u8; 2];
+    while stream.read_exact(&mut buf).is_ok() {
+        let message = match buf[0] {
+            TAG_SYNC => Message::Sync,
+            TAG_DATA => Message::Data(buf[1]),
+            TAG_REPLY => Message::Reply(buf[1]),
+            _ => break,
+        };
+        if sender.send(message).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::serial::tests::gameboy_sending;
+
+    #[test]
+    fn tcp_link_exchanges_bytes() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        // the slave stays connected until the master is done, or the master's last SYNC goes nowhere
+        let (done, wait) = mpsc::channel::<()>();
+        let slave = thread::spawn(move || {
+            let mut gameboy = gameboy_sending(0x34, 0x80);
+            gameboy.connect_serial(Box::new(TcpLink::listen(&listener).unwrap()));
+            for _ in 0..20000 {
+                gameboy.tick();
+            }
+            let _ = wait.recv();
+            (gameboy.read(0xFF01), gameboy.read(0xFF02))
+        });
+
+        let mut master = gameboy_sending(0x12, 0x81);
+        master.connect_serial(Box::new(TcpLink::connect(address).unwrap()));
+        for _ in 0..20000 {
+            master.tick();
+        }
+        done.send(()).unwrap();
+
+        assert_eq!((master.read(0xFF01), master.read(0xFF02)), (0x34, 0x7F));
+        assert_eq!(slave.join().unwrap(), (0x12, 0x7E));
+        assert_eq!(master.take_serial_status(), None);
+    }
+
+    #[test]
+    fn non_blocking_ends_share_a_thread() {
+        // blocking, each end would wait on the other forever
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let host = thread::spawn(move || TcpLink::listen(&listener).unwrap());
+        let guest = TcpLink::connect(address).unwrap();
+
+        let mut master = gameboy_sending(0x12, 0x81);
+        let mut slave = gameboy_sending(0x34, 0x80);
+        master.connect_serial(Box::new(guest.non_blocking()));
+        slave.connect_serial(Box::new(host.join().unwrap().non_blocking()));
+        while master.clock < 20000 || slave.clock < 20000 {
+            master.tick();
+            slave.tick();
+        }
+
+        assert_eq!((master.read(0xFF01), master.read(0xFF02)), (0x34, 0x7F));
+        assert_eq!((slave.read(0xFF01), slave.read(0xFF02)), (0x12, 0x7E));
+        assert_eq!(master.take_serial_status(), None);
+    }
+
+    #[test]
+    fn reports_the_other_end_going_away() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let host = thread::spawn(move || drop(TcpLink::listen(&listener).unwrap()));
+
+        let mut gameboy = gameboy_sending(0x12, 0x81);
+        gameboy.connect_serial(Box::new(TcpLink::connect(address).unwrap()));
+        host.join().unwrap();
+        for _ in 0..20000 {
+            gameboy.tick();
+        }
+        let status = gameboy.take_serial_status().unwrap();
+        assert!(status.starts_with("Link cable disconnected"), "{status}");
+        assert_eq!(gameboy.take_serial_status(), None);
+    }
+
+    #[test]
+    fn gives_up_on_a_stalled_peer() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        // connects, then never sends a thing but keeps the connection open
+        let (done, wait) = mpsc::channel::<()>();
+        let host = thread::spawn(move || {
+            let link = TcpLink::listen(&listener).unwrap();
+            let _ = wait.recv();
+            drop(link);
+        });
+
+        let mut gameboy = gameboy_sending(0x12, 0x80);
+        gameboy.connect_serial(Box::new(TcpLink::connect(address).unwrap()));
+        for _ in 0..(MAX_LEAD_QUANTA as u32 + 2) * SYNC_QUANTUM {
+            gameboy.tick();
+        }
+        let status = gameboy.take_serial_status().unwrap();
+        assert!(status.contains("stopped responding"), "{status}");
+        done.send(()).unwrap();
+        host.join().unwrap();
+    }
+}
@@ -0,0 +1,566 @@
+// Super Game Boy. The game talks to the SGB by pulsing P14/P15 in the joypad register to send
+// 16 byte packets, and the SGB colours the screen with four palettes assigned per 8x8 region and
+// draws a 256x224 border around it.
+//
+// Bulk data (border tiles and map, palette and attribute tables) is sent by putting it on screen
+// and sending a *_TRN command, the SGB then reads it off the next frame. We read the same 4KB
+// straight out of VRAM through the BG map at the next VBlank.
+use crate::gb::GameBoy;
+use crate::renderer::rgb555;
+use crate::state::big_array;
+use serde::{Deserialize, Serialize};
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+const PACKET_SIZE: usize = 16;
+const TRANSFER_SIZE: usize = 0x1000;
+const SCREEN_TILES_X: usize = 20;
+const SCREEN_TILES_Y: usize = 18;
+const ATTRIBUTE_FILE_SIZE: usize = SCREEN_TILES_X * SCREEN_TILES_Y / 4;
+const NUM_ATTRIBUTE_FILES: usize = 45;
+
+pub const BORDER_WIDTH: usize = 256;
+pub const BORDER_HEIGHT: usize = 224;
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+// marks border pixels that show the background colour (colour 0 of palette 0)
+const TRANSPARENT: u16 = 0x8000;
+
+pub const MASK_NONE: u8 = 0;
+pub const MASK_FREEZE: u8 = 1;
+pub const MASK_BLACK: u8 = 2;
+pub const MASK_COLOUR_0: u8 = 3;
+
+// what the SGB shows before the game sets any palettes
+const DEFAULT_PALETTE: [u16; 4] = [
+    rgb555(0xF7, 0xE7, 0xC6),
+    rgb555(0xD6, 0x8E, 0x49),
+    rgb555(0xA6, 0x37, 0x25),
+    rgb555(0x33, 0x1E, 0x50),
+];
+
+#[derive(Serialize, Deserialize)]
+pub struct Sgb {
+    pub enabled: bool,
+    // packet being received and how many bits of it so far, None between packets
+    packet: [u8; PACKET_SIZE],
+    packet_bits: Option<usize>,
+    previous_p1: u8,
+    // packets of a multi packet command received so far
+    command: Vec<u8>,
+    pub palettes: [[u16; 4]; 4],
+    // palette number for each 8x8 region of the screen
+    #[serde(with = "big_array")]
+    pub attributes: Box<[u8; SCREEN_TILES_X * SCREEN_TILES_Y]>,
+    system_palettes: Vec<u16>,
+    attribute_files: Vec<u8>,
+    border_tiles: Vec<u8>,
+    border_map: Vec<u8>,
+    border_palettes: Vec<u16>,
+    // RGB555, rendered whenever the border tiles, map or palettes change
+    border: Option<Vec<u16>>,
+    pub mask: u8,
+    pub players: u8,
+    pub current_player: u8,
+    // button and d-pad state for players 2-4, same layout as keys_ssba/keys_dulr
+    extra_keys: [(u8, u8); 3],
+    // *_TRN command waiting for the next VBlank, and its parameter
+    pending_transfer: Option<(u8, u8)>,
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            packet: [0; PACKET_SIZE],
+            packet_bits: None,
+            previous_p1: 0x30,
+            command: Vec::new(),
+            palettes: [DEFAULT_PALETTE; 4],
+            attributes: Box::new([0; SCREEN_TILES_X * SCREEN_TILES_Y]),
+            system_palettes: vec![0; 512 * 4],
+            attribute_files: vec![0; NUM_ATTRIBUTE_FILES * ATTRIBUTE_FILE_SIZE],
+            border_tiles: vec![0; 2 * TRANSFER_SIZE],
+            border_map: Vec::new(),
+            border_palettes: vec![0; 4 * 16],
+            border: None,
+            mask: MASK_NONE,
+            players: 1,
+            current_player: 0,
+            extra_keys: [(0xF, 0xF); 3],
+            pending_transfer: None,
+        }
+    }
+}
+
+fn colour_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]]) & 0x7FFF
+}
+
+impl Sgb {
+    fn set_attribute(&mut self, x: usize, y: usize, palette: u8) {
+        if x < SCREEN_TILES_X && y < SCREEN_TILES_Y {
+            self.attributes[y * SCREEN_TILES_X + x] = palette & 3;
+        }
+    }
+
+    // Colour 0 is shared by all four palettes, whichever one set it last wins.
+    fn set_palette_pair(&mut self, first: usize, second: usize, data: &[u8]) {
+        let colour_0 = colour_at(data, 1);
+        for (palette, offset) in [(first, 3), (second, 9)] {
+            for i in 0..3 {
+                self.palettes[palette][i + 1] = colour_at(data, offset + i * 2);
+            }
+        }
+        for palette in self.palettes.iter_mut() {
+            palette[0] = colour_0;
+        }
+    }
+
+    fn attr_blk(&mut self, data: &[u8]) {
+        let sets = (data[1] as usize).min(18);
+        for set in data[2..].chunks_exact(6).take(sets) {
+            let control = set[0] & 7;
+            let (inside, mut border, outside) = (set[1] & 3, (set[1] >> 2) & 3, (set[1] >> 4) & 3);
+            let mut border_enabled = control & 2 != 0;
+            // setting only the inside or only the outside also sets the border
+            if control == 1 {
+                (border_enabled, border) = (true, inside);
+            } else if control == 4 {
+                (border_enabled, border) = (true, outside);
+            }
+            let (x1, y1, x2, y2) = (set[2] & 0x1F, set[3] & 0x1F, set[4] & 0x1F, set[5] & 0x1F);
+            for y in 0..SCREEN_TILES_Y as u8 {
+                for x in 0..SCREEN_TILES_X as u8 {
+                    let in_rect = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let on_border = in_rect && (x == x1 || x == x2 || y == y1 || y == y2);
+                    let palette = if on_border {
+                        border_enabled.then_some(border)
+                    } else if in_rect {
+                        (control & 1 != 0).then_some(inside)
+                    } else {
+                        (control & 4 != 0).then_some(outside)
+                    };
+                    if let Some(palette) = palette {
+                        self.set_attribute(x as usize, y as usize, palette);
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, data: &[u8]) {
+        let lines = data[1] as usize;
+        for &line in data[2..].iter().take(lines) {
+            let (number, palette) = ((line & 0x1F) as usize, (line >> 5) & 3);
+            for i in 0..SCREEN_TILES_X.max(SCREEN_TILES_Y) {
+                match line & 0x80 {
+                    0 => self.set_attribute(number, i, palette),
+                    _ => self.set_attribute(i, number, palette),
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, data: &[u8]) {
+        let (after, before, on) = (data[1] & 3, (data[1] >> 2) & 3, (data[1] >> 4) & 3);
+        let horizontal = data[1] & 0x40 != 0;
+        let coordinate = (data[2] & 0x1F) as usize;
+        for y in 0..SCREEN_TILES_Y {
+            for x in 0..SCREEN_TILES_X {
+                let position = if horizontal { y } else { x };
+                let palette = match position.cmp(&coordinate) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on,
+                    std::cmp::Ordering::Greater => after,
+                };
+                self.set_attribute(x, y, palette);
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y) = ((data[1] & 0x1F) as usize, (data[2] & 0x1F) as usize);
+        let count = (u16::from_le_bytes([data[3], data[4]]) as usize).min(SCREEN_TILES_X * SCREEN_TILES_Y);
+        let vertical = data[5] & 1 != 0;
+        for i in 0..count {
+            let Some(&byte) = data.get(6 + i / 4) else { break };
+            self.set_attribute(x, y, byte >> (6 - 2 * (i % 4)));
+            if vertical {
+                y += 1;
+                if y == SCREEN_TILES_Y {
+                    (x, y) = ((x + 1) % SCREEN_TILES_X, 0);
+                }
+            } else {
+                x += 1;
+                if x == SCREEN_TILES_X {
+                    (x, y) = (0, (y + 1) % SCREEN_TILES_Y);
+                }
+            }
+        }
+    }
+
+    fn apply_attribute_file(&mut self, file: usize) {
+        let file = file.min(NUM_ATTRIBUTE_FILES - 1) * ATTRIBUTE_FILE_SIZE;
+        for i in 0..SCREEN_TILES_X * SCREEN_TILES_Y {
+            let byte = self.attribute_files[file + i / 4];
+            self.attributes[i] = (byte >> (6 - 2 * (i % 4))) & 3;
+        }
+    }
+
+    fn pal_set(&mut self, data: &[u8]) {
+        for palette in 0..4 {
+            let number = (u16::from_le_bytes([data[1 + palette * 2], data[2 + palette * 2]]) & 0x1FF) as usize;
+            self.palettes[palette].copy_from_slice(&self.system_palettes[number * 4..number * 4 + 4]);
+        }
+        for palette in 1..4 {
+            self.palettes[palette][0] = self.palettes[0][0];
+        }
+        if data[9] & 0x80 != 0 {
+            self.apply_attribute_file((data[9] & 0x3F) as usize);
+        }
+        if data[9] & 0x40 != 0 {
+            self.mask = MASK_NONE;
+        }
+    }
+
+    fn finish_transfer(&mut self, command: u8, parameter: u8, data: &[u8]) {
+        match command {
+            CHR_TRN => {
+                let offset = (parameter as usize & 1) * TRANSFER_SIZE;
+                self.border_tiles[offset..offset + TRANSFER_SIZE].copy_from_slice(data);
+                self.render_border();
+            }
+            PCT_TRN => {
+                self.border_map = data[..0x800].to_vec();
+                self.border_palettes = (0..4 * 16).map(|i| colour_at(data, 0x800 + i * 2)).collect();
+                self.render_border();
+            }
+            PAL_TRN => {
+                self.system_palettes = (0..512 * 4).map(|i| colour_at(data, i * 2)).collect();
+            }
+            ATTR_TRN => {
+                self.attribute_files.copy_from_slice(&data[..NUM_ATTRIBUTE_FILES * ATTRIBUTE_FILE_SIZE]);
+            }
+            _ => {}
+        }
+    }
+
+    // Border tiles are 4 bits per pixel in the SNES layout: 32 bytes per tile, bitplanes 0 and 1
+    // interleaved in the first 16 bytes and 2 and 3 in the second. Map entries are 16 bits: tile
+    // number, palette 4-7 in bits 10-12, x flip in bit 14 and y flip in bit 15.
+    fn render_border(&mut self) {
+        if self.border_map.is_empty() {
+            return;
+        }
+        let mut border = vec![TRANSPARENT; BORDER_WIDTH * BORDER_HEIGHT];
+        for (i, entry) in self.border_map.chunks_exact(2).take(32 * 28).enumerate() {
+            let entry = u16::from_le_bytes([entry[0], entry[1]]);
+            let tile = &self.border_tiles[(entry as usize & 0xFF) * 32..][..32];
+            let palette = ((entry >> 10) as usize & 3) * 16;
+            let (x_flip, y_flip) = (entry & 0x4000 != 0, entry & 0x8000 != 0);
+            for row in 0..8 {
+                let tile_row = if y_flip { 7 - row } else { row };
+                let planes = [
+                    tile[tile_row * 2],
+                    tile[tile_row * 2 + 1],
+                    tile[16 + tile_row * 2],
+                    tile[16 + tile_row * 2 + 1],
+                ];
+                for column in 0..8 {
+                    let bit = if x_flip { column } else { 7 - column };
+                    let colour = planes
+                        .iter()
+                        .enumerate()
+                        .fold(0, |colour, (plane, byte)| colour | (((byte >> bit) & 1) << plane));
+                    if colour != 0 {
+                        let (x, y) = ((i % 32) * 8 + column, (i / 32) * 8 + row);
+                        border[y * BORDER_WIDTH + x] = self.border_palettes[palette + colour as usize];
+                    }
+                }
+            }
+        }
+        self.border = Some(border);
+    }
+}
+
+// Applies MASK_EN to a finished frame. Returns false if the frame should not be shown at all.
+pub(crate) fn mask_frame(mask: u8, colour_0: u16, frame: &mut [u16]) -> bool {
+    match mask {
+        MASK_FREEZE => return false,
+        MASK_BLACK => frame.fill(0),
+        MASK_COLOUR_0 => frame.fill(colour_0),
+        _ => {}
+    }
+    true
+}
+
+impl GameBoy {
+    // Header byte 0x146 is 0x03 for SGB games, and the SGB only listens to them if the old
+    // licensee code (0x14B) is 0x33.
+    pub fn cartridge_supports_sgb(&self) -> bool {
+        self.memory.cartridge.get(0x146) == Some(&0x03) && self.memory.cartridge.get(0x14B) == Some(&0x33)
+    }
+
+    pub fn start_sgb_mode(&mut self) {
+        self.sgb.enabled = true;
+    }
+
+    // Watches joypad register writes for packets. A packet starts with both P14 and P15 low,
+    // then each bit is P14 low (0) or P15 low (1) with both high in between, lowest bit first, and
+    // ends with a 0 stop bit.
+    pub(crate) fn sgb_joypad_write(&mut self, data: u8) {
+        let p1 = data & 0x30;
+        let previous = self.sgb.previous_p1;
+        self.sgb.previous_p1 = p1;
+        match p1 {
+            0x00 => {
+                self.sgb.packet = [0; PACKET_SIZE];
+                self.sgb.packet_bits = Some(0);
+            }
+            0x10 | 0x20 if previous == 0x30 => {
+                let Some(bits) = self.sgb.packet_bits else { return };
+                let bit = (p1 == 0x10) as u8;
+                if bits < PACKET_SIZE * 8 {
+                    self.sgb.packet[bits / 8] |= bit << (bits % 8);
+                    self.sgb.packet_bits = Some(bits + 1);
+                } else {
+                    self.sgb.packet_bits = None;
+                    if bit == 0 {
+                        self.sgb_packet_received();
+                    }
+                }
+            }
+            // with MLT_REQ on, P15 going high moves on to the next controller
+            0x30 if previous & 0x20 == 0 && self.sgb.packet_bits.is_none() && self.sgb.players > 1 => {
+                self.sgb.current_player = (self.sgb.current_player + 1) % self.sgb.players;
+            }
+            _ => {}
+        }
+    }
+
+    fn sgb_packet_received(&mut self) {
+        let packet = self.sgb.packet;
+        if self.sgb.command.is_empty() && packet[0] & 7 == 0 {
+            return;
+        }
+        self.sgb.command.extend_from_slice(&packet);
+        let length = (self.sgb.command[0] & 7) as usize;
+        if self.sgb.command.len() < length * PACKET_SIZE {
+            return;
+        }
+        let data = std::mem::take(&mut self.sgb.command);
+        self.sgb_command(&data);
+    }
+
+    fn sgb_command(&mut self, data: &[u8]) {
+        let command = data[0] >> 3;
+        self.logger.log_info(&format!("SGB command {command:#04x}"));
+        let sgb = &mut self.sgb;
+        match command {
+            PAL01 => sgb.set_palette_pair(0, 1, data),
+            PAL23 => sgb.set_palette_pair(2, 3, data),
+            PAL03 => sgb.set_palette_pair(0, 3, data),
+            PAL12 => sgb.set_palette_pair(1, 2, data),
+            ATTR_BLK => sgb.attr_blk(data),
+            ATTR_LIN => sgb.attr_lin(data),
+            ATTR_DIV => sgb.attr_div(data),
+            ATTR_CHR => sgb.attr_chr(data),
+            PAL_SET => sgb.pal_set(data),
+            ATTR_SET => {
+                sgb.apply_attribute_file((data[1] & 0x3F) as usize);
+                if data[1] & 0x40 != 0 {
+                    sgb.mask = MASK_NONE;
+                }
+            }
+            MASK_EN => sgb.mask = data[1] & 3,
+            MLT_REQ => {
+                sgb.players = match data[1] & 3 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                sgb.current_player = 0;
+            }
+            CHR_TRN | PCT_TRN | PAL_TRN | ATTR_TRN => sgb.pending_transfer = Some((command, data[1])),
+            _ => self
+                .logger
+                .log_warning(&format!("Unimplemented SGB command {command:#04x}")),
+        }
+    }
+
+    // Called at the start of VBlank to pick up data sent with a *_TRN command: the first 256 tiles
+    // on screen, in BG map order.
+    pub(crate) fn sgb_vblank(&mut self) {
+        let Some((command, parameter)) = self.sgb.pending_transfer.take() else { return };
+        let map_base: u16 = if self.r.lcdc & 0x08 != 0 { 0x9C00 } else { 0x9800 };
+        let mut data = vec![0; TRANSFER_SIZE];
+        for (i, tile) in data.chunks_exact_mut(16).enumerate() {
+            let map_address = map_base + (i / SCREEN_TILES_X) as u16 * 32 + (i % SCREEN_TILES_X) as u16;
+            let mut tile_num = self.vram(0, map_address);
+            let mut tile_data_base = 0;
+            self.select_tile_addressing_method(&mut tile_num, &mut tile_data_base);
+            let address = tile_data_base + tile_num as u16 * 16;
+            for (offset, byte) in tile.iter_mut().enumerate() {
+                *byte = self.vram(0, address + offset as u16);
+            }
+        }
+        self.sgb.finish_transfer(command, parameter, &data);
+    }
+
+    // The colour for a DMG shade at (x, ly), through the palette of that region of the screen.
+    pub(crate) fn sgb_colour(&self, x: usize, shade: u8) -> u16 {
+        let region = (self.r.ly as usize / 8).min(SCREEN_TILES_Y - 1) * SCREEN_TILES_X + x / 8;
+        self.sgb.palettes[self.sgb.attributes[region] as usize][shade as usize]
+    }
+
+    // The whole 256x224 SGB picture, border included, once the game has sent a border.
+    pub fn sgb_frame(&self) -> Option<Vec<u16>> {
+        let border = self.sgb.border.as_ref().filter(|_| self.sgb.enabled)?;
+        let background = self.sgb.palettes[0][0];
+        let mut frame: Vec<u16> = border
+            .iter()
+            .map(|&pixel| if pixel == TRANSPARENT { background } else { pixel })
+            .collect();
+        for (y, line) in self.display().chunks_exact(160).enumerate() {
+            let start = (SCREEN_Y + y) * BORDER_WIDTH + SCREEN_X;
+            frame[start..start + 160].copy_from_slice(line);
+        }
+        Some(frame)
+    }
+
+    // Button and d-pad state of whichever controller the joypad register is reading.
+    pub(crate) fn current_player_keys(&self) -> (u8, u8) {
+        match self.sgb.current_player {
+            0 => (self.keys_ssba, self.keys_dulr),
+            player => self.sgb.extra_keys[player as usize - 1],
+        }
+    }
+
+    // Players 2-4 only exist for SGB multiplayer games, player 0 is the GameBoy's own controller.
+    pub fn press_player_key(&mut self, player: u8, key_id: u8) {
+        self.set_player_key(player, key_id, true);
+    }
+
+    pub fn unpress_player_key(&mut self, player: u8, key_id: u8) {
+        self.set_player_key(player, key_id, false);
+    }
+
+    // Button and d-pad state of a controller, 0 being the GameBoy's own.
+    pub(crate) fn player_keys(&self, player: u8) -> Option<(u8, u8)> {
+        match player {
+            0 => Some((self.keys_ssba, self.keys_dulr)),
+            1..=3 => Some(self.sgb.extra_keys[player as usize - 1]),
+            _ => None,
+        }
+    }
+
+    fn set_player_key(&mut self, player: u8, key_id: u8, pressed: bool) {
+        let keys = match player {
+            0 if pressed => return self.press_key(key_id),
+            0 => return self.unpress_key(key_id),
+            1..=3 => {
+                self.log_key_event(player, key_id, pressed);
+                &mut self.sgb.extra_keys[player as usize - 1]
+            }
+            _ => return,
+        };
+        let (keys, bit) = if key_id > 3 { (&mut keys.1, key_id - 4) } else { (&mut keys.0, key_id) };
+        if pressed {
+            *keys &= !(1 << bit);
+        } else {
+            *keys |= 1 << bit;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gb;
+
+    fn send_packet(gameboy: &mut gb::GameBoy, packet: &[u8; 16]) {
+        gameboy.write(0xFF00, 0x00);
+        gameboy.write(0xFF00, 0x30);
+        for i in 0..128 {
+            let bit = (packet[i / 8] >> (i % 8)) & 1;
+            gameboy.write(0xFF00, if bit == 1 { 0x10 } else { 0x20 });
+            gameboy.write(0xFF00, 0x30);
+        }
+        gameboy.write(0xFF00, 0x20);
+        gameboy.write(0xFF00, 0x30);
+    }
+
+    fn sgb_gameboy() -> gb::GameBoy {
+        let mut gameboy = gb::init();
+        gameboy.start_sgb_mode();
+        gameboy
+    }
+
+    #[test]
+    fn pal01_sets_palettes() {
+        let mut gameboy = sgb_gameboy();
+        let mut packet = [0u8; 16];
+        packet[0] = (super::PAL01 << 3) | 1;
+        packet[1..3].copy_from_slice(&0x7FFFu16.to_le_bytes());
+        packet[3..5].copy_from_slice(&0x001Fu16.to_le_bytes());
+        packet[9..11].copy_from_slice(&0x03E0u16.to_le_bytes());
+        send_packet(&mut gameboy, &packet);
+        assert_eq!(gameboy.sgb.palettes[0][..2], [0x7FFF, 0x001F]);
+        assert_eq!(gameboy.sgb.palettes[1][..2], [0x7FFF, 0x03E0]);
+        assert_eq!(gameboy.sgb.palettes[3][0], 0x7FFF);
+    }
+
+    #[test]
+    fn attr_blk_colours_a_region() {
+        let mut gameboy = sgb_gameboy();
+        let mut packet = [0u8; 16];
+        packet[0] = (super::ATTR_BLK << 3) | 1;
+        packet[1] = 1;
+        // inside only (so the border too) with palette 2, tiles (1, 1) to (3, 3)
+        packet[2..8].copy_from_slice(&[0x01, 0x02, 1, 1, 3, 3]);
+        send_packet(&mut gameboy, &packet);
+        assert_eq!(gameboy.sgb.attributes[20 + 1], 2);
+        assert_eq!(gameboy.sgb.attributes[2 * 20 + 2], 2);
+        assert_eq!(gameboy.sgb.attributes[0], 0);
+        assert_eq!(gameboy.sgb.attributes[4 * 20 + 4], 0);
+    }
+
+    #[test]
+    fn mlt_req_cycles_through_controllers() {
+        let mut gameboy = sgb_gameboy();
+        let mut packet = [0u8; 16];
+        packet[0] = (super::MLT_REQ << 3) | 1;
+        packet[1] = 3;
+        send_packet(&mut gameboy, &packet);
+        gameboy.press_player_key(2, 0);
+
+        let mut ids = vec![];
+        for _ in 0..4 {
+            gameboy.write(0xFF00, 0x30);
+            ids.push(gameboy.read(0xFF00) & 0x0F);
+            gameboy.write(0xFF00, 0x10);
+            if ids.len() == 3 {
+                // player 3's A button
+                assert_eq!(gameboy.read(0xFF00) & 0x0F, 0x0E);
+            }
+        }
+        gameboy.write(0xFF00, 0x30);
+        assert_eq!(ids, [0x0F, 0x0E, 0x0D, 0x0C]);
+        assert_eq!(gameboy.read(0xFF00) & 0x0F, 0x0F);
+    }
+}
@@ -1,10 +1,26 @@
 // https://github.com/SingleStepTests/sm83
+//
+// Clone that repository into dmg/sm83 (`git clone --depth 1 https://github.com/SingleStepTests/sm83`
+// in the dmg folder) to run these, CI does. Without it the tests fail rather than pass having
+// checked nothing. Every opcode file is run and the failures are summed up per file.
+//
+// Bus timing isn't right for every instruction yet. The opcode files known to fail that check are
+// listed in tests/sm83_timing_failures.txt, and running with SM83_BLESS=1 rewrites the list with
+// whatever fails now.
 #[cfg(test)]
 mod single_step_test {
     use crate::gb;
     use crate::gb::State;
-    use crate::memory::{MappingType};
-    use std::{fs, path::Path, path::PathBuf};
+    use crate::memory::MappingType;
+    use std::{fs, path::Path, path::PathBuf, thread};
+
+    // show this many failing tests of each file
+    const FAILURES_SHOWN: usize = 3;
+
+    const TIMING_FAILURES_HEADER: &str = "\
+# Opcode files of the SingleStepTests whose bus timing is known to be wrong, one per line.
+# Regenerate with `SM83_BLESS=1 cargo test --release sm83_bus_timing` in the dmg folder.
+";
 
     type SingleStepTestsRam = Vec<(u16, u8)>;
 
@@ -25,7 +41,9 @@ mod single_step_test {
 
     type SingleStepTestsFinal = SingleStepTestsInitial;
 
-    type SingleStepTestsCycles = Vec<(u16, Option<u16>, String)>;
+    // one entry per M-cycle: address, data (None if the bus wasn't used), and pins, "r-m" for a
+    // read and "-wm" for a write
+    type SingleStepTestsCycles = Vec<(u16, Option<u8>, String)>;
 
     #[derive(serde::Serialize, serde::Deserialize)]
     struct SingleStepTest {
@@ -35,10 +53,27 @@ mod single_step_test {
         cycles: SingleStepTestsCycles,
     }
 
-    fn run_individual_test(gameboy: &mut gb::GameBoy, test_json: &serde_json::Value) {
-        let test: SingleStepTest = serde_json::from_value::<SingleStepTest>(test_json.clone())
-            .expect("Could not deserialise JSON into Rust type");
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    enum Check {
+        // registers, RAM and the bus accesses in order
+        Results,
+        // also which M-cycle each access happened on, and how many M-cycles the instruction took
+        Timing,
+    }
+
+    fn expected_accesses(test: &SingleStepTest) -> Vec<(u64, gb::BusAccess)> {
+        let mut accesses = vec![];
+        for (cycle, (address, data, pins)) in test.cycles.iter().enumerate() {
+            let Some(value) = *data else { continue };
+            let write = pins.as_bytes().get(1) == Some(&b'w');
+            if write || pins.starts_with('r') {
+                accesses.push((cycle as u64, gb::BusAccess { address: *address, value, write }));
+            }
+        }
+        accesses
+    }
 
+    fn run_individual_test(gameboy: &mut gb::GameBoy, test: &SingleStepTest, check: Check) -> Result<(), String> {
         gameboy.r.a = test.initial.a;
         gameboy.r.f = test.initial.f;
         gameboy.r.b = test.initial.b;
@@ -53,56 +88,144 @@ mod single_step_test {
         gameboy.cycles_to_idle = Some(0);
 
         // Write to RAM
-        for cell in test.initial.ram {
+        for cell in &test.initial.ram {
             gameboy.write(cell.0, cell.1);
         }
 
-        // tick the CPU
-        for _ in 0..(test.cycles.len() + 1) {
+        // tick the CPU until it's about to fetch the next instruction, recording the bus
+        let start = gameboy.clock;
+        gameboy.bus_log = Some(vec![]);
+        gameboy.tick();
+        while gameboy.cycles_to_idle != Some(0) && gameboy.clock - start < 64 {
             gameboy.tick();
         }
+        let cycles = (gameboy.clock - start) as u64;
+        let accesses: Vec<(u64, gb::BusAccess)> = gameboy
+            .bus_log
+            .take()
+            .unwrap()
+            .into_iter()
+            .map(|(clock, access)| ((clock - start) as u64, access))
+            .collect();
 
         // Compare the final state of the processor to the test
-        assert_eq!(gameboy.r.a, test.r#final.a, "A mismatch");
-        assert_eq!(gameboy.r.f, test.r#final.f, "F mismatch");
-        assert_eq!(gameboy.r.b, test.r#final.b, "B mismatch");
-        assert_eq!(gameboy.r.c, test.r#final.c, "C mismatch");
-        assert_eq!(gameboy.r.d, test.r#final.d, "D mismatch");
-        assert_eq!(gameboy.r.e, test.r#final.e, "E mismatch");
-        assert_eq!(gameboy.r.h, test.r#final.h, "H mismatch");
-        assert_eq!(gameboy.r.l, test.r#final.l, "L mismatch");
-        assert_eq!(gameboy.r.sp, test.r#final.sp, "SP mismatch");
-        assert_eq!(gameboy.r.pc, test.r#final.pc, "PC mismatch");
-
-        for cell in test.r#final.ram {
+        let r = &gameboy.r;
+        let registers = [r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l];
+        let expected = &test.r#final;
+        let expected_registers = [expected.a, expected.f, expected.b, expected.c, expected.d, expected.e, expected.h, expected.l];
+        for (i, name) in ["A", "F", "B", "C", "D", "E", "H", "L"].iter().enumerate() {
+            if registers[i] != expected_registers[i] {
+                return Err(format!("{name} is {:#04x}, expected {:#04x}", registers[i], expected_registers[i]));
+            }
+        }
+        if (r.sp, r.pc) != (expected.sp, expected.pc) {
+            return Err(format!("SP:PC is {:04x}:{:04x}, expected {:04x}:{:04x}", r.sp, r.pc, expected.sp, expected.pc));
+        }
+        for cell in &expected.ram {
             let ram_value: u8 = gameboy.read(cell.0);
-            assert_eq!(ram_value, cell.1, "RAM mismatch at address {:#04X}", cell.0);
+            if ram_value != cell.1 {
+                return Err(format!("RAM at {:#06x} is {ram_value:#04x}, expected {:#04x}", cell.0, cell.1));
+            }
+        }
+
+        let expected_accesses = expected_accesses(test);
+        let order = |accesses: &[(u64, gb::BusAccess)]| accesses.iter().map(|(_, access)| *access).collect::<Vec<_>>();
+        if order(&accesses) != order(&expected_accesses) {
+            return Err(format!("bus accesses {:?}, expected {:?}", order(&accesses), order(&expected_accesses)));
+        }
+        if check == Check::Timing {
+            if cycles != test.cycles.len() as u64 {
+                return Err(format!("took {cycles} M-cycles, expected {}", test.cycles.len()));
+            }
+            if accesses != expected_accesses {
+                return Err(format!("bus accesses on M-cycles {accesses:?}, expected {expected_accesses:?}"));
+            }
         }
+        Ok(())
     }
 
-    #[datatest::files("sm83/v1", {
-        path in r"^(.*).json",
-    })]
-    fn datatest_run_test_file(path: &Path) {
+    // Runs every test in a file, and describes the ones that failed.
+    fn run_test_file(path: &PathBuf, check: Check) -> Vec<String> {
+        let file_contents: String = fs::read_to_string(path).expect("Could not read test file");
+        let tests: Vec<SingleStepTest> =
+            serde_json::from_str(&file_contents).expect("Could not parse test JSON");
+
         let mut gameboy = gb::init();
         gameboy.test_mode = true;
         gameboy.memory.mapping_type = MappingType::Flat;
-        run_test_file(&mut gameboy, &PathBuf::from(path));
+        tests
+            .iter()
+            .filter_map(|test| run_individual_test(&mut gameboy, test, check).err().map(|e| format!("{}: {e}", test.name)))
+            .collect()
     }
 
-    fn run_test_file(gameboy: &mut gb::GameBoy, path: &PathBuf) {
-        println!("{:?}", path.file_name().unwrap());
-        let file_contents: String = fs::read_to_string(path).expect("Could not read test file");
-        let tests_json: serde_json::Value =
-            serde_json::from_str(&file_contents).expect("Could not parse test JSON");
+    // Returns the name of every opcode file with failing tests.
+    fn run_all(check: Check) -> Vec<String> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("sm83/v1");
+        let entries = fs::read_dir(&dir).unwrap_or_else(|e| {
+            panic!("{}: {e}, clone https://github.com/SingleStepTests/sm83 into the dmg folder", dir.display())
+        });
+        let mut paths: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "json"))
+            .collect();
+        paths.sort();
+
+        let threads = thread::available_parallelism().map_or(4, |n| n.get());
+        let chunk_size = paths.len().div_ceil(threads).max(1);
+        let results: Vec<(PathBuf, Vec<String>)> = thread::scope(|scope| {
+            let handles: Vec<_> = paths
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || chunk.iter().map(|path| (path.clone(), run_test_file(path, check))).collect::<Vec<_>>())
+                })
+                .collect();
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        });
 
-        if let Some(tests_vector) = tests_json.as_array() {
-            for test in tests_vector {
-                run_individual_test(gameboy, test);
+        assert!(!results.is_empty(), "no opcode files in {}", dir.display());
+        let mut failed_files = vec![];
+        for (path, failures) in &results {
+            if failures.is_empty() {
+                continue;
             }
-        } else {
-            println!("Could not parse test JSON as JSON array");
-            assert!(false);
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            println!("{name}: {} failed", failures.len());
+            for failure in failures.iter().take(FAILURES_SHOWN) {
+                println!("    {failure}");
+            }
+            failed_files.push(name);
+        }
+        println!("{} of {} opcode files have failing tests", failed_files.len(), results.len());
+        failed_files
+    }
+
+    #[test]
+    fn sm83_results() {
+        let failed_files = run_all(Check::Results);
+        assert!(failed_files.is_empty(), "failing opcode files: {failed_files:?}");
+    }
+
+    #[test]
+    fn sm83_bus_timing() {
+        let known_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/sm83_timing_failures.txt");
+        let failed_files = run_all(Check::Timing);
+        if std::env::var_os("SM83_BLESS").is_some() {
+            let mut list = String::from(TIMING_FAILURES_HEADER);
+            for name in &failed_files {
+                list += &format!("{name}\n");
+            }
+            fs::write(&known_path, list).unwrap();
+            return;
+        }
+
+        let known = fs::read_to_string(&known_path).unwrap_or_default();
+        let known: Vec<&str> = known.lines().filter(|line| !line.is_empty() && !line.starts_with('#')).collect();
+        for name in known.iter().filter(|name| !failed_files.iter().any(|failed| failed == *name)) {
+            println!("{name} passes now, take it off {}", known_path.display());
         }
+        let regressions: Vec<&String> = failed_files.iter().filter(|name| !known.contains(&name.as_str())).collect();
+        assert!(regressions.is_empty(), "opcode files with wrong bus timing: {regressions:?}");
     }
 }
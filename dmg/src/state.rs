@@ -0,0 +1,151 @@
+// Save states. A state is a small header followed by the whole GameBoy serialised with bincode,
+// minus the boot ROM and cartridge ROM which stay loaded in the running GameBoy, whatever is
+// plugged into the serial port, the debugger's breakpoints, and the key log.
+use crate::gb::GameBoy;
+use std::fmt;
+use std::mem::swap;
+
+const SAVE_STATE_MAGIC: &[u8; 8] = b"SCGBSTAT";
+// Bump this whenever anything serialised in GameBoy changes shape.
+pub const SAVE_STATE_VERSION: u32 = 8;
+const HEADER_SIZE: usize = 16;
+
+#[derive(Debug)]
+pub enum StateError {
+    NotASaveState,
+    UnsupportedVersion(u32),
+    WrongCartridge,
+    Decode(bincode::Error),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::NotASaveState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(v) => write!(
+                f,
+                "save state version {v} is not supported (expected {SAVE_STATE_VERSION})"
+            ),
+            StateError::WrongCartridge => write!(f, "save state was made with a different cartridge"),
+            StateError::Decode(e) => write!(f, "couldn't decode save state: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl GameBoy {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_SIZE + 0x30000);
+        out.extend_from_slice(SAVE_STATE_MAGIC);
+        out.extend_from_slice(&SAVE_STATE_VERSION.to_le_bytes());
+        out.extend_from_slice(&self.cartridge_id().to_le_bytes());
+        bincode::serialize_into(&mut out, self).expect("GameBoy should always serialise");
+        out
+    }
+
+    // On error the running GameBoy is left untouched.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        if data.len() < HEADER_SIZE || &data[0..8] != SAVE_STATE_MAGIC {
+            return Err(StateError::NotASaveState);
+        }
+        let version = u32::from_le_bytes(data[8..12].try_into().unwrap());
+        if version != SAVE_STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        if u32::from_le_bytes(data[12..16].try_into().unwrap()) != self.cartridge_id() {
+            return Err(StateError::WrongCartridge);
+        }
+        let mut state: GameBoy =
+            bincode::deserialize(&data[HEADER_SIZE..]).map_err(StateError::Decode)?;
+
+        swap(&mut state.memory.boot_rom, &mut self.memory.boot_rom);
+        swap(&mut state.memory.cartridge, &mut self.memory.cartridge);
+        swap(&mut state.logger, &mut self.logger);
+        swap(&mut state.serial.device, &mut self.serial.device);
+        swap(&mut state.debugger, &mut self.debugger);
+        swap(&mut state.key_log, &mut self.key_log);
+        *self = state;
+        Ok(())
+    }
+
+    // Header and global checksums (0x14D-0x14F), enough to catch loading a state into the wrong game.
+    fn cartridge_id(&self) -> u32 {
+        match self.memory.cartridge.get(0x14D..=0x14F) {
+            Some(checksums) => u32::from_le_bytes([checksums[0], checksums[1], checksums[2], 0]),
+            None => 0,
+        }
+    }
+}
+
+// serde only implements (De)Serialize for arrays up to 32 elements. The big buffers are boxed so
+// that a GameBoy stays small enough to move around on the stack.
+pub(crate) mod big_array {
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer, T: Serialize, const N: usize>(
+        array: &[T; N],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(array.iter())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>, T: Deserialize<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<Box<[T; N]>, D::Error> {
+        let items = Vec::<T>::deserialize(deserializer)?;
+        let len = items.len();
+        items
+            .into_boxed_slice()
+            .try_into()
+            .map_err(|_| D::Error::invalid_length(len, &"an array of the right size"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gb;
+
+    fn looping_gameboy() -> gb::GameBoy {
+        let mut gameboy = gb::init();
+        let mut rom = vec![0; 0x8000];
+        // 0x100: inc a; ld (hl+), a; jr -4
+        rom[0x100..0x104].copy_from_slice(&[0x3C, 0x22, 0x18, 0xFC]);
+        gameboy.memory.cartridge = rom;
+        gameboy.mbc = gameboy.detect_mbc();
+        gameboy.r.bank = 1;
+        gameboy.r.pc = 0x100;
+        gameboy.set_hl(0xC000);
+        gameboy.r.lcdc = 0x91;
+        gameboy.r.tac = 0x05;
+        gameboy.r.ie = 0x05;
+        gameboy
+    }
+
+    #[test]
+    fn load_state_resumes_bit_for_bit() {
+        let mut gameboy = looping_gameboy();
+        for _ in 0..12345 {
+            gameboy.tick();
+        }
+        let saved = gameboy.save_state();
+        for _ in 0..40000 {
+            gameboy.tick();
+        }
+        let expected = gameboy.save_state();
+
+        let mut restored = looping_gameboy();
+        restored.load_state(&saved).unwrap();
+        for _ in 0..40000 {
+            restored.tick();
+        }
+        assert!(restored.save_state() == expected);
+    }
+
+    #[test]
+    fn load_state_rejects_garbage() {
+        let mut gameboy = looping_gameboy();
+        assert!(gameboy.load_state(b"definitely not a save state").is_err());
+    }
+}
@@ -0,0 +1,231 @@
+// CPU trace in the gameboy-doctor format (https://github.com/robert/gameboy-doctor), one line per
+// instruction with the registers before it runs and the 4 bytes at PC:
+//
+//   A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+//
+// Diffing a trace against one from another emulator finds the first instruction that goes wrong.
+// `with_disassembly` adds the instruction after the doctor's columns, `... PCMEM:00,C3,13,02 | nop`,
+// which the doctor won't compare against.
+// The doctor's reference traces were made with LY always reading 0x90, see `with_ly_stub`.
+use crate::gb::GameBoy;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub struct Trace {
+    out: Box<dyn Write>,
+    // don't write anything until PC reaches this
+    start_at: Option<u16>,
+    // stop for good when PC reaches this
+    stop_at: Option<u16>,
+    max_lines: Option<u64>,
+    pub(crate) stub_ly: bool,
+    disassembly: bool,
+    started: bool,
+    done: bool,
+    // the first write that failed, tracing stops there
+    error: Option<io::Error>,
+    pub lines: u64,
+}
+
+impl Trace {
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Self {
+            out,
+            start_at: None,
+            stop_at: None,
+            max_lines: None,
+            stub_ly: false,
+            disassembly: false,
+            started: false,
+            done: false,
+            error: None,
+            lines: 0,
+        }
+    }
+
+    pub fn to_file(path: &Path) -> io::Result<Self> {
+        Ok(Self::new(Box::new(BufWriter::new(File::create(path)?))))
+    }
+
+    pub fn starting_at(mut self, pc: u16) -> Self {
+        self.start_at = Some(pc);
+        self
+    }
+
+    pub fn stopping_at(mut self, pc: u16) -> Self {
+        self.stop_at = Some(pc);
+        self
+    }
+
+    pub fn with_max_lines(mut self, lines: u64) -> Self {
+        self.max_lines = Some(lines);
+        self
+    }
+
+    // LY reads 0x90 while tracing, as the gameboy-doctor expects
+    pub fn with_ly_stub(mut self) -> Self {
+        self.stub_ly = true;
+        self
+    }
+
+    pub fn with_disassembly(mut self) -> Self {
+        self.disassembly = true;
+        self
+    }
+
+    // Whether the trace is over, because it hit its stop trigger or line limit.
+    fn finished(&self, pc: u16) -> bool {
+        self.stop_at == Some(pc) || self.max_lines.is_some_and(|max| self.lines >= max)
+    }
+}
+
+impl GameBoy {
+    pub fn start_trace(&mut self, trace: Trace) {
+        self.logger.trace = Some(trace);
+    }
+
+    // Flushes the trace and hands it back, with the number of lines written, or the error if any of
+    // it couldn't be written.
+    pub fn stop_trace(&mut self) -> io::Result<Option<Trace>> {
+        let Some(mut trace) = self.logger.trace.take() else { return Ok(None) };
+        match trace.error.take() {
+            Some(e) => Err(e),
+            None => trace.out.flush().map(|()| Some(trace)),
+        }
+    }
+
+    // Called before each instruction is fetched.
+    pub(crate) fn trace_instruction(&mut self) {
+        let pc = self.r.pc;
+        let Some(trace) = &mut self.logger.trace else { return };
+        if trace.done {
+            return;
+        }
+        if trace.finished(pc) {
+            trace.done = true;
+            trace.error = trace.out.flush().err();
+            return;
+        }
+        if !trace.started && trace.start_at.map_or(true, |start| start == pc) {
+            trace.started = true;
+        }
+        if !trace.started {
+            return;
+        }
+
+        let pcmem = [0, 1, 2, 3].map(|i| self.peek(pc.wrapping_add(i)));
+        let r = &self.r;
+        let mut line = format!(
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l, r.sp, pc, pcmem[0], pcmem[1], pcmem[2], pcmem[3]
+        );
+        if self.logger.trace.as_ref().unwrap().disassembly {
+            line += &format!(" | {}", self.disassemble(pc));
+        }
+        let trace = self.logger.trace.as_mut().unwrap();
+        match writeln!(trace.out, "{line}") {
+            Ok(()) => trace.lines += 1,
+            Err(e) => {
+                trace.done = true;
+                trace.error = Some(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::load_rom;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn gameboy_doctor_format() {
+        let mut rom = vec![0; 0x8000];
+        // 0x100: nop; jp 0x150; 0x150: ldh a, (0x44); jr -2
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x150..0x154].copy_from_slice(&[0xF0, 0x44, 0x18, 0xFC]);
+        let mut gameboy = load_rom(rom, None);
+        let buffer = SharedBuffer::default();
+        gameboy.start_trace(Trace::new(Box::new(buffer.clone())).starting_at(0x101).with_max_lines(4).with_ly_stub());
+        for _ in 0..100 {
+            gameboy.tick();
+        }
+        assert_eq!(gameboy.stop_trace().unwrap().unwrap().lines, 4);
+
+        let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,50,01,00",
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0150 PCMEM:F0,44,18,FC",
+                "A:90 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0152 PCMEM:18,FC,00,00",
+                "A:90 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0150 PCMEM:F0,44,18,FC",
+            ]
+        );
+    }
+
+    #[test]
+    fn disassembly_column() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        let mut gameboy = load_rom(rom, None);
+        let buffer = SharedBuffer::default();
+        gameboy.start_trace(Trace::new(Box::new(buffer.clone())).with_max_lines(2).with_disassembly());
+        for _ in 0..10 {
+            gameboy.tick();
+        }
+        gameboy.stop_trace().unwrap();
+
+        let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,50,01 | nop",
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,50,01,00 | jp $0150",
+            ]
+        );
+    }
+
+    struct FullDisk;
+
+    impl Write for FullDisk {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("no space left"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_errors_are_returned() {
+        let mut gameboy = load_rom(vec![0; 0x8000], None);
+        gameboy.start_trace(Trace::new(Box::new(FullDisk)));
+        for _ in 0..10 {
+            gameboy.tick();
+        }
+        let Err(e) = gameboy.stop_trace() else { panic!("the trace didn't fail") };
+        assert_eq!(e.to_string(), "no space left");
+        assert!(gameboy.stop_trace().unwrap().is_none());
+    }
+}
@@ -193,11 +193,8 @@ impl GameBoy {
         (self.r.bg_pal >> (data << 1)) & 0b11
     }
 
-    pub fn map_sprite_palette(&mut self, palette: u8, colour: u8) -> u8 {
-        (self.read(0xFF48 + palette as u16) >> (colour << 1)) & 0b11
-    }
-
-    pub fn oam(&self, offset: u8) -> u8 {
-        self.memory.main[self.oam_base as usize + offset as usize]
+    pub fn map_sprite_palette(&self, palette: u8, colour: u8) -> u8 {
+        let obp = if palette == 0 { self.r.obp0 } else { self.r.obp1 };
+        (obp >> (colour << 1)) & 0b11
     }
 }
@@ -0,0 +1,130 @@
+// Pictures of VRAM for the debug views: the tile data, the two tile maps and the sprites, as RGB555
+// through the current palettes, the way the PPU would draw them.
+use crate::gb::{GameBoy, Sprite};
+
+// 8000-97FF, shown 16 tiles across and 24 down
+pub const TILE_COUNT: usize = 384;
+pub const TILE_SHEET_WIDTH: usize = 16 * 8;
+pub const TILE_SHEET_HEIGHT: usize = 24 * 8;
+// a tile map is 32x32 tiles
+pub const TILE_MAP_SIZE: usize = 256;
+
+impl GameBoy {
+    // Colour number of pixel (x, y) of the tile whose data starts at `address`.
+    fn tile_pixel(&self, bank: u8, address: u16, x: u8, y: u8) -> u8 {
+        let low = self.vram(bank, address + 2 * y as u16);
+        let high = self.vram(bank, address + 2 * y as u16 + 1);
+        ((low >> (7 - x)) & 1) | (((high >> (7 - x)) & 1) << 1)
+    }
+
+    // All 384 tiles in a VRAM bank, through the background palette (BGP, or CGB palette 0).
+    pub fn render_tile_sheet(&self, bank: u8) -> Vec<u16> {
+        let mut image = vec![0; TILE_SHEET_WIDTH * TILE_SHEET_HEIGHT];
+        for tile in 0..TILE_COUNT {
+            let address = 0x8000 + tile as u16 * 16;
+            let (left, top) = (tile % 16 * 8, tile / 16 * 8);
+            for y in 0..8 {
+                for x in 0..8 {
+                    let colour = self.tile_pixel(bank, address, x, y);
+                    image[(top + y as usize) * TILE_SHEET_WIDTH + left + x as usize] =
+                        self.background_colour(0, 0, colour);
+                }
+            }
+        }
+        image
+    }
+
+    // The whole tile map at `base` (0x9800 or 0x9C00), 256x256, using the tile data LCDC bit 4
+    // selects and in CGB mode the attributes in VRAM bank 1.
+    pub fn render_tile_map(&self, base: u16) -> Vec<u16> {
+        let mut image = vec![0; TILE_MAP_SIZE * TILE_MAP_SIZE];
+        for entry in 0..32 * 32 {
+            let mut tile_num = self.vram(0, base + entry);
+            let attributes = if self.cgb.enabled { self.vram(1, base + entry) } else { 0 };
+            let mut tile_data_base = 0;
+            self.select_tile_addressing_method(&mut tile_num, &mut tile_data_base);
+            let address = tile_data_base + tile_num as u16 * 16;
+            let (left, top) = (entry as usize % 32 * 8, entry as usize / 32 * 8);
+            for y in 0..8 {
+                for x in 0..8 {
+                    let tile_x = if attributes & 0x20 != 0 { 7 - x } else { x };
+                    let tile_y = if attributes & 0x40 != 0 { 7 - y } else { y };
+                    let colour = self.tile_pixel((attributes >> 3) & 1, address, tile_x, tile_y);
+                    image[(top + y as usize) * TILE_MAP_SIZE + left + x as usize] =
+                        self.background_colour(0, attributes, colour);
+                }
+            }
+        }
+        image
+    }
+
+    // A sprite as it's drawn: 8 pixels wide and `size_y` tall, flipped, through its palette. Colour 0
+    // is transparent and comes out as None.
+    pub fn render_sprite_preview(&self, sprite: &Sprite) -> Vec<Option<u16>> {
+        // In 8x16 mode bit 0 of the tile number is ignored, the bottom half is the next tile.
+        let tile_num = if sprite.size_y == 16 { sprite.tile_num & !1 } else { sprite.tile_num };
+        let address = 0x8000 + tile_num as u16 * 16;
+        let mut image = Vec::with_capacity(8 * sprite.size_y as usize);
+        for y in 0..sprite.size_y {
+            let row = if sprite.y_flip != 0 { sprite.size_y - 1 - y } else { y };
+            for x in 0..8 {
+                let column = if sprite.x_flip != 0 { 7 - x } else { x };
+                // the tiles of an 8x16 sprite are next to each other, so row 8 is row 0 of the second
+                let colour = self.tile_pixel(sprite.vram_bank, address + (row as u16 / 8) * 16, column, row % 8);
+                image.push((colour != 0).then(|| self.sprite_colour(0, *sprite, colour)));
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::gb;
+    use crate::renderer::DMG_COLOURS;
+
+    fn gameboy() -> gb::GameBoy {
+        let mut gameboy = gb::init();
+        gameboy.r.bg_pal = 0b11_10_01_00;
+        gameboy.r.obp0 = 0b11_10_01_00;
+        // tile 1: colour 3 in the top left pixel, colour 1 in the rest of the top row
+        gameboy.memory.main[0x8010] = 0xFF;
+        gameboy.memory.main[0x8011] = 0x80;
+        gameboy
+    }
+
+    #[test]
+    fn tile_map_follows_lcdc_addressing() {
+        let mut gameboy = gameboy();
+        // the same data as tile 1 at 9010, tile 1 with 8800 addressing
+        gameboy.memory.main[0x9010] = 0xFF;
+        gameboy.memory.main[0x9C21] = 1;
+
+        gameboy.r.lcdc = 0x10;
+        let map = gameboy.render_tile_map(0x9C00);
+        // entry 0x21 is column 1 row 1, at (8, 8)
+        assert_eq!(map[8 * 256 + 8], DMG_COLOURS[3]);
+        assert_eq!(map[8 * 256 + 9], DMG_COLOURS[1]);
+        assert_eq!(map[9 * 256 + 8], DMG_COLOURS[0]);
+
+        gameboy.r.lcdc = 0;
+        let map = gameboy.render_tile_map(0x9C00);
+        assert_eq!(map[8 * 256 + 8], DMG_COLOURS[1]);
+        assert_eq!(gameboy.render_tile_map(0x9800)[8 * 256 + 8], DMG_COLOURS[0]);
+    }
+
+    #[test]
+    fn sprites_are_flipped_and_transparent() {
+        let mut gameboy = gameboy();
+        // tile 1, x flip
+        gameboy.memory.main[0xFE00..0xFE04].copy_from_slice(&[16, 8, 1, 0x20]);
+        let sprite = gameboy.oam_sprite(0);
+        assert_eq!((sprite.x, sprite.y, sprite.size_y), (0, 0, 8));
+
+        let image = gameboy.render_sprite_preview(&sprite);
+        assert_eq!(image.len(), 64);
+        assert_eq!(image[7], Some(DMG_COLOURS[3]));
+        assert_eq!(image[0], Some(DMG_COLOURS[1]));
+        assert_eq!(image[8], None);
+    }
+}
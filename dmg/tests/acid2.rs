@@ -0,0 +1,67 @@
+// dmg-acid2 (https://github.com/mattcurrie/dmg-acid2) regression test, against the reference image
+// from the same repository. Both are MIT licensed and live in tests/roms, tests/roms/fetch.sh
+// downloads them if they're missing.
+use dmg::renderer::DMG_COLOURS;
+use dmg::runner::{self, M_CYCLES_PER_FRAME};
+use std::fs::{self, File};
+use std::path::Path;
+
+const FRAMES: u64 = 20;
+
+fn read(path: &Path) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| panic!("{}: {e}, run tests/roms/fetch.sh to download it", path.display()))
+}
+
+// Shade 0-3 of each pixel of a greyscale or RGB reference image.
+fn reference_shades(png: &[u8]) -> Vec<u8> {
+    let mut decoder = png::Decoder::new(png);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    assert_eq!((info.width, info.height), (160, 144));
+    let channels = info.color_type.samples();
+    pixels[..info.buffer_size()]
+        .chunks_exact(channels)
+        .map(|pixel| 3 - ((pixel[0] as u16 + 0x2A) / 0x55) as u8)
+        .collect()
+}
+
+// Writes what was drawn next to the reference, to compare them by eye when the test fails.
+fn save_actual(path: &Path, shades: &[u8]) {
+    let mut encoder = png::Encoder::new(File::create(path).unwrap(), 160, 144);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let pixels: Vec<u8> = shades.iter().map(|shade| 0xFF - shade * 0x55).collect();
+    encoder.write_header().unwrap().write_image_data(&pixels).unwrap();
+}
+
+#[test]
+fn dmg_acid2_matches_reference() {
+    let roms = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms");
+    let rom = read(&roms.join("dmg-acid2.gb"));
+    let reference = reference_shades(&read(&roms.join("reference-dmg.png")));
+
+    let mut gameboy = runner::load_rom(rom, None);
+    for _ in 0..FRAMES * M_CYCLES_PER_FRAME {
+        gameboy.tick();
+    }
+
+    let shades: Vec<u8> = gameboy
+        .display()
+        .iter()
+        .map(|colour| DMG_COLOURS.iter().position(|c| c == colour).unwrap() as u8)
+        .collect();
+    let wrong = shades.iter().zip(&reference).filter(|(a, b)| a != b).count();
+    if wrong > 0 {
+        let actual = std::env::temp_dir().join("dmg-acid2-actual.png");
+        save_actual(&actual, &shades);
+        let first = shades.iter().zip(&reference).position(|(a, b)| a != b).unwrap();
+        panic!(
+            "{wrong} pixels differ from the reference, the first at ({}, {}). The screen is in {}",
+            first % 160,
+            first / 160,
+            actual.display()
+        );
+    }
+}
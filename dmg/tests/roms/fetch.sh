@@ -0,0 +1,36 @@
+#!/bin/sh
+# Downloads the test ROMs the tests in dmg/tests need into this folder. Files already here are
+# left alone.
+set -eu
+cd "$(dirname "$0")"
+
+fetch() {
+    if [ ! -e "$2" ]; then
+        echo "Downloading $2"
+        curl -fsSL -o "$2" "$1"
+    fi
+}
+
+fetch https://github.com/mattcurrie/dmg-acid2/releases/download/v1.0/dmg-acid2.gb dmg-acid2.gb
+fetch https://raw.githubusercontent.com/mattcurrie/dmg-acid2/master/img/reference-dmg.png reference-dmg.png
+
+# Blargg's tests and the Mooneye test suite, from the collection at
+# https://github.com/c-sp/gameboy-test-roms
+if [ ! -d blargg ] || [ ! -d mooneye ]; then
+    echo "Downloading gameboy-test-roms"
+    archive=$(mktemp -d)
+    trap 'rm -rf "$archive"' EXIT
+    curl -fsSL -o "$archive/roms.zip" \
+        https://github.com/c-sp/gameboy-test-roms/releases/download/v7.0/game-boy-test-roms-v7.0.zip
+    unzip -q "$archive/roms.zip" -d "$archive"
+    if [ ! -d blargg ]; then
+        mkdir blargg
+        for test in cpu_instrs instr_timing mem_timing; do
+            cp "$archive/blargg/$test/$test.gb" blargg/
+        done
+    fi
+    if [ ! -d mooneye ]; then
+        mkdir mooneye
+        cp -r "$archive/mooneye-test-suite/acceptance" mooneye/
+    fi
+fi
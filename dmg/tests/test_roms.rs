@@ -0,0 +1,202 @@
+// Conformance suite: Blargg's tests and the Mooneye acceptance tests, in tests/roms:
+//
+//   tests/roms/blargg/        cpu_instrs.gb, instr_timing.gb, mem_timing.gb (or the individual
+//                             tests of each). Not redistributable, so not committed.
+//   tests/roms/mooneye/       the acceptance folder of the Mooneye test suite, any layout
+//
+// tests/roms/fetch.sh downloads whichever are missing, and the suite fails without them. dmg-acid2
+// has its own test, see acid2.rs.
+//
+// Every ROM found is run and the results are printed as a table (see them with
+// `cargo test --test test_roms -- --nocapture`). Tests listed in tests/known_failures.txt are
+// expected to fail, any other failure fails the suite, so regressions in the CPU or PPU get caught.
+// Running with TEST_ROMS_BLESS=1 rewrites that list with whatever fails now.
+use dmg::gb::GameBoy;
+use dmg::runner::{self, Runner, M_CYCLES_PER_FRAME};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+// cpu_instrs takes almost a minute of emulated time
+const BLARGG_MAX_FRAMES: u64 = 60 * 70;
+const MOONEYE_MAX_FRAMES: u64 = 60 * 10;
+
+// Blargg's tests that don't print over serial leave their result in cartridge RAM
+const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+const BLARGG_RUNNING: u8 = 0x80;
+
+// Mooneye tests finish with LD B,B, having set these registers on success and all to 0x42 on failure
+const LD_B_B: u8 = 0x40;
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+
+const KNOWN_FAILURES_HEADER: &str = "\
+# Test ROMs that are known to fail, one per line as the path under tests/roms, e.g.
+# mooneye/acceptance/ppu/intr_2_mode0_timing_sprites.gb
+# The suite fails if any other test fails, and notes it when one of these starts passing.
+# Regenerate with `TEST_ROMS_BLESS=1 cargo test --release --test test_roms` in the dmg folder.
+";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Blargg,
+    Mooneye,
+}
+
+enum Outcome {
+    Pass,
+    Fail(String),
+}
+
+fn roms_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/roms")
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else { return };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().is_some_and(|extension| extension == "gb") {
+            roms.push(path);
+        }
+    }
+}
+
+// Mooneye test names end in the models they're meant for, e.g. boot_regs-dmgABC or di_timing-GS
+// (G is the DMG). Tests without a suffix run on everything.
+fn runs_on_dmg(path: &Path) -> bool {
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    match name.rsplit_once('-') {
+        Some((_, models)) => {
+            models.contains("dmgABC") || (models.contains('G') && models.chars().all(|c| "GSCA".contains(c)))
+        }
+        None => true,
+    }
+}
+
+fn load(path: &Path) -> GameBoy {
+    runner::load_rom(fs::read(path).unwrap(), None)
+}
+
+fn run_blargg(path: &Path) -> Outcome {
+    let mut runner = Runner::new(load(path), false);
+    while runner.frames() < BLARGG_MAX_FRAMES {
+        runner.run(runner.cycles + M_CYCLES_PER_FRAME, None);
+        let output = runner.serial_output();
+        if output.contains("Passed") {
+            return Outcome::Pass;
+        }
+        if output.contains("Failed") {
+            return Outcome::Fail(output.trim().lines().last().unwrap_or_default().to_string());
+        }
+        let gameboy = &runner.gameboy;
+        let signature = [gameboy.peek(0xA001), gameboy.peek(0xA002), gameboy.peek(0xA003)];
+        let status = gameboy.peek(0xA000);
+        if signature == BLARGG_SIGNATURE && status != BLARGG_RUNNING {
+            return match status {
+                0 => Outcome::Pass,
+                code => Outcome::Fail(format!("result code {code}")),
+            };
+        }
+    }
+    Outcome::Fail("timed out".to_string())
+}
+
+fn run_mooneye(path: &Path) -> Outcome {
+    let mut gameboy = load(path);
+    for _ in 0..MOONEYE_MAX_FRAMES * M_CYCLES_PER_FRAME {
+        gameboy.tick();
+        let r = &gameboy.r;
+        let registers = [r.b, r.c, r.d, r.e, r.h, r.l];
+        if registers != MOONEYE_PASS && registers != MOONEYE_FAIL {
+            continue;
+        }
+        if gameboy.peek(gameboy.r.pc) == LD_B_B {
+            return match registers == MOONEYE_PASS {
+                true => Outcome::Pass,
+                false => Outcome::Fail("failure registers".to_string()),
+            };
+        }
+    }
+    Outcome::Fail("timed out".to_string())
+}
+
+fn run(kind: Kind, path: &Path) -> Outcome {
+    match kind {
+        Kind::Blargg => run_blargg(path),
+        Kind::Mooneye => run_mooneye(path),
+    }
+}
+
+fn known_failures_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/known_failures.txt")
+}
+
+fn known_failures() -> Vec<String> {
+    fs::read_to_string(known_failures_path())
+        .unwrap_or_default()
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn test_rom_suite() {
+    let dir = roms_dir();
+    let mut tests = vec![];
+    for (kind, subdir) in [(Kind::Blargg, "blargg"), (Kind::Mooneye, "mooneye")] {
+        let mut roms = vec![];
+        find_roms(&dir.join(subdir), &mut roms);
+        roms.retain(|path| kind != Kind::Mooneye || runs_on_dmg(path));
+        // a suite that silently runs nothing isn't checking anything
+        assert!(
+            !roms.is_empty(),
+            "no test ROMs in {}, run tests/roms/fetch.sh to download them",
+            dir.join(subdir).display()
+        );
+        tests.extend(roms.into_iter().map(|path| (kind, path)));
+    }
+    tests.sort_by(|a, b| a.1.cmp(&b.1));
+
+    // each test gets its own thread, GameBoys aren't Send so they're made inside it
+    let outcomes: Vec<Outcome> = thread::scope(|scope| {
+        let handles: Vec<_> = tests
+            .iter()
+            .map(|(kind, path)| scope.spawn(move || run(*kind, path)))
+            .collect();
+        handles.into_iter().map(|handle| handle.join().unwrap()).collect()
+    });
+
+    let known_failures = known_failures();
+    let mut failures = vec![];
+    let mut regressions = vec![];
+    println!("{:<60} result", "test");
+    for ((_, path), outcome) in tests.iter().zip(&outcomes) {
+        let name = path.strip_prefix(&dir).unwrap().to_string_lossy().replace('\\', "/");
+        let known = known_failures.contains(&name);
+        let result = match (outcome, known) {
+            (Outcome::Pass, false) => "pass".to_string(),
+            (Outcome::Pass, true) => "pass (listed as a known failure)".to_string(),
+            (Outcome::Fail(reason), true) => format!("FAIL (known): {reason}"),
+            (Outcome::Fail(reason), false) => {
+                regressions.push(name.clone());
+                format!("FAIL: {reason}")
+            }
+        };
+        if matches!(outcome, Outcome::Fail(_)) {
+            failures.push(name.clone());
+        }
+        println!("{name:<60} {result}");
+    }
+    let passed = outcomes.iter().filter(|outcome| matches!(outcome, Outcome::Pass)).count();
+    println!("{passed}/{} passed", outcomes.len());
+    if std::env::var_os("TEST_ROMS_BLESS").is_some() {
+        let list: String = failures.iter().map(|name| format!("{name}\n")).collect();
+        fs::write(known_failures_path(), format!("{KNOWN_FAILURES_HEADER}{list}")).unwrap();
+        return;
+    }
+    assert!(regressions.is_empty(), "unexpected failures: {regressions:?}");
+}
@@ -1,47 +1,135 @@
+use crate::audio::AudioOutput;
+use crate::emulator;
+use crate::emulator::link::LinkedPlayer;
+use crate::emulator::rewind::REWIND_SECONDS;
+use crate::options;
+use crate::widgets::cpu_inspector::CpuInspector;
+use crate::widgets::debugger::DebuggerWindow;
 use crate::widgets::framebuf::FrameBufWidget;
-use dmg::gb;
+use crate::widgets::memory_viewer::MemoryViewer;
+use crate::widgets::vram_viewer::VramViewer;
+use crate::Options;
 use dmg::gb::GameBoy;
-use std::{env, fs};
+use dmg::movie::{Movie, MoviePlayer};
+use dmg::rewind::Rewind;
+use dmg::save;
+use dmg::serial::tcp::TcpLink;
+use std::io;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+
+// Seconds between writes of battery-backed RAM to the .sav, whatever the repaint rate.
+const SRAM_FLUSH_INTERVAL: f64 = 1.0;
+
 pub struct ScgbGui {
     pub framebuf: FrameBufWidget,
     pub gameboy: GameBoy,
+    pub rom_path: PathBuf,
+    pub sav_path: Option<PathBuf>,
+    pub status: String,
+    // egui time of the last .sav write
+    sram_flushed_at: f64,
+    pub(crate) audio: Option<AudioOutput>,
+    pub(crate) last_update_time: Option<f64>,
+    pub(crate) cycle_debt: f64,
+    // player 2, when running two GameBoys over a link cable
+    pub link: Option<LinkedPlayer>,
+    // with --link-host or --link-connect, the link cable once it has connected
+    pub(crate) waiting_for_link: Option<Receiver<io::Result<TcpLink>>>,
+    pub rewind: Rewind,
+    // the movie being recorded, or played back
+    pub movie: Option<Movie>,
+    pub playback: Option<MoviePlayer>,
+    pub debugger: DebuggerWindow,
+    pub memory_viewer: MemoryViewer,
+    pub vram_viewer: VramViewer,
+    pub cpu_inspector: CpuInspector,
+}
+
+// Loads the save file for a cartridge with battery backed RAM, returning where to write it back.
+pub fn load_battery_save(gameboy: &mut GameBoy, path: PathBuf) -> Option<PathBuf> {
+    if !gameboy.has_battery() {
+        return None;
+    }
+    match gameboy.load_sav(&path) {
+        Ok(true) => log::info!("Loaded save file {}", path.display()),
+        Ok(false) => {}
+        Err(e) => log::error!("Couldn't read save file {}: {}", path.display(), e),
+    }
+    Some(path)
+}
+
+fn flush_sram(gameboy: &mut GameBoy, sav_path: &Option<PathBuf>) {
+    if let Some(path) = sav_path {
+        if gameboy.sram_dirty() {
+            if let Err(e) = gameboy.save_sav(path) {
+                log::error!("Couldn't write save file {}: {}", path.display(), e);
+            }
+        }
+    }
 }
 
 impl ScgbGui {
     /// Called once before the first frame.
-    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+    pub fn new(cc: &eframe::CreationContext<'_>, options: Options) -> Self {
         // This is also where you can customize the look and feel of egui using
         // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
 
         let framebuf = FrameBufWidget::new(cc);
-        let mut gameboy = gb::init();
-
-        let data: Vec<u8> = fs::read(
-            "dmg_boot.bin",
-        )
-        .expect("couldnt read file");
-
-        let args: Vec<String> = env::args().collect();
-        if args.len() != 2 {
-            panic!("Provide the ROM file to run")
+        let mut gameboy = emulator::load_gameboy(&options.rom_path);
+        gameboy.renderer_kind = options.renderer;
+        let mut status = String::new();
+        let mut waiting_for_link = None;
+        match options::link_in_background(&options.serial) {
+            Some(Ok(receiver)) => {
+                waiting_for_link = Some(receiver);
+                status = options.serial.waiting_message().unwrap_or_default();
+            }
+            Some(Err(e)) => status = format!("Couldn't connect the link cable: {e}"),
+            None => match options.serial.device(&options.rom_path) {
+                Ok(device) => gameboy.connect_serial(device),
+                Err(e) => status = format!("Couldn't connect the link cable: {e}"),
+            },
         }
 
-        let rom: Vec<u8> = fs::read(
-            args[1].clone(),
-        )
-        .expect("couldnt read file");
+        let sav_path = load_battery_save(&mut gameboy, save::sav_path(&options.rom_path));
+        let link = options
+            .link
+            .as_deref()
+            .map(|rom_path| LinkedPlayer::new(cc, &mut gameboy, &options.rom_path, rom_path));
 
-        for i in 0..=0xFF {
-            gameboy.memory.boot_rom[i] = data[i];
+        let audio = AudioOutput::new(gameboy.apu.sample_rate());
+        if audio.is_none() {
+            log::info!("No audio output, running silently");
         }
 
-        gameboy.memory.cartridge = rom;
-
-        gameboy.mbc = gameboy.detect_mbc();
-        let cart_decode = gameboy.decode_cart_header();
-        gameboy.logger.log_info(&format!("{:?}", cart_decode));
+        Self {
+            framebuf,
+            gameboy,
+            rom_path: options.rom_path,
+            sav_path,
+            status,
+            sram_flushed_at: 0.0,
+            audio,
+            last_update_time: None,
+            cycle_debt: 0.0,
+            link,
+            waiting_for_link,
+            rewind: Rewind::new(REWIND_SECONDS),
+            movie: None,
+            playback: None,
+            debugger: DebuggerWindow::default(),
+            memory_viewer: MemoryViewer::default(),
+            vram_viewer: VramViewer::default(),
+            cpu_inspector: CpuInspector::default(),
+        }
+    }
 
-        Self { framebuf, gameboy }
+    pub fn flush_sram(&mut self) {
+        flush_sram(&mut self.gameboy, &self.sav_path);
+        if let Some(link) = &mut self.link {
+            flush_sram(&mut link.gameboy, &link.sav_path);
+        }
     }
 }
 
@@ -50,6 +138,12 @@ impl eframe::App for ScgbGui {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         self.draw(ctx);
 
+        let now = ctx.input(|i| i.time);
+        if now - self.sram_flushed_at >= SRAM_FLUSH_INTERVAL {
+            self.sram_flushed_at = now;
+            self.flush_sram();
+        }
+
         // Put your widgets into a `SidePanel`, `TopBottomPanel`, `CentralPanel`, `Window` or `Area`.
         // For inspiration and more examples, go to https://emilk.github.io/egui
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
@@ -64,6 +158,14 @@ impl eframe::App for ScgbGui {
                             ctx.send_viewport_cmd(egui::ViewportCommand::Close);
                         }
                     });
+                    ui.menu_button("State", |ui| self.state_slot_menu(ui));
+                    ui.menu_button("Movie", |ui| self.movie_menu(ui));
+                    ui.menu_button("Debug", |ui| {
+                        ui.checkbox(&mut self.debugger.open, "Debugger");
+                        ui.checkbox(&mut self.cpu_inspector.open, "CPU");
+                        ui.checkbox(&mut self.memory_viewer.open, "Memory");
+                        ui.checkbox(&mut self.vram_viewer.open, "VRAM");
+                    });
                     ui.add_space(16.0);
                 }
 
@@ -71,19 +173,46 @@ impl eframe::App for ScgbGui {
             });
         });
 
+        self.debugger.show(ctx, &mut self.gameboy);
+        self.cpu_inspector.show(ctx, &mut self.gameboy);
+        self.memory_viewer.show(ctx, &mut self.gameboy);
+        self.vram_viewer.show(ctx, &self.gameboy);
+
         egui::CentralPanel::default().show(ctx, |ui| {
             // The central panel the region left after adding TopPanel's and SidePanel's
             ui.heading("supercoolgb");
-
-            ui.vertical_centered(|ui| {
-                let padding_height = (ui.available_height() - self.framebuf.scaled_height()) / 2.0;
-                if padding_height > 0.0 {
-                    ui.allocate_space(egui::Vec2::from([1.0, padding_height]));
+            ui.label(&self.status);
+
+            match &mut self.link {
+                Some(link) => {
+                    ui.columns(2, |columns| {
+                        columns[0].vertical_centered(|ui| {
+                            ui.label("Player 1");
+                            self.framebuf.draw(ui, &self.gameboy);
+                        });
+                        columns[1].vertical_centered(|ui| {
+                            ui.label("Player 2");
+                            link.framebuf.draw(ui, &link.gameboy);
+                        });
+                    });
                 }
-                self.framebuf.draw(ui, &self.gameboy);
-            });
+                None => {
+                    ui.vertical_centered(|ui| {
+                        let padding_height =
+                            (ui.available_height() - self.framebuf.scaled_height()) / 2.0;
+                        if padding_height > 0.0 {
+                            ui.allocate_space(egui::Vec2::from([1.0, padding_height]));
+                        }
+                        self.framebuf.draw(ui, &self.gameboy);
+                    });
+                }
+            }
 
-            ctx.request_repaint(); // TODO make this run at exactly 59.7Hz
+            ctx.request_repaint(); // emulation speed is paced by ScgbGui::run_emulation
         });
     }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        self.flush_sram();
+    }
 }
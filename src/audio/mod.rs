@@ -0,0 +1,142 @@
+// Audio output. Samples pulled from the APU go through a resampler into a ring buffer, which the
+// host audio callback drains. How full the ring buffer is also drives emulation speed, see
+// ScgbGui::run_emulation.
+// only constructed when there is an audio device to play to
+#[cfg_attr(not(all(feature = "audio", not(target_arch = "wasm32"))), allow(dead_code))]
+mod resampler;
+pub mod wav;
+
+use resampler::Resampler;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+// How much audio we try to keep queued, in seconds. Lower is less latency but more likely to crackle.
+const TARGET_LATENCY: f64 = 0.05;
+// How far the resampling ratio may be pushed to correct the buffer level (0.5%, inaudible).
+const MAX_RATE_ADJUST: f64 = 0.005;
+
+pub struct AudioOutput {
+    ring: Arc<Mutex<VecDeque<f32>>>,
+    resampler: Resampler,
+    source_rate: u32,
+    device_rate: u32,
+    #[cfg(all(feature = "audio", not(target_arch = "wasm32")))]
+    _stream: cpal::Stream,
+}
+
+impl AudioOutput {
+    // Returns None if audio support isn't compiled in or there is no usable output device.
+    #[cfg(all(feature = "audio", not(target_arch = "wasm32")))]
+    pub fn new(source_rate: u32) -> Option<Self> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+        let device = cpal::default_host().default_output_device()?;
+        let config = match device.default_output_config() {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("No usable audio output config: {e}");
+                return None;
+            }
+        };
+        let device_rate = config.sample_rate().0;
+        let ring = Arc::new(Mutex::new(VecDeque::new()));
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => host::build_stream::<f32>(&device, &config.config(), &ring),
+            cpal::SampleFormat::I16 => host::build_stream::<i16>(&device, &config.config(), &ring),
+            cpal::SampleFormat::U16 => host::build_stream::<u16>(&device, &config.config(), &ring),
+            format => {
+                log::error!("Unsupported audio sample format {format}");
+                return None;
+            }
+        };
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::error!("Couldn't open audio output: {e}");
+                return None;
+            }
+        };
+        if let Err(e) = stream.play() {
+            log::error!("Couldn't start audio output: {e}");
+            return None;
+        }
+
+        Some(Self {
+            ring,
+            resampler: Resampler::new(source_rate, device_rate),
+            source_rate,
+            device_rate,
+            _stream: stream,
+        })
+    }
+
+    #[cfg(not(all(feature = "audio", not(target_arch = "wasm32"))))]
+    pub fn new(_source_rate: u32) -> Option<Self> {
+        None
+    }
+
+    pub fn queued_frames(&self) -> usize {
+        self.ring.lock().unwrap().len() / 2
+    }
+
+    pub fn target_queued_frames(&self) -> usize {
+        (self.device_rate as f64 * TARGET_LATENCY) as usize
+    }
+
+    pub fn push(&mut self, samples: &[f32]) {
+        // Speed up or slow down playback very slightly depending on how far the buffer is from
+        // where we want it.
+        let target = self.target_queued_frames() as f64;
+        let error = (target - self.queued_frames() as f64) / target;
+        let adjust = 1.0 + (error * MAX_RATE_ADJUST).clamp(-MAX_RATE_ADJUST, MAX_RATE_ADJUST);
+        self.resampler
+            .set_ratio(self.device_rate as f64 / self.source_rate as f64 * adjust);
+
+        let mut ring = self.ring.lock().unwrap();
+        self.resampler.process(samples, &mut *ring);
+        // Never let more than a second pile up (e.g. if the device stalls).
+        let max_len = 2 * self.device_rate as usize;
+        if ring.len() > max_len {
+            let excess = ring.len() - max_len;
+            ring.drain(..excess + excess % 2);
+        }
+    }
+}
+
+#[cfg(all(feature = "audio", not(target_arch = "wasm32")))]
+mod host {
+    use cpal::traits::DeviceTrait;
+    use cpal::{FromSample, SizedSample};
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+
+    pub fn build_stream<T: SizedSample + FromSample<f32>>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        ring: &Arc<Mutex<VecDeque<f32>>>,
+    ) -> Result<cpal::Stream, cpal::BuildStreamError> {
+        let ring = Arc::clone(ring);
+        let channels = config.channels as usize;
+        device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                let mut ring = ring.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    // play silence if the emulator falls behind
+                    let left = ring.pop_front().unwrap_or(0.0);
+                    let right = ring.pop_front().unwrap_or(left);
+                    for (channel, sample) in frame.iter_mut().enumerate() {
+                        let value = match (channels, channel) {
+                            (2.., 0) => left,
+                            (2.., 1) => right,
+                            _ => (left + right) / 2.0,
+                        };
+                        *sample = T::from_sample(value);
+                    }
+                }
+            },
+            |e| log::error!("Audio output error: {e}"),
+            None,
+        )
+    }
+}
@@ -0,0 +1,79 @@
+// Linear interpolating resampler for interleaved stereo samples. The ratio can be nudged while
+// running, which is how the audio output keeps its buffer from draining or overflowing when the
+// emulator and the sound card clocks disagree slightly.
+pub struct Resampler {
+    ratio: f64, // output rate / input rate
+    position: f64,
+    previous: [f32; 2],
+}
+
+impl Resampler {
+    pub fn new(input_rate: u32, output_rate: u32) -> Self {
+        Self {
+            ratio: output_rate as f64 / input_rate as f64,
+            position: 0.0,
+            previous: [0.0; 2],
+        }
+    }
+
+    pub fn set_ratio(&mut self, ratio: f64) {
+        self.ratio = ratio;
+    }
+
+    pub fn process(&mut self, input: &[f32], output: &mut impl Extend<f32>) {
+        let step = 1.0 / self.ratio;
+        for frame in input.chunks_exact(2) {
+            // emit every output frame that falls between the previous input frame and this one
+            while self.position < 1.0 {
+                let t = self.position as f32;
+                output.extend([
+                    self.previous[0] + (frame[0] - self.previous[0]) * t,
+                    self.previous[1] + (frame[1] - self.previous[1]) * t,
+                ]);
+                self.position += step;
+            }
+            self.position -= 1.0;
+            self.previous = [frame[0], frame[1]];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames_out(resampler: &mut Resampler, input_frames: usize) -> usize {
+        let mut output = vec![];
+        resampler.process(&vec![0.0; input_frames * 2], &mut output);
+        assert_eq!(output.len() % 2, 0);
+        output.len() / 2
+    }
+
+    #[test]
+    fn converts_between_rates() {
+        // a second of audio comes out as a second of audio at the output rate
+        for (input_rate, output_rate) in [(48000, 44100), (48000, 48000), (44100, 48000), (48000, 96000)] {
+            let mut resampler = Resampler::new(input_rate, output_rate);
+            let frames = frames_out(&mut resampler, input_rate as usize);
+            assert!(frames.abs_diff(output_rate as usize) <= 1, "{input_rate} -> {output_rate}: {frames}");
+        }
+    }
+
+    #[test]
+    fn follows_the_ratio() {
+        let mut resampler = Resampler::new(48000, 48000);
+        resampler.set_ratio(1.005);
+        assert_eq!(frames_out(&mut resampler, 10000), 10050);
+        resampler.set_ratio(0.5);
+        assert_eq!(frames_out(&mut resampler, 10000), 5000);
+    }
+
+    #[test]
+    fn interpolates_between_frames() {
+        let mut resampler = Resampler::new(1, 2);
+        let mut output = vec![];
+        resampler.process(&[1.0, -1.0, 3.0, -3.0], &mut output);
+        // starting from silence, each output frame lands on an input frame or halfway between two
+        assert_eq!(output, [0.0, 0.0, 0.5, -0.5, 1.0, -1.0, 2.0, -2.0]);
+    }
+}
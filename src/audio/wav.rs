@@ -0,0 +1,50 @@
+// Headless WAV dump, so the audio output can be checked without a sound card (e.g. in CI).
+use crate::emulator::M_CYCLES_PER_FRAME;
+use dmg::gb::GameBoy;
+use std::path::Path;
+
+pub fn dump_wav(gameboy: &mut GameBoy, path: &Path, frames: u32) -> Result<(), hound::Error> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: gameboy.apu.sample_rate(),
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for _ in 0..frames {
+        for _ in 0..M_CYCLES_PER_FRAME {
+            gameboy.tick();
+        }
+        for sample in gameboy.drain_audio_samples() {
+            writer.write_sample((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16)?;
+        }
+    }
+    writer.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_a_16_bit_stereo_wav() {
+        let path = std::env::temp_dir().join(format!("scgb-wav-test-{}.wav", std::process::id()));
+        let mut gameboy = dmg::runner::load_rom(vec![0; 0x8000], None);
+        dump_wav(&mut gameboy, &path, 2).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        let reader = hound::WavReader::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        let spec = reader.spec();
+        assert_eq!(spec.channels, 2);
+        assert_eq!(spec.sample_rate, gameboy.apu.sample_rate());
+        assert_eq!(spec.bits_per_sample, 16);
+        assert_eq!(spec.sample_format, hound::SampleFormat::Int);
+        // the APU makes a sample whenever another 1/48000th of the M-cycles have gone by
+        let frames = 2 * M_CYCLES_PER_FRAME as u64 * spec.sample_rate as u64 / (1 << 20);
+        assert_eq!(reader.duration() as u64, frames);
+        assert_eq!(data.len() as u64, 44 + frames * 4);
+    }
+}
@@ -0,0 +1,56 @@
+// Link cable play: a second GameBoy running in lockstep with the first, connected to it through
+// a virtual link cable. Player 2 has its own ROM, save file and keys.
+use crate::app;
+use crate::emulator;
+use crate::widgets::framebuf::FrameBufWidget;
+use dmg::gb::GameBoy;
+use dmg::serial;
+use egui::Key;
+use std::path::{Path, PathBuf};
+
+// A, B, select, start, right, left, up, down
+pub const KEYMAP_PLAYER_2: [Key; 8] = [
+    Key::Period,
+    Key::Comma,
+    Key::Backspace,
+    Key::Enter,
+    Key::ArrowRight,
+    Key::ArrowLeft,
+    Key::ArrowUp,
+    Key::ArrowDown,
+];
+
+pub struct LinkedPlayer {
+    pub framebuf: FrameBufWidget,
+    pub gameboy: GameBoy,
+    pub sav_path: Option<PathBuf>,
+}
+
+impl LinkedPlayer {
+    // Loads player 2 and plugs both GameBoys into either end of a link cable.
+    pub fn new(
+        cc: &eframe::CreationContext<'_>,
+        player_1: &mut GameBoy,
+        player_1_rom: &Path,
+        rom_path: &Path,
+    ) -> Self {
+        let mut gameboy = emulator::load_gameboy(rom_path);
+        gameboy.renderer_kind = player_1.renderer_kind;
+        let (port_1, port_2) = serial::link_cable();
+        player_1.connect_serial(Box::new(port_1));
+        gameboy.connect_serial(Box::new(port_2));
+
+        // Both players can run the same ROM, but they mustn't share a save file.
+        let mut path = dmg::save::sav_path(rom_path);
+        if rom_path == player_1_rom {
+            path = rom_path.with_extension("p2.sav");
+        }
+        let sav_path = app::load_battery_save(&mut gameboy, path);
+
+        Self {
+            framebuf: FrameBufWidget::new(cc),
+            gameboy,
+            sav_path,
+        }
+    }
+}
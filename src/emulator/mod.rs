@@ -1,31 +1,191 @@
+pub mod link;
+mod movie;
+pub mod rewind;
+mod state_slots;
+
 use egui::Key;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::TryRecvError;
+
+use crate::audio::wav;
+use crate::{Options, ScgbGui};
+use dmg::gb::GameBoy;
+use dmg::movie::MoviePlayer;
+use dmg::runner;
+
+pub const M_CYCLES_PER_FRAME: u32 = 17556;
+const M_CYCLES_PER_SECOND: f64 = 1048576.0;
+// Don't try to catch up more than this many frames in one repaint (e.g. after the window was
+// dragged or the machine was suspended).
+const MAX_FRAMES_PER_UPDATE: u32 = 4;
 
-use crate::ScgbGui;
+// A, B, select, start, right, left, up, down
+const KEYMAP: [Key; 8] = [
+    Key::K,
+    Key::J,
+    Key::Num1,
+    Key::Space,
+    Key::D,
+    Key::A,
+    Key::W,
+    Key::S,
+];
+
+// Without dmg_boot.bin in the working directory the game starts straight at its entry point.
+pub fn load_gameboy(rom_path: &Path) -> GameBoy {
+    let boot_rom = fs::read("dmg_boot.bin").ok();
+    let rom: Vec<u8> = fs::read(rom_path).expect("couldnt read file");
+    runner::load_rom(rom, boot_rom.as_deref())
+}
+
+// Runs without a window, for the command line modes that don't need one.
+pub fn run_headless(options: &Options) -> Result<(), String> {
+    let mut gameboy = load_gameboy(&options.rom_path);
+    gameboy.renderer_kind = options.renderer;
+    if let Some(message) = options.serial.waiting_message() {
+        eprintln!("{message}");
+    }
+    let device = options
+        .serial
+        .device(&options.rom_path)
+        .map_err(|e| format!("Couldn't connect the link cable: {e}"))?;
+    gameboy.connect_serial(device);
+    if let Some(path) = &options.dump_wav {
+        wav::dump_wav(&mut gameboy, path, options.frames)
+            .map_err(|e| format!("Couldn't write {}: {e}", path.display()))?;
+    }
+    if let Some(status) = gameboy.take_serial_status() {
+        eprintln!("{status}");
+    }
+    Ok(())
+}
 
 impl ScgbGui {
     pub fn draw(&mut self, ctx: &egui::Context) {
-        // TODO put the keymap somewhere reasonable
-        let keymap: [Key; 8] = [
-            Key::K,
-            Key::J,
-            Key::Num1,
-            Key::Space,
-            Key::D,
-            Key::A,
-            Key::W,
-            Key::S,
-        ];
-        for j in 0..keymap.len() {
-            if ctx.input(|i| i.key_down(keymap[j])) {
-                self.gameboy.press_key(j as u8);
-                self.gameboy.logger.log_info(&format!("key {} pressed", j));
-            } else if ctx.input(|i| i.key_released(keymap[j])) {
-                self.gameboy.unpress_key(j as u8);
-                self.gameboy.logger.log_info(&format!("key {} released", j));
+        // the host doesn't start until the other Game Boy has connected
+        if !self.link_connected() {
+            return;
+        }
+        // a movie playing back presses the keys itself
+        if self.playback.is_none() {
+            handle_keys(ctx, &mut self.gameboy, 0, &KEYMAP);
+            match &mut self.link {
+                Some(link) => handle_keys(ctx, &mut link.gameboy, 0, &link::KEYMAP_PLAYER_2),
+                // without a link cable player 2's keys are the second SGB controller
+                None => handle_keys(ctx, &mut self.gameboy, 1, &link::KEYMAP_PLAYER_2),
+            }
+        }
+        self.handle_state_slot_keys(ctx);
+
+        if !self.handle_rewind_key(ctx) {
+            self.run_emulation(ctx);
+            self.record_keys();
+        }
+        if let Some(status) = self.gameboy.take_serial_status() {
+            self.status = status;
+        }
+    }
+
+    fn link_connected(&mut self) -> bool {
+        let Some(receiver) = &self.waiting_for_link else { return true };
+        match receiver.try_recv() {
+            Err(TryRecvError::Empty) => return false,
+            Ok(Ok(link)) => {
+                // waiting on the other end would freeze the window
+                self.gameboy.connect_serial(Box::new(link.non_blocking()));
+                self.status = "Link cable connected".to_string();
             }
+            Ok(Err(e)) => self.status = format!("Couldn't connect the link cable: {e}"),
+            Err(TryRecvError::Disconnected) => {}
         }
-        for _ in 0..17556 {
-            self.gameboy.tick();
+        self.waiting_for_link = None;
+        true
+    }
+
+    // With audio, run frames until enough sound is queued up, so the sound card's clock sets the
+    // pace. Without audio, run as many M-cycles as real time has passed since the last repaint.
+    fn run_emulation(&mut self, ctx: &egui::Context) {
+        let now = ctx.input(|i| i.time);
+        let elapsed = now - self.last_update_time.unwrap_or(now);
+        self.last_update_time = Some(now);
+
+        match &mut self.audio {
+            Some(audio) => {
+                let mut frames = 0;
+                let mut waiting_on_link = false;
+                while audio.queued_frames() < audio.target_queued_frames()
+                    && frames < MAX_FRAMES_PER_UPDATE
+                    && !stopped(&self.gameboy)
+                    && !waiting_on_link
+                {
+                    for _ in 0..M_CYCLES_PER_FRAME {
+                        if stopped(&self.gameboy) {
+                            break;
+                        }
+                        tick(&mut self.gameboy, &mut self.link, &mut self.playback);
+                        // the other end is behind, try again next repaint
+                        if self.gameboy.waiting_on_link() {
+                            waiting_on_link = true;
+                            break;
+                        }
+                    }
+                    audio.push(&self.gameboy.drain_audio_samples());
+                    frames += 1;
+                }
+            }
+            None => {
+                let max_cycles = (MAX_FRAMES_PER_UPDATE * M_CYCLES_PER_FRAME) as f64;
+                self.cycle_debt = (self.cycle_debt + elapsed * M_CYCLES_PER_SECOND).min(max_cycles);
+                while self.cycle_debt >= 1.0 {
+                    if stopped(&self.gameboy) {
+                        self.cycle_debt = 0.0;
+                        break;
+                    }
+                    tick(&mut self.gameboy, &mut self.link, &mut self.playback);
+                    if self.gameboy.waiting_on_link() {
+                        self.cycle_debt = 0.0;
+                        break;
+                    }
+                    self.cycle_debt -= 1.0;
+                }
+                self.gameboy.drain_audio_samples();
+            }
+        }
+        // only player 1 is heard
+        if let Some(link) = &mut self.link {
+            link.gameboy.drain_audio_samples();
         }
     }
 }
+
+fn handle_keys(ctx: &egui::Context, gameboy: &mut GameBoy, player: u8, keymap: &[Key; 8]) {
+    // keys typed into a text field aren't for the game, but still let go of any held down before
+    let typing = ctx.wants_keyboard_input();
+    for j in 0..keymap.len() {
+        if !typing && ctx.input(|i| i.key_down(keymap[j])) {
+            gameboy.press_player_key(player, j as u8);
+            gameboy.logger.log_info(&format!("key {} pressed", j));
+        } else if ctx.input(|i| i.key_released(keymap[j])) {
+            gameboy.unpress_player_key(player, j as u8);
+            gameboy.logger.log_info(&format!("key {} released", j));
+        }
+    }
+}
+
+// Stopped in the debugger. Player 2 waits too, so the link cable stays in sync.
+fn stopped(gameboy: &GameBoy) -> bool {
+    gameboy.debugger.stop_reason().is_some()
+}
+
+// Linked GameBoys take turns one M-cycle at a time, so neither gets ahead of the other on the cable.
+// A movie's keys are pressed on the M-cycle they were recorded on.
+fn tick(gameboy: &mut GameBoy, link: &mut Option<link::LinkedPlayer>, playback: &mut Option<MoviePlayer>) {
+    if let Some(player) = playback {
+        player.apply(gameboy);
+    }
+    gameboy.tick();
+    if let Some(link) = link {
+        link.gameboy.tick();
+    }
+}
@@ -0,0 +1,119 @@
+// Input movies, stored next to the ROM as `<rom>.movie`. Recording logs the keys pressed from the
+// current state on, playing one back loads that state and presses the same keys at the same clocks.
+use crate::ScgbGui;
+use dmg::movie::Movie;
+use std::fs;
+use std::path::PathBuf;
+
+// The movie only has player 1's GameBoy in it, player 2 would go differently on playback.
+const LINKED_STATUS: &str = "Movies aren't available while two Game Boys are linked";
+
+impl ScgbGui {
+    fn movie_path(&self) -> PathBuf {
+        self.rom_path.with_extension("movie")
+    }
+
+    pub fn start_recording(&mut self) {
+        if self.link.is_some() {
+            self.status = LINKED_STATUS.to_string();
+            return;
+        }
+        self.playback = None;
+        self.movie = Some(Movie::record(&mut self.gameboy));
+        self.status = "Recording a movie".to_string();
+    }
+
+    pub fn stop_recording(&mut self) {
+        let Some(mut movie) = self.movie.take() else { return };
+        let keys = self.gameboy.take_key_events();
+        movie.extend(&self.gameboy, &keys);
+        let path = self.movie_path();
+        self.status = match fs::write(&path, movie.to_bytes()) {
+            Ok(()) => format!("Saved a {} frame movie to {}", movie.frames(), path.display()),
+            Err(e) => format!("Couldn't write {}: {e}", path.display()),
+        };
+    }
+
+    pub fn play_movie(&mut self) {
+        if self.link.is_some() {
+            self.status = LINKED_STATUS.to_string();
+            return;
+        }
+        let path = self.movie_path();
+        let movie = match fs::read(&path) {
+            Ok(data) => Movie::from_bytes(&data),
+            Err(e) => {
+                self.status = format!("Couldn't read {}: {e}", path.display());
+                return;
+            }
+        };
+        self.status = match movie.and_then(|movie| Ok((movie.frames(), self.gameboy.play_movie(&movie)?))) {
+            Ok((frames, player)) => {
+                self.movie = None;
+                self.playback = Some(player);
+                self.rewind.clear();
+                format!("Playing a {frames} frame movie")
+            }
+            Err(e) => format!("Couldn't play {}: {e}", path.display()),
+        };
+    }
+
+    pub fn stop_playback(&mut self) {
+        if self.playback.take().is_some() {
+            // let go of whatever the movie was holding down, the keyboard takes over from here
+            for player in 0..2 {
+                for key in 0..8 {
+                    self.gameboy.unpress_player_key(player, key);
+                }
+            }
+        }
+    }
+
+    // After running: adds the keys pressed to the recording and the rewind history, and ends playback
+    // once the movie is over.
+    pub(crate) fn record_keys(&mut self) {
+        let keys = self.gameboy.take_key_events();
+        if let Some(movie) = &mut self.movie {
+            movie.extend(&self.gameboy, &keys);
+        }
+        if self.link.is_none() {
+            self.rewind.record(&self.gameboy, &keys);
+        }
+        if self
+            .playback
+            .as_ref()
+            .is_some_and(|player| player.finished(&self.gameboy))
+        {
+            self.stop_playback();
+            self.status = "Movie finished".to_string();
+        }
+    }
+
+    pub(crate) fn movie_menu(&mut self, ui: &mut egui::Ui) {
+        if self.movie.is_some() {
+            if ui.button("Stop recording").clicked() {
+                self.stop_recording();
+                ui.close_menu();
+            }
+        } else if ui
+            .add_enabled(self.playback.is_none(), egui::Button::new("Record"))
+            .clicked()
+        {
+            self.start_recording();
+            ui.close_menu();
+        }
+        if self.playback.is_some() {
+            if ui.button("Stop playing").clicked() {
+                self.stop_playback();
+                self.status = "Stopped the movie".to_string();
+                ui.close_menu();
+            }
+        } else {
+            let playable = self.movie.is_none() && self.movie_path().exists();
+            if ui.add_enabled(playable, egui::Button::new("Play")).clicked() {
+                self.play_movie();
+                ui.close_menu();
+            }
+        }
+    }
+}
@@ -0,0 +1,35 @@
+// Holding R rewinds player 1 a frame per repaint, up to a minute back.
+use crate::ScgbGui;
+use egui::Key;
+
+pub const REWIND_SECONDS: u32 = 60;
+const REWIND_KEY: Key = Key::R;
+
+impl ScgbGui {
+    // Returns true if it rewound instead of letting the emulation run this repaint.
+    pub(crate) fn handle_rewind_key(&mut self, ctx: &egui::Context) -> bool {
+        // like save states, rewinding one GameBoy would leave the link cable out of sync
+        let unavailable = self.link.is_some() || self.playback.is_some() || super::stopped(&self.gameboy);
+        // an R typed into a text field, the debugger's say, isn't a rewind
+        if !ctx.input(|i| i.key_down(REWIND_KEY)) || unavailable || ctx.wants_keyboard_input() {
+            return false;
+        }
+        self.status = match self.rewind.rewind_frame(&mut self.gameboy) {
+            true => {
+                // a movie being recorded goes back too, and carries on from here
+                if let Some(movie) = &mut self.movie {
+                    movie.rewound(&mut self.gameboy);
+                }
+                format!(
+                    "Rewinding, {:.1}s left",
+                    self.rewind.frames(&self.gameboy) as f64 / 60.0
+                )
+            }
+            false => "Can't rewind any further".to_string(),
+        };
+        self.gameboy.drain_audio_samples();
+        // don't try to catch up on the time spent rewinding
+        self.last_update_time = None;
+        true
+    }
+}
@@ -0,0 +1,102 @@
+// Numbered save state slots, stored next to the ROM as `<rom>.ss1` to `<rom>.ss9`.
+// F1-F9 loads a slot, Shift+F1-F9 saves to it.
+use crate::ScgbGui;
+use egui::Key;
+use std::fs;
+use std::path::PathBuf;
+
+pub const NUM_STATE_SLOTS: u8 = 9;
+
+const SLOT_KEYS: [Key; NUM_STATE_SLOTS as usize] = [
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+];
+
+// A state only covers one GameBoy, loading it would leave the link cable out of sync.
+const LINKED_STATUS: &str = "Save states aren't available while two Game Boys are linked";
+
+impl ScgbGui {
+    fn state_slot_path(&self, slot: u8) -> PathBuf {
+        self.rom_path.with_extension(format!("ss{slot}"))
+    }
+
+    pub fn save_state_slot(&mut self, slot: u8) {
+        if self.link.is_some() {
+            self.status = LINKED_STATUS.to_string();
+            return;
+        }
+        let path = self.state_slot_path(slot);
+        self.status = match fs::write(&path, self.gameboy.save_state()) {
+            Ok(()) => format!("Saved state to slot {slot}"),
+            Err(e) => format!("Couldn't write {}: {e}", path.display()),
+        };
+    }
+
+    pub fn load_state_slot(&mut self, slot: u8) {
+        if self.link.is_some() {
+            self.status = LINKED_STATUS.to_string();
+            return;
+        }
+        // the movie would jump to wherever the state is
+        if self.movie.is_some() || self.playback.is_some() {
+            self.status = "Stop the movie before loading a state".to_string();
+            return;
+        }
+        let path = self.state_slot_path(slot);
+        self.status = match fs::read(&path) {
+            Ok(data) => match self.gameboy.load_state(&data) {
+                Ok(()) => {
+                    self.rewind.clear();
+                    format!("Loaded state from slot {slot}")
+                }
+                Err(e) => format!("Couldn't load slot {slot}: {e}"),
+            },
+            Err(e) => format!("Couldn't read {}: {e}", path.display()),
+        };
+    }
+
+    pub(crate) fn handle_state_slot_keys(&mut self, ctx: &egui::Context) {
+        if ctx.wants_keyboard_input() {
+            return;
+        }
+        for (i, key) in SLOT_KEYS.iter().enumerate() {
+            let slot = i as u8 + 1;
+            let (pressed, shift) = ctx.input(|input| (input.key_pressed(*key), input.modifiers.shift));
+            if pressed && shift {
+                self.save_state_slot(slot);
+            } else if pressed {
+                self.load_state_slot(slot);
+            }
+        }
+    }
+
+    pub(crate) fn state_slot_menu(&mut self, ui: &mut egui::Ui) {
+        ui.menu_button("Save", |ui| {
+            for slot in 1..=NUM_STATE_SLOTS {
+                if ui.button(format!("Slot {slot}  (Shift+F{slot})")).clicked() {
+                    self.save_state_slot(slot);
+                    ui.close_menu();
+                }
+            }
+        });
+        ui.menu_button("Load", |ui| {
+            for slot in 1..=NUM_STATE_SLOTS {
+                let exists = self.state_slot_path(slot).exists();
+                if ui
+                    .add_enabled(exists, egui::Button::new(format!("Slot {slot}  (F{slot})")))
+                    .clicked()
+                {
+                    self.load_state_slot(slot);
+                    ui.close_menu();
+                }
+            }
+        });
+    }
+}
@@ -1,7 +1,11 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
 mod app;
+mod audio;
 mod emulator;
+mod options;
 mod util;
 mod widgets;
 pub use app::ScgbGui;
+pub use emulator::run_headless;
+pub use options::Options;
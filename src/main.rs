@@ -6,6 +6,18 @@
 fn main() -> eframe::Result {
     env_logger::init(); // Log to stderr (if you run with `RUST_LOG=debug`).
 
+    let options = supercoolgb::Options::from_args().unwrap_or_else(|e| {
+        eprintln!("{e}");
+        std::process::exit(2)
+    });
+    if options.dump_wav.is_some() {
+        if let Err(e) = supercoolgb::run_headless(&options) {
+            eprintln!("{e}");
+            std::process::exit(1)
+        }
+        return Ok(());
+    }
+
     let native_options = eframe::NativeOptions {
 
         viewport: egui::ViewportBuilder::default()
@@ -21,7 +33,7 @@ fn main() -> eframe::Result {
     eframe::run_native(
         "eframe template",
         native_options,
-        Box::new(|cc| Ok(Box::new(supercoolgb::ScgbGui::new(cc)))),
+        Box::new(|cc| Ok(Box::new(supercoolgb::ScgbGui::new(cc, options)))),
     )
 }
 
@@ -51,7 +63,10 @@ fn main() {
             .start(
                 canvas,
                 web_options,
-                Box::new(|cc| Ok(Box::new(supercoolgb::ScgbGui::new(cc)))),
+                Box::new(|cc| {
+                    let options = supercoolgb::Options::from_args().expect("Provide the ROM file to run");
+                    Ok(Box::new(supercoolgb::ScgbGui::new(cc, options)))
+                }),
             )
             .await;
 
@@ -0,0 +1,180 @@
+// Command line options.
+//   supercoolgb <rom> [--serial <none|stdout|loopback|printer> | --link <rom>
+//                      | --link-host <[address:]port> | --link-connect <host:port>] [--ppu <scanline|fifo>]
+//                      [--dump-wav <out.wav> [--frames <n>]]
+use dmg::fifo::RendererKind;
+use dmg::serial::printer::Printer;
+use dmg::serial::tcp::TcpLink;
+use dmg::serial::{self, SerialDevice};
+use std::env;
+use std::io;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+const USAGE: &str = "usage: supercoolgb <rom> [--serial <none|stdout|loopback|printer> | --link <rom> | --link-host <[address:]port> | --link-connect <host:port>] [--ppu <scanline|fifo>] [--dump-wav <out.wav> [--frames <n>]]";
+
+// What to plug into the link port.
+#[derive(Clone, Default)]
+pub enum SerialOption {
+    #[default]
+    None,
+    Stdout,
+    Loopback,
+    // Game Boy Printer, saving pages in a folder next to the ROM
+    Printer,
+    // link cable to another emulator over TCP, either waiting for it to connect on an address or
+    // connecting to it
+    Host(String),
+    Connect(String),
+}
+
+// Only this machine can connect unless --link-host is given an address to listen on.
+const DEFAULT_HOST_ADDRESS: &str = "127.0.0.1";
+
+impl SerialOption {
+    pub fn device(&self, rom_path: &Path) -> io::Result<Box<dyn SerialDevice>> {
+        Ok(match self {
+            SerialOption::None => Box::new(serial::Disconnected),
+            SerialOption::Stdout => Box::new(serial::StdoutSink),
+            SerialOption::Loopback => Box::new(serial::Loopback),
+            SerialOption::Printer => Box::new(Printer::new(&prints_dir(rom_path))),
+            // blocks until the other end connects, see link_in_background for the window
+            SerialOption::Host(address) => Box::new(TcpLink::listen(&TcpListener::bind(address.as_str())?)?),
+            SerialOption::Connect(address) => Box::new(TcpLink::connect(address.as_str())?),
+        })
+    }
+
+    // What to tell the user while the link cable is connecting.
+    pub fn waiting_message(&self) -> Option<String> {
+        match self {
+            SerialOption::Host(address) => Some(format!("Waiting for the other Game Boy to connect on {address}...")),
+            SerialOption::Connect(address) => Some(format!("Connecting to the other Game Boy at {address}...")),
+            _ => None,
+        }
+    }
+}
+
+// Connects the link cable of --link-host or --link-connect on another thread, so the window keeps
+// drawing while it waits. None for the other options.
+pub fn link_in_background(serial: &SerialOption) -> Option<io::Result<Receiver<io::Result<TcpLink>>>> {
+    let (sender, receiver) = mpsc::channel();
+    match serial {
+        SerialOption::Host(address) => {
+            let listener = match TcpListener::bind(address.as_str()) {
+                Ok(listener) => listener,
+                Err(e) => return Some(Err(e)),
+            };
+            thread::spawn(move || sender.send(TcpLink::listen(&listener)));
+        }
+        SerialOption::Connect(address) => {
+            let address = address.clone();
+            thread::spawn(move || sender.send(TcpLink::connect(address.as_str())));
+        }
+        _ => return None,
+    }
+    Some(Ok(receiver))
+}
+
+// --link-host takes a port, listened on at DEFAULT_HOST_ADDRESS, or an address and port.
+fn host_address(arg: &str) -> Result<String, String> {
+    let (address, port) = arg.rsplit_once(':').unwrap_or((DEFAULT_HOST_ADDRESS, arg));
+    port.parse::<u16>().map_err(|_| format!("--link-host: not a port: {port}"))?;
+    Ok(format!("{address}:{port}"))
+}
+
+// game.gb prints to game-prints/
+fn prints_dir(rom_path: &Path) -> PathBuf {
+    let stem = rom_path.file_stem().unwrap_or_default().to_string_lossy();
+    rom_path.with_file_name(format!("{stem}-prints"))
+}
+
+pub struct Options {
+    pub rom_path: PathBuf,
+    // Run without a window and write the audio of the first `frames` frames to this file.
+    pub dump_wav: Option<PathBuf>,
+    pub frames: u32,
+    pub serial: SerialOption,
+    // ROM for a second GameBoy connected to the first by a link cable
+    pub link: Option<PathBuf>,
+    // the scanline renderer is faster, the FIFO one gets mid-line effects right
+    pub renderer: RendererKind,
+}
+
+impl Options {
+    pub fn from_args() -> Result<Self, String> {
+        let mut rom_path = None;
+        let mut dump_wav = None;
+        let mut frames = 600;
+        let mut serial = None;
+        let mut link = None;
+        let mut renderer = RendererKind::default();
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--dump-wav" => {
+                    dump_wav = Some(PathBuf::from(args.next().ok_or(USAGE)?));
+                }
+                "--frames" => {
+                    let n = args.next().ok_or(USAGE)?;
+                    frames = n.parse().map_err(|_| format!("--frames: not a number: {n}"))?;
+                }
+                "--serial" => {
+                    serial = Some(match args.next().ok_or(USAGE)?.as_str() {
+                        "none" => SerialOption::None,
+                        "stdout" => SerialOption::Stdout,
+                        "loopback" => SerialOption::Loopback,
+                        "printer" => SerialOption::Printer,
+                        other => return Err(format!("--serial: unknown device {other}\n{USAGE}")),
+                    });
+                }
+                "--link-host" => {
+                    serial = Some(SerialOption::Host(host_address(&args.next().ok_or(USAGE)?)?));
+                }
+                "--link-connect" => {
+                    serial = Some(SerialOption::Connect(args.next().ok_or(USAGE)?));
+                }
+                "--link" => {
+                    link = Some(PathBuf::from(args.next().ok_or(USAGE)?));
+                }
+                "--ppu" => {
+                    renderer = match args.next().ok_or(USAGE)?.as_str() {
+                        "scanline" => RendererKind::Scanline,
+                        "fifo" => RendererKind::Fifo,
+                        other => return Err(format!("--ppu: unknown renderer {other}\n{USAGE}")),
+                    };
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown option {arg}\n{USAGE}")),
+                _ if rom_path.is_none() => rom_path = Some(PathBuf::from(arg)),
+                _ => return Err(USAGE.to_string()),
+            }
+        }
+
+        if serial.is_some() && link.is_some() {
+            return Err(format!("only one link cable or serial device can be used\n{USAGE}"));
+        }
+
+        Ok(Self {
+            rom_path: rom_path.ok_or("Provide the ROM file to run\n".to_string() + USAGE)?,
+            dump_wav,
+            frames,
+            serial: serial.unwrap_or_default(),
+            link,
+            renderer,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn link_host_only_listens_locally_by_default() {
+        assert_eq!(host_address("5000").unwrap(), "127.0.0.1:5000");
+        assert_eq!(host_address("0.0.0.0:5000").unwrap(), "0.0.0.0:5000");
+        assert!(host_address("localhost").is_err());
+    }
+}
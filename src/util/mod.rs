@@ -1,9 +1,8 @@
-pub fn dmg_colour(pixel: u8) -> egui::Color32 {
-    match pixel {
-        0 => egui::Color32::from_rgb(155, 188, 15),
-        1 => egui::Color32::from_rgb(139, 172, 15),
-        2 => egui::Color32::from_rgb(48, 98, 48),
-        3 => egui::Color32::from_rgb(15, 56, 15),
-        _ => egui::Color32::from_rgb(15, 56, 15),
-    }
+// The emulator draws in RGB555 (5 bits per channel, red in the low bits).
+pub fn rgb555_colour(pixel: u16) -> egui::Color32 {
+    let expand = |channel: u16| {
+        let channel = (channel & 0x1F) as u8;
+        (channel << 3) | (channel >> 2)
+    };
+    egui::Color32::from_rgb(expand(pixel), expand(pixel >> 5), expand(pixel >> 10))
 }
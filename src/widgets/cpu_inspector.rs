@@ -0,0 +1,242 @@
+// Player 1's CPU registers, interrupts, timer and LCD control, live while running and editable while
+// the debugger has it stopped.
+use dmg::gb::{GameBoy, InterruptType, IsrState, State};
+use eframe::egui;
+
+const INTERRUPTS: [(InterruptType, &str); 5] = [
+    (InterruptType::VBlank, "VBlank"),
+    (InterruptType::LCD, "LCD"),
+    (InterruptType::Timer, "Timer"),
+    (InterruptType::Serial, "Serial"),
+    (InterruptType::Joypad, "Joypad"),
+];
+
+const LCDC_BITS: [&str; 8] = [
+    "BG/window enable (CGB: priority)",
+    "OBJ enable",
+    "OBJ 8x16",
+    "BG map 9C00",
+    "Tile data 8000",
+    "Window enable",
+    "Window map 9C00",
+    "LCD enable",
+];
+
+// bits 0-2 are read only
+const STAT_BITS: [(u8, &str); 4] = [
+    (3, "Mode 0 (HBlank) interrupt"),
+    (4, "Mode 1 (VBlank) interrupt"),
+    (5, "Mode 2 (OAM scan) interrupt"),
+    (6, "LY == LYC interrupt"),
+];
+
+const PPU_MODES: [&str; 4] = ["HBlank", "VBlank", "OAM scan", "Drawing"];
+
+#[derive(Default)]
+pub struct CpuInspector {
+    pub open: bool,
+}
+
+fn hex_u8(ui: &mut egui::Ui, label: &str, value: &mut u8) -> egui::Response {
+    ui.label(label);
+    ui.add(egui::DragValue::new(value).hexadecimal(2, false, true))
+}
+
+fn hex_u16(ui: &mut egui::Ui, label: &str, value: &mut u16) {
+    ui.label(label);
+    ui.add(egui::DragValue::new(value).hexadecimal(4, false, true));
+}
+
+fn bit(ui: &mut egui::Ui, value: &mut u8, bit: u8, label: &str) {
+    let mut set = *value & (1 << bit) != 0;
+    if ui.checkbox(&mut set, label).changed() {
+        *value ^= 1 << bit;
+    }
+}
+
+impl CpuInspector {
+    pub fn show(&mut self, ctx: &egui::Context, gameboy: &mut GameBoy) {
+        let mut open = self.open;
+        egui::Window::new("CPU").open(&mut open).show(ctx, |ui| {
+            let stopped = gameboy.debugger.stop_reason().is_some();
+            ui.horizontal(|ui| {
+                if stopped {
+                    if ui.button("Continue").clicked() {
+                        gameboy.resume();
+                    }
+                } else {
+                    if ui.button("Pause").clicked() {
+                        gameboy.pause();
+                    }
+                    ui.label("Pause to edit");
+                }
+            });
+            ui.add_enabled_ui(stopped, |ui| {
+                ui.columns(2, |columns| {
+                    registers(&mut columns[0], gameboy);
+                    columns[0].separator();
+                    cpu_state(&mut columns[0], gameboy);
+                    columns[0].separator();
+                    timer(&mut columns[0], gameboy);
+                    interrupts(&mut columns[1], gameboy);
+                    columns[1].separator();
+                    lcd(&mut columns[1], gameboy);
+                });
+            });
+        });
+        self.open = open;
+    }
+}
+
+fn registers(ui: &mut egui::Ui, gameboy: &mut GameBoy) {
+    let r = &mut gameboy.r;
+    egui::Grid::new("cpu_registers").show(ui, |ui| {
+        hex_u8(ui, "A", &mut r.a);
+        // the low nibble of F is always 0
+        if hex_u8(ui, "F", &mut r.f).changed() {
+            r.f &= 0xF0;
+        }
+        ui.end_row();
+        hex_u8(ui, "B", &mut r.b);
+        hex_u8(ui, "C", &mut r.c);
+        ui.end_row();
+        hex_u8(ui, "D", &mut r.d);
+        hex_u8(ui, "E", &mut r.e);
+        ui.end_row();
+        hex_u8(ui, "H", &mut r.h);
+        hex_u8(ui, "L", &mut r.l);
+        ui.end_row();
+        hex_u16(ui, "SP", &mut r.sp);
+        hex_u16(ui, "PC", &mut r.pc);
+        ui.end_row();
+    });
+    ui.horizontal(|ui| {
+        for (flag, name) in [(7, "Z"), (6, "N"), (5, "H"), (4, "C")] {
+            bit(ui, &mut r.f, flag, name);
+        }
+    });
+}
+
+fn cpu_state(ui: &mut egui::Ui, gameboy: &mut GameBoy) {
+    egui::Grid::new("cpu_state").show(ui, |ui| {
+        ui.label("State");
+        egui::ComboBox::from_id_salt("cpu_state_state")
+            .selected_text(format!("{:?}", gameboy.state))
+            .show_ui(ui, |ui| {
+                for state in [State::Execute, State::Halted, State::Stopped, State::InterruptHandler] {
+                    let name = format!("{state:?}");
+                    ui.selectable_value(&mut gameboy.state, state, name);
+                }
+            });
+        ui.end_row();
+
+        ui.label("ISR");
+        egui::ComboBox::from_id_salt("cpu_state_isr")
+            .selected_text(format!("{:?}", gameboy.isr_state))
+            .show_ui(ui, |ui| {
+                let states = [
+                    IsrState::Wait1,
+                    IsrState::Wait2,
+                    IsrState::PCPush1,
+                    IsrState::PCPush2,
+                    IsrState::Jump,
+                ];
+                for state in states {
+                    let name = format!("{state:?}");
+                    ui.selectable_value(&mut gameboy.isr_state, state, name);
+                }
+            });
+        ui.end_row();
+
+        ui.label("IME");
+        ui.checkbox(&mut gameboy.ime, "");
+        ui.end_row();
+
+        // EI enables interrupts after the next instruction, this counts down to it
+        ui.label("EI pending");
+        ui.horizontal(|ui| {
+            let mut pending = gameboy.ime_dispatch.is_some();
+            if ui.checkbox(&mut pending, "").changed() {
+                gameboy.ime_dispatch = pending.then_some(2);
+            }
+            if let Some(countdown) = &mut gameboy.ime_dispatch {
+                ui.add(egui::DragValue::new(countdown).range(0..=2));
+            }
+        });
+        ui.end_row();
+    });
+}
+
+fn timer(ui: &mut egui::Ui, gameboy: &mut GameBoy) {
+    let r = &mut gameboy.r;
+    egui::Grid::new("cpu_timer").show(ui, |ui| {
+        // DIV is the top byte of the 16 bit divider
+        let mut div = (r.div_16 >> 8) as u8;
+        if hex_u8(ui, "DIV", &mut div).changed() {
+            r.div_16 = (div as u16) << 8 | (r.div_16 & 0xFF);
+        }
+        hex_u8(ui, "TIMA", &mut r.tima);
+        ui.end_row();
+        hex_u8(ui, "TMA", &mut r.tma);
+        hex_u8(ui, "TAC", &mut r.tac);
+        ui.end_row();
+    });
+    let clock = ["4096 Hz", "262144 Hz", "65536 Hz", "16384 Hz"][r.tac as usize & 3];
+    ui.horizontal(|ui| {
+        bit(ui, &mut r.tac, 2, "Timer enable");
+        ui.label(clock);
+    });
+}
+
+fn interrupts(ui: &mut egui::Ui, gameboy: &mut GameBoy) {
+    let r = &mut gameboy.r;
+    egui::Grid::new("cpu_interrupts").show(ui, |ui| {
+        hex_u8(ui, "IE", &mut r.ie);
+        hex_u8(ui, "IF", &mut r.r#if);
+        ui.end_row();
+    });
+    egui::Grid::new("cpu_interrupt_bits").show(ui, |ui| {
+        for heading in ["", "IE", "IF", ""] {
+            ui.strong(heading);
+        }
+        ui.end_row();
+        for (interrupt, name) in INTERRUPTS {
+            let index = interrupt as u8;
+            ui.label(name);
+            bit(ui, &mut r.ie, index, "");
+            bit(ui, &mut r.r#if, index, "");
+            // requested and enabled, so it's dispatched as soon as IME allows
+            if r.ie & r.r#if & (1 << index) != 0 {
+                ui.label("pending");
+            } else {
+                ui.label("");
+            }
+            ui.end_row();
+        }
+    });
+}
+
+fn lcd(ui: &mut egui::Ui, gameboy: &mut GameBoy) {
+    let r = &mut gameboy.r;
+    egui::Grid::new("cpu_lcd").show(ui, |ui| {
+        hex_u8(ui, "LCDC", &mut r.lcdc);
+        hex_u8(ui, "STAT", &mut r.stat);
+        ui.end_row();
+        hex_u8(ui, "LY", &mut r.ly);
+        hex_u8(ui, "LYC", &mut r.lyc);
+        ui.end_row();
+    });
+    for (index, name) in LCDC_BITS.iter().enumerate().rev() {
+        bit(ui, &mut r.lcdc, index as u8, name);
+    }
+    ui.separator();
+    ui.label(format!(
+        "Mode {} ({}), LY == LYC: {}",
+        r.stat & 3,
+        PPU_MODES[r.stat as usize & 3],
+        r.stat & 4 != 0
+    ));
+    for (index, name) in STAT_BITS {
+        bit(ui, &mut r.stat, index, name);
+    }
+}
@@ -0,0 +1,184 @@
+// Pausing, stepping, and breakpoints and watchpoints for player 1's GameBoy.
+use dmg::debugger::{self, Watchpoint};
+use dmg::gb::GameBoy;
+use eframe::egui;
+
+// instructions shown from PC onwards while stopped
+const DISASSEMBLY_LINES: usize = 8;
+
+pub struct DebuggerWindow {
+    pub open: bool,
+    breakpoint_input: String,
+    watchpoint_input: String,
+    scanline: u8,
+    error: String,
+}
+
+impl Default for DebuggerWindow {
+    fn default() -> Self {
+        Self {
+            open: false,
+            breakpoint_input: String::new(),
+            watchpoint_input: String::new(),
+            scanline: 144,
+            error: String::new(),
+        }
+    }
+}
+
+fn watchpoint_label(watchpoint: &Watchpoint) -> String {
+    let mut accesses = String::new();
+    for (watched, letter) in [(watchpoint.read, 'r'), (watchpoint.write, 'w'), (watchpoint.execute, 'x')] {
+        if watched {
+            accesses.push(letter);
+        }
+    }
+    match watchpoint.start == watchpoint.end {
+        true => format!("{:04X} {accesses}", watchpoint.start),
+        false => format!("{:04X}-{:04X} {accesses}", watchpoint.start, watchpoint.end),
+    }
+}
+
+impl DebuggerWindow {
+    pub fn show(&mut self, ctx: &egui::Context, gameboy: &mut GameBoy) {
+        let mut open = self.open;
+        egui::Window::new("Debugger").open(&mut open).show(ctx, |ui| {
+            self.controls(ui, gameboy);
+            if gameboy.debugger.stop_reason().is_some() {
+                ui.separator();
+                disassembly(ui, gameboy);
+            }
+            ui.separator();
+            self.breakpoints(ui, gameboy);
+            ui.separator();
+            self.watchpoints(ui, gameboy);
+            if !self.error.is_empty() {
+                ui.colored_label(ui.visuals().error_fg_color, &self.error);
+            }
+        });
+        self.open = open;
+    }
+
+    fn controls(&mut self, ui: &mut egui::Ui, gameboy: &mut GameBoy) {
+        let stopped = gameboy.debugger.stop_reason().is_some();
+        match gameboy.debugger.stop_reason() {
+            Some(reason) => ui.label(format!(
+                "Stopped: {reason}\nPC: {:02X}:{:04X}",
+                gameboy.bank_at(gameboy.r.pc),
+                gameboy.r.pc
+            )),
+            None => ui.label("Running"),
+        };
+        ui.horizontal(|ui| {
+            if stopped {
+                if ui.button("Continue").clicked() {
+                    gameboy.resume();
+                }
+            } else if ui.button("Pause").clicked() {
+                gameboy.pause();
+            }
+            ui.add_enabled_ui(stopped, |ui| {
+                if ui.button("Step").clicked() {
+                    gameboy.step_instruction();
+                }
+                if ui.button("Step over").clicked() {
+                    gameboy.step_over();
+                }
+                if ui.button("Step out").clicked() {
+                    gameboy.step_out();
+                }
+            });
+        });
+        ui.add_enabled_ui(stopped, |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("Run to scanline").clicked() {
+                    gameboy.run_to_scanline(self.scanline);
+                }
+                ui.add(egui::DragValue::new(&mut self.scanline).range(0..=153));
+                if ui.button("Next frame").clicked() {
+                    gameboy.run_to_next_frame();
+                }
+            });
+        });
+    }
+
+    fn breakpoints(&mut self, ui: &mut egui::Ui, gameboy: &mut GameBoy) {
+        ui.label("Breakpoints");
+        let mut removed = None;
+        for (i, breakpoint) in gameboy.debugger.breakpoints.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                let label = breakpoint.to_string();
+                ui.checkbox(&mut breakpoint.enabled, label);
+                if ui.small_button("x").clicked() {
+                    removed = Some(i);
+                }
+            });
+        }
+        if let Some(i) = removed {
+            gameboy.debugger.breakpoints.remove(i);
+        }
+        let added = add_row(ui, &mut self.breakpoint_input, "[bank:]addr [if a == 3]");
+        if let Some(text) = added {
+            self.error = match debugger::parse_breakpoint(&text) {
+                Ok(breakpoint) => {
+                    if !gameboy.debugger.breakpoints.contains(&breakpoint) {
+                        gameboy.debugger.breakpoints.push(breakpoint);
+                    }
+                    self.breakpoint_input.clear();
+                    String::new()
+                }
+                Err(e) => e,
+            };
+        }
+    }
+
+    fn watchpoints(&mut self, ui: &mut egui::Ui, gameboy: &mut GameBoy) {
+        ui.label("Watchpoints");
+        let mut removed = None;
+        for (i, watchpoint) in gameboy.debugger.watchpoints.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                let label = watchpoint_label(watchpoint);
+                ui.checkbox(&mut watchpoint.enabled, label);
+                if ui.small_button("x").clicked() {
+                    removed = Some(i);
+                }
+            });
+        }
+        if let Some(i) = removed {
+            gameboy.debugger.watchpoints.remove(i);
+        }
+        let added = add_row(ui, &mut self.watchpoint_input, "start[-end] rwx");
+        if let Some(text) = added {
+            self.error = match debugger::parse_watchpoint(&text) {
+                Ok(watchpoint) => {
+                    gameboy.debugger.watchpoints.push(watchpoint);
+                    self.watchpoint_input.clear();
+                    String::new()
+                }
+                Err(e) => e,
+            };
+        }
+    }
+}
+
+fn disassembly(ui: &mut egui::Ui, gameboy: &mut GameBoy) {
+    let mut address = gameboy.r.pc;
+    for _ in 0..DISASSEMBLY_LINES {
+        let instruction = gameboy.disassemble(address);
+        let marker = if address == gameboy.r.pc { ">" } else { " " };
+        ui.monospace(format!("{marker} {address:04X}  {instruction}"));
+        address = address.wrapping_add(instruction.length as u16);
+    }
+}
+
+// A text box with an Add button, returning the text when it's submitted.
+fn add_row(ui: &mut egui::Ui, input: &mut String, hint: &str) -> Option<String> {
+    ui.horizontal(|ui| {
+        let response = ui.add(egui::TextEdit::singleline(input).hint_text(hint).desired_width(160.0));
+        let entered = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+        let clicked = ui.button("Add").clicked();
+        (entered || clicked).then(|| input.trim().to_string())
+    })
+    .inner
+    .filter(|text| !text.is_empty())
+}
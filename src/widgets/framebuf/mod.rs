@@ -2,6 +2,7 @@
 
 use crate::util;
 use dmg::gb::GameBoy;
+use dmg::sgb;
 use eframe::egui;
 use eframe::egui::Vec2;
 
@@ -37,12 +38,16 @@ impl FrameBufWidget {
     }
 
     pub fn draw(&mut self, ui: &mut egui::Ui, gb: &GameBoy) -> egui::Response {
-        let frame = gb.display();
-        self.display_size = [160, 144];
+        // SGB games that send a border are shown with it
+        let (frame, display_size) = match gb.sgb_frame() {
+            Some(frame) => (frame, [sgb::BORDER_WIDTH as u16, sgb::BORDER_HEIGHT as u16]),
+            None => (gb.display().to_vec(), [160, 144]),
+        };
+        self.display_size = display_size;
         self.texture.set(
             egui::ColorImage {
                 size: self.display_size.map(|i| i.into()),
-                pixels: Vec::from_iter(frame.iter().map(|c| util::dmg_colour(*c))),
+                pixels: Vec::from_iter(frame.iter().map(|c| util::rgb555_colour(*c))),
             },
             egui::TextureOptions::NEAREST,
         );
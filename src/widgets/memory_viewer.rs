@@ -0,0 +1,279 @@
+// Hex view of player 1's memory: the CPU's address space, or a ROM, SRAM or VRAM bank or OAM as
+// stored, without the banking. Bytes that changed since the last repaint are highlighted, and a
+// byte can be edited by clicking it.
+use dmg::gb::GameBoy;
+use dmg::memory::MemoryRegion;
+use eframe::egui;
+
+const BYTES_PER_ROW: usize = 16;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Source {
+    Bus,
+    Region(MemoryRegion),
+}
+
+const SOURCES: [(Source, &str); 5] = [
+    (Source::Bus, "CPU bus"),
+    (Source::Region(MemoryRegion::Rom), "ROM"),
+    (Source::Region(MemoryRegion::Sram), "SRAM"),
+    (Source::Region(MemoryRegion::Vram), "VRAM"),
+    (Source::Region(MemoryRegion::Oam), "OAM"),
+];
+
+pub struct MemoryViewer {
+    pub open: bool,
+    source: Source,
+    bank: usize,
+    // the bytes at the last repaint, to see what changed
+    previous: Vec<u8>,
+    selected: Option<usize>,
+    edit: String,
+    goto_input: String,
+    search_input: String,
+    scroll_to: Option<usize>,
+    message: String,
+}
+
+impl Default for MemoryViewer {
+    fn default() -> Self {
+        Self {
+            open: false,
+            source: Source::Bus,
+            bank: 0,
+            previous: vec![],
+            selected: None,
+            edit: String::new(),
+            goto_input: String::new(),
+            search_input: String::new(),
+            scroll_to: None,
+            message: String::new(),
+        }
+    }
+}
+
+// Hex bytes such as `DE AD BE EF`, or text in quotes such as `"TETRIS"`.
+fn parse_search(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim();
+    if let Some(quoted) = text.strip_prefix('"') {
+        return Ok(quoted.trim_end_matches('"').as_bytes().to_vec());
+    }
+    let digits: String = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || digits.len() % 2 != 0 {
+        return Err(format!("expected hex bytes or \"text\", got {text}"));
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| format!("not hex: {text}")))
+        .collect()
+}
+
+impl MemoryViewer {
+    // CPU address of the first byte shown
+    fn base_address(&self) -> usize {
+        match self.source {
+            Source::Bus => 0,
+            Source::Region(MemoryRegion::Rom) if self.bank == 0 => 0,
+            Source::Region(MemoryRegion::Rom) => 0x4000,
+            Source::Region(MemoryRegion::Sram) => 0xA000,
+            Source::Region(MemoryRegion::Vram) => 0x8000,
+            Source::Region(MemoryRegion::Oam) => 0xFE00,
+        }
+    }
+
+    fn bytes(&self, gameboy: &GameBoy) -> Vec<u8> {
+        match self.source {
+            Source::Bus => (0..=0xFFFF).map(|address| gameboy.peek(address)).collect(),
+            Source::Region(region) => gameboy.region(region, self.bank).to_vec(),
+        }
+    }
+
+    fn poke(&self, gameboy: &mut GameBoy, offset: usize, value: u8) {
+        match self.source {
+            Source::Bus => gameboy.poke(offset as u16, value),
+            Source::Region(region) => {
+                if let Some(byte) = gameboy.region_mut(region, self.bank).get_mut(offset) {
+                    *byte = value;
+                }
+            }
+        }
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, gameboy: &mut GameBoy) {
+        let mut open = self.open;
+        egui::Window::new("Memory")
+            .open(&mut open)
+            .default_width(560.0)
+            .show(ctx, |ui| {
+                let bytes = self.bytes(gameboy);
+                self.toolbar(ui, gameboy, &bytes);
+                if !self.message.is_empty() {
+                    ui.label(&self.message);
+                }
+                ui.separator();
+                self.rows(ui, gameboy, &bytes);
+                self.previous = bytes;
+            });
+        self.open = open;
+    }
+
+    fn toolbar(&mut self, ui: &mut egui::Ui, gameboy: &GameBoy, bytes: &[u8]) {
+        ui.horizontal(|ui| {
+            let name = SOURCES.iter().find(|(source, _)| *source == self.source).unwrap().1;
+            let before = (self.source, self.bank);
+            egui::ComboBox::from_id_salt("memory_source")
+                .selected_text(name)
+                .show_ui(ui, |ui| {
+                    for (source, name) in SOURCES {
+                        ui.selectable_value(&mut self.source, source, name);
+                    }
+                });
+            if let Source::Region(region) = self.source {
+                let banks = gameboy.region_banks(region);
+                self.bank = self.bank.min(banks.saturating_sub(1));
+                if banks > 1 {
+                    ui.label("Bank");
+                    ui.add(
+                        egui::DragValue::new(&mut self.bank)
+                            .range(0..=banks - 1)
+                            .hexadecimal(2, false, true),
+                    );
+                }
+            }
+            if (self.source, self.bank) != before {
+                self.selected = None;
+                self.previous.clear();
+            }
+        });
+        ui.horizontal(|ui| {
+            let goto = ui.add(
+                egui::TextEdit::singleline(&mut self.goto_input)
+                    .hint_text("go to address")
+                    .desired_width(100.0),
+            );
+            if goto.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                self.go_to(bytes.len());
+            }
+            let search = ui.add(
+                egui::TextEdit::singleline(&mut self.search_input)
+                    .hint_text("search: DE AD or \"text\"")
+                    .desired_width(180.0),
+            );
+            let entered = search.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if ui.button("Find next").clicked() || entered {
+                self.find_next(bytes);
+            }
+        });
+    }
+
+    fn go_to(&mut self, len: usize) {
+        let text = self.goto_input.trim().trim_start_matches('$').trim_start_matches("0x");
+        self.message = match usize::from_str_radix(text, 16) {
+            Ok(address) if address >= self.base_address() && address - self.base_address() < len => {
+                self.select(address - self.base_address());
+                String::new()
+            }
+            Ok(address) => format!("{address:04X} isn't in this view"),
+            Err(_) => format!("not a hex address: {}", self.goto_input),
+        };
+    }
+
+    fn find_next(&mut self, bytes: &[u8]) {
+        let needle = match parse_search(&self.search_input) {
+            Ok(needle) => needle,
+            Err(e) => {
+                self.message = e;
+                return;
+            }
+        };
+        // start after the selected byte and wrap around
+        let start = self.selected.map_or(0, |offset| offset + 1);
+        let found = (start..bytes.len())
+            .chain(0..start)
+            .find(|&offset| bytes[offset..].starts_with(&needle));
+        self.message = match found {
+            Some(offset) => {
+                self.select(offset);
+                String::new()
+            }
+            None => "not found".to_string(),
+        };
+    }
+
+    fn select(&mut self, offset: usize) {
+        self.selected = Some(offset);
+        self.edit.clear();
+        self.scroll_to = Some(offset / BYTES_PER_ROW);
+    }
+
+    fn rows(&mut self, ui: &mut egui::Ui, gameboy: &mut GameBoy, bytes: &[u8]) {
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace) + ui.spacing().item_spacing.y;
+        let rows = bytes.len().div_ceil(BYTES_PER_ROW);
+        let mut scroll_area = egui::ScrollArea::vertical().auto_shrink(false);
+        if let Some(row) = self.scroll_to.take() {
+            scroll_area = scroll_area.vertical_scroll_offset(row as f32 * row_height);
+        }
+        let highlight = ui.visuals().warn_fg_color;
+        let show_bank = matches!(self.source, Source::Region(_)) && self.bank > 0;
+
+        scroll_area.show_rows(ui, row_height, rows, |ui, row_range| {
+            for row in row_range {
+                let start = row * BYTES_PER_ROW;
+                let end = (start + BYTES_PER_ROW).min(bytes.len());
+                ui.horizontal(|ui| {
+                    ui.spacing_mut().item_spacing.x = 6.0;
+                    let address = self.base_address() + start;
+                    let label = match show_bank {
+                        true => format!("{:02X}:{address:04X}", self.bank),
+                        false => format!("{address:04X}"),
+                    };
+                    ui.monospace(label);
+                    for (offset, &byte) in bytes.iter().enumerate().take(end).skip(start) {
+                        if self.selected == Some(offset) {
+                            self.edit_cell(ui, gameboy, offset, byte);
+                            continue;
+                        }
+                        let mut text = egui::RichText::new(format!("{byte:02X}")).monospace();
+                        if self.previous.get(offset).is_some_and(|&previous| previous != byte) {
+                            text = text.color(highlight);
+                        }
+                        if ui.add(egui::Label::new(text).sense(egui::Sense::click())).clicked() {
+                            self.selected = Some(offset);
+                            self.edit.clear();
+                        }
+                    }
+                    let ascii: String = bytes[start..end]
+                        .iter()
+                        .map(|&byte| if byte.is_ascii_graphic() { byte as char } else { '.' })
+                        .collect();
+                    ui.monospace(ascii);
+                });
+            }
+        });
+    }
+
+    fn edit_cell(&mut self, ui: &mut egui::Ui, gameboy: &mut GameBoy, offset: usize, byte: u8) {
+        if self.edit.is_empty() {
+            self.edit = format!("{byte:02X}");
+        }
+        let response = ui.add(
+            egui::TextEdit::singleline(&mut self.edit)
+                .font(egui::TextStyle::Monospace)
+                .char_limit(2)
+                .desired_width(16.0),
+        );
+        if !response.has_focus() && !response.lost_focus() {
+            response.request_focus();
+        }
+        if response.lost_focus() {
+            if ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                match u8::from_str_radix(self.edit.trim(), 16) {
+                    Ok(value) => self.poke(gameboy, offset, value),
+                    Err(_) => self.message = format!("not a hex byte: {}", self.edit),
+                }
+            }
+            self.selected = None;
+            self.edit.clear();
+        }
+    }
+}
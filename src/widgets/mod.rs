@@ -1 +1,5 @@
+pub mod cpu_inspector;
+pub mod debugger;
 pub mod framebuf;
+pub mod memory_viewer;
+pub mod vram_viewer;
@@ -0,0 +1,233 @@
+// Tiles, tile maps and sprites in player 1's VRAM, drawn with the current palettes.
+use crate::util;
+use dmg::gb::GameBoy;
+use dmg::vram::{TILE_MAP_SIZE, TILE_SHEET_HEIGHT, TILE_SHEET_WIDTH};
+use eframe::egui;
+
+const SCALE: f32 = 2.0;
+const SPRITE_SCALE: f32 = 3.0;
+const VIEWPORT_COLOUR: egui::Color32 = egui::Color32::from_rgb(230, 60, 60);
+const WINDOW_COLOUR: egui::Color32 = egui::Color32::from_rgb(60, 140, 230);
+
+#[derive(PartialEq)]
+enum Tab {
+    Tiles,
+    TileMaps,
+    Oam,
+}
+
+pub struct VramViewer {
+    pub open: bool,
+    tab: Tab,
+    bank: u8,
+    map_base: u16,
+    tiles: Option<egui::TextureHandle>,
+    map: Option<egui::TextureHandle>,
+    // one per OAM entry
+    sprites: Vec<Option<egui::TextureHandle>>,
+}
+
+impl Default for VramViewer {
+    fn default() -> Self {
+        Self {
+            open: false,
+            tab: Tab::Tiles,
+            bank: 0,
+            map_base: 0x9800,
+            tiles: None,
+            map: None,
+            sprites: vec![None; 40],
+        }
+    }
+}
+
+// Uploads an image to `texture`, creating it the first time.
+fn update_texture<'a>(
+    texture: &'a mut Option<egui::TextureHandle>,
+    ctx: &egui::Context,
+    name: &str,
+    size: [usize; 2],
+    pixels: Vec<egui::Color32>,
+) -> &'a egui::TextureHandle {
+    let image = egui::ColorImage { size, pixels };
+    match texture {
+        Some(texture) => {
+            texture.set(image, egui::TextureOptions::NEAREST);
+            texture
+        }
+        None => texture.insert(ctx.load_texture(name, image, egui::TextureOptions::NEAREST)),
+    }
+}
+
+fn rgb555_image(pixels: &[u16]) -> Vec<egui::Color32> {
+    pixels.iter().map(|&pixel| util::rgb555_colour(pixel)).collect()
+}
+
+fn scaled_image(ui: &mut egui::Ui, texture: &egui::TextureHandle, scale: f32) -> egui::Response {
+    ui.add(egui::Image::new(egui::load::SizedTexture::new(
+        texture.id(),
+        texture.size_vec2() * scale,
+    )))
+}
+
+impl VramViewer {
+    pub fn show(&mut self, ctx: &egui::Context, gameboy: &GameBoy) {
+        let mut open = self.open;
+        egui::Window::new("VRAM").open(&mut open).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.tab, Tab::Tiles, "Tiles");
+                ui.selectable_value(&mut self.tab, Tab::TileMaps, "Tile maps");
+                ui.selectable_value(&mut self.tab, Tab::Oam, "OAM");
+            });
+            ui.separator();
+            match self.tab {
+                Tab::Tiles => self.tiles(ui, gameboy),
+                Tab::TileMaps => self.tile_maps(ui, gameboy),
+                Tab::Oam => self.oam(ui, gameboy),
+            }
+        });
+        self.open = open;
+    }
+
+    fn tiles(&mut self, ui: &mut egui::Ui, gameboy: &GameBoy) {
+        if gameboy.cgb.enabled {
+            ui.horizontal(|ui| {
+                ui.label("VRAM bank");
+                ui.selectable_value(&mut self.bank, 0, "0");
+                ui.selectable_value(&mut self.bank, 1, "1");
+            });
+        } else {
+            self.bank = 0;
+        }
+        let pixels = rgb555_image(&gameboy.render_tile_sheet(self.bank));
+        let texture = update_texture(
+            &mut self.tiles,
+            ui.ctx(),
+            "vram_tiles",
+            [TILE_SHEET_WIDTH, TILE_SHEET_HEIGHT],
+            pixels,
+        );
+        let response = scaled_image(ui, texture, SCALE);
+        if let Some(position) = response.hover_pos() {
+            let offset = (position - response.rect.min) / (SCALE * 8.0);
+            let tile = (offset.y as usize).min(23) * 16 + (offset.x as usize).min(15);
+            let address = 0x8000 + tile * 16;
+            // tiles 0-255 with 8000 addressing, then -128 to 127 with 8800 addressing
+            let signed = match tile {
+                0x100.. => format!(", {:02X} from 9000", tile - 0x100),
+                0x80..=0xFF => format!(", {tile:02X} from 8800"),
+                _ => String::new(),
+            };
+            response.on_hover_text(format!("{address:04X}: tile {:02X}{signed}", tile % 0x100));
+        }
+    }
+
+    fn tile_maps(&mut self, ui: &mut egui::Ui, gameboy: &GameBoy) {
+        let lcdc = gameboy.r.lcdc;
+        let bg_map = if lcdc & 0x08 != 0 { 0x9C00 } else { 0x9800 };
+        let window_map = if lcdc & 0x40 != 0 { 0x9C00 } else { 0x9800 };
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.map_base, 0x9800, "9800");
+            ui.selectable_value(&mut self.map_base, 0x9C00, "9C00");
+            ui.label(format!(
+                "Background {bg_map:04X}, window {window_map:04X}, tile data {}",
+                if lcdc & 0x10 != 0 { "8000" } else { "8800" }
+            ));
+        });
+
+        let pixels = rgb555_image(&gameboy.render_tile_map(self.map_base));
+        let texture = update_texture(
+            &mut self.map,
+            ui.ctx(),
+            "vram_map",
+            [TILE_MAP_SIZE, TILE_MAP_SIZE],
+            pixels,
+        );
+        let response = scaled_image(ui, texture, SCALE);
+        let painter = ui.painter_at(response.rect);
+        let stroke = |colour| egui::Stroke::new(1.5, colour);
+        let map_rect = |x: f32, y: f32, width: f32, height: f32| {
+            egui::Rect::from_min_size(
+                response.rect.min + egui::vec2(x, y) * SCALE,
+                egui::vec2(width, height) * SCALE,
+            )
+        };
+
+        let mut legend = vec![];
+        if self.map_base == bg_map {
+            // the viewport wraps around the edges of the map, so draw it a map's width and height
+            // to the left and above as well and let the painter clip it
+            let (scx, scy) = (gameboy.r.scx as f32, gameboy.r.scy as f32);
+            for (dx, dy) in [(0.0, 0.0), (-256.0, 0.0), (0.0, -256.0), (-256.0, -256.0)] {
+                painter.rect_stroke(
+                    map_rect(scx + dx, scy + dy, 160.0, 144.0),
+                    0.0,
+                    stroke(VIEWPORT_COLOUR),
+                    egui::StrokeKind::Middle,
+                );
+            }
+            legend.push((VIEWPORT_COLOUR, format!("viewport at SCX {scx}, SCY {scy}")));
+        }
+        // the part of the window on screen, which always starts at the top left of its map
+        let (wx, wy) = (gameboy.r.wx as i16, gameboy.r.wy as i16);
+        let (width, height) = ((167 - wx).min(160), 144 - wy);
+        if self.map_base == window_map && lcdc & 0x20 != 0 && width > 0 && height > 0 {
+            let left = (7 - wx).max(0) as f32;
+            painter.rect_stroke(
+                map_rect(left, 0.0, width as f32, height as f32),
+                0.0,
+                stroke(WINDOW_COLOUR),
+                egui::StrokeKind::Middle,
+            );
+            legend.push((WINDOW_COLOUR, format!("window at WX {wx}, WY {wy}")));
+        }
+        for (colour, text) in legend {
+            ui.colored_label(colour, text);
+        }
+    }
+
+    fn oam(&mut self, ui: &mut egui::Ui, gameboy: &GameBoy) {
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::Grid::new("oam").striped(true).show(ui, |ui| {
+                for heading in ["", "", "X", "Y", "Tile", "Palette", "Flags"] {
+                    ui.strong(heading);
+                }
+                ui.end_row();
+                for index in 0..40 {
+                    let sprite = gameboy.oam_sprite(index);
+                    let pixels = gameboy
+                        .render_sprite_preview(&sprite)
+                        .iter()
+                        .map(|pixel| pixel.map_or(egui::Color32::TRANSPARENT, util::rgb555_colour))
+                        .collect();
+                    let name = format!("vram_sprite_{index}");
+                    let size = [8, sprite.size_y as usize];
+                    let texture = update_texture(&mut self.sprites[index as usize], ui.ctx(), &name, size, pixels);
+
+                    ui.monospace(format!("{index:2}"));
+                    scaled_image(ui, texture, SPRITE_SCALE);
+                    ui.monospace(format!("{:4}", sprite.x));
+                    ui.monospace(format!("{:4}", sprite.y));
+                    ui.monospace(format!("{:02X}", sprite.tile_num));
+                    let palette = match gameboy.cgb.enabled {
+                        true => format!("{} bank {}", sprite.cgb_pal, sprite.vram_bank),
+                        false => format!("OBP{}", sprite.pal),
+                    };
+                    ui.monospace(palette);
+                    let mut flags = vec![];
+                    if sprite.x_flip != 0 {
+                        flags.push("X flip");
+                    }
+                    if sprite.y_flip != 0 {
+                        flags.push("Y flip");
+                    }
+                    if sprite.priority != 0 {
+                        flags.push("behind BG");
+                    }
+                    ui.label(flags.join(", "));
+                    ui.end_row();
+                }
+            });
+        });
+    }
+}